@@ -0,0 +1,292 @@
+use std::cmp;
+use std::iter::FusedIterator;
+use std::ops::Range;
+use std::slice;
+
+use super::{ArcK, DVec, Measure, Node, NodeRef, Shift, SharedPointerKind};
+use iter::Iter;
+
+/// A difference between two versions of a `DVec`, as reported by
+/// `DVec::diff`. Versions are compared index by index, so an element
+/// inserted near the front shows up as a change at every later index.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DiffItem<T> {
+    /// Both versions have an element at `index`, and they differ.
+    Changed { index: usize, old: T, new: T },
+    /// Only the new version has an element at `index`.
+    Appended { index: usize, value: T },
+    /// Only the old version has an element at `index`.
+    Removed { index: usize, value: T },
+}
+
+/// An iterator over the differences between two versions of a `DVec`,
+/// in order of index, created by `DVec::diff`.
+pub struct Diff<'a, T: 'a, const BITS: usize = 5, P: SharedPointerKind = ArcK, M: Measure<T> + 'a = ()> {
+    old: Walker<'a, T, BITS, P, M>,
+    new: Walker<'a, T, BITS, P, M>,
+    // Both walkers are at `index`, which is below `common_len` until
+    // the elements the two versions have in common are exhausted.
+    index: usize,
+    common_len: usize,
+    // The elements of the longer version past `common_len`, and
+    // whether that version is the old one.
+    rest: Iter<'a, T, BITS, P, M>,
+    removed: bool,
+}
+
+/// The subtrees left at one level of a `Walker`, with their shift.
+type Level<'a, T, const BITS: usize, P, M> = (slice::Iter<'a, NodeRef<T, BITS, P, M>>, Shift);
+
+/// One side of a `Diff`, which walks a vector from the front, stepping
+/// over whole subtrees when it can.
+struct Walker<'a, T: 'a, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> {
+    // For every level of the tree above the current position, the
+    // subtrees still to visit, along with their shift.
+    stack: Vec<Level<'a, T, BITS, P, M>>,
+    // The elements left in the current leaf. This starts out as the
+    // head of the vector; `tail` takes over once the tree is done.
+    leaf: &'a [T],
+    tail: &'a [T],
+}
+
+impl<T: PartialEq, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// Returns an iterator over the indices at which `other` differs
+    /// from `self`. Subtrees that the two vectors share, because one
+    /// was cloned from the other, are skipped without looking at their
+    /// elements, so two versions that differ in `k` places are
+    /// compared in about O(k log n) time.
+    ///
+    /// ```rust
+    /// # use dogged::{DVec, DiffItem};
+    /// let old: DVec<u32> = (0..1_000_000).collect();
+    /// let mut new = old.clone();
+    /// new[500_000] = 7;
+    /// new.pop();
+    /// let changes: Vec<_> = old.diff(&new).collect();
+    /// assert_eq!(changes, vec![
+    ///     DiffItem::Changed { index: 500_000, old: &500_000, new: &7 },
+    ///     DiffItem::Removed { index: 999_999, value: &999_999 },
+    /// ]);
+    /// ```
+    pub fn diff<'a>(&'a self, other: &'a DVec<T, BITS, P, M>) -> Diff<'a, T, BITS, P, M> {
+        let common_len = cmp::min(self.len(), other.len());
+        let removed = self.len() > other.len();
+        let mut rest = if removed { self.iter() } else { other.iter() };
+        if common_len > 0 {
+            rest.nth(common_len - 1);
+        }
+        Diff {
+            old: Walker::new(self),
+            new: Walker::new(other),
+            index: 0,
+            common_len,
+            rest,
+            removed,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// Merges `ours` and `theirs`, two versions derived from `base`.
+    /// An index that changed on only one side takes that side's
+    /// element; for an index that changed on both sides to different
+    /// elements, `resolve(index, base, ours, theirs)` picks the result.
+    /// An element is `None` where that version is too short to have
+    /// one, so an edit on one side and a removal on the other is a
+    /// conflict too, and a resolver returns `None` to leave the index
+    /// out. Indices left out are dropped from the merged vector, and
+    /// the elements after them move up.
+    ///
+    /// The result starts out as a clone of `ours`, and only the indices
+    /// that `diff` reports are looked at, so a merge shares most of its
+    /// tree with `ours` and costs about O(k log n) for `k` changes.
+    ///
+    /// ```rust
+    /// # use dogged::DVec;
+    /// let base: DVec<&str> = vec!["a", "b", "c"].into_iter().collect();
+    /// let (mut ours, mut theirs) = (base.clone(), base.clone());
+    /// ours[0] = "A";
+    /// ours[2] = "x";
+    /// theirs[2] = "y";
+    /// theirs.push("d");
+    ///
+    /// let merged = DVec::merge3(&base, &ours, &theirs, |_, _, ours, theirs| {
+    ///     Some(if ours < theirs { *theirs.unwrap() } else { *ours.unwrap() })
+    /// });
+    /// assert!(merged.iter().eq(&["A", "b", "y", "d"]));
+    /// ```
+    pub fn merge3<F>(base: &DVec<T, BITS, P, M>,
+                     ours: &DVec<T, BITS, P, M>,
+                     theirs: &DVec<T, BITS, P, M>,
+                     mut resolve: F)
+                     -> DVec<T, BITS, P, M>
+        where F: FnMut(usize, Option<&T>, Option<&T>, Option<&T>) -> Option<T>
+    {
+        let mut merged = ours.clone();
+        // Runs of indices of `ours` to leave out, which are only cut
+        // once every index has been looked at.
+        let mut left_out: Vec<Range<usize>> = vec![];
+        let mut ours_changed = base.diff(ours).map(|item| item.index()).peekable();
+        let mut theirs_changed = base.diff(theirs).map(|item| item.index()).peekable();
+        loop {
+            let index = match (ours_changed.peek(), theirs_changed.peek()) {
+                (Some(&o), Some(&t)) => cmp::min(o, t),
+                (Some(&o), None) => o,
+                (None, Some(&t)) => t,
+                (None, None) => break,
+            };
+            let changed_ours = ours_changed.next_if_eq(&index).is_some();
+            let changed_theirs = theirs_changed.next_if_eq(&index).is_some();
+
+            let element = match (changed_ours, changed_theirs) {
+                (false, true) => theirs.get(index).cloned(),
+                (true, true) if ours.get(index) != theirs.get(index) => {
+                    resolve(index, base.get(index), ours.get(index), theirs.get(index))
+                }
+                // Only ours changed, or both changed the same way.
+                _ if index < ours.len() => continue, // already in `merged`
+                _ => None,
+            };
+            match element {
+                Some(element) if index < ours.len() => {
+                    merged.set(index, element);
+                }
+                Some(element) => merged.push(element),
+                None if index < ours.len() => match left_out.last_mut() {
+                    Some(run) if run.end == index => run.end += 1,
+                    _ => left_out.push(index..index + 1),
+                },
+                None => {}
+            }
+        }
+        for run in left_out.iter().rev() {
+            let mut rest = merged.split_off(run.start);
+            merged.append(rest.split_off(run.len()));
+        }
+        merged
+    }
+}
+
+impl<T> DiffItem<T> {
+    /// The index at which the versions differ.
+    pub fn index(&self) -> usize {
+        match *self {
+            DiffItem::Changed { index, .. } |
+            DiffItem::Appended { index, .. } |
+            DiffItem::Removed { index, .. } => index,
+        }
+    }
+}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> Walker<'a, T, BITS, P, M> {
+    fn new(vec: &'a DVec<T, BITS, P, M>) -> Self {
+        let roots = match vec.root {
+            Some(ref root) => slice::from_ref(root),
+            None => &[],
+        };
+        Walker {
+            stack: vec![(roots.iter(), vec.shift)],
+            leaf: &vec.head,
+            tail: &vec.tail,
+        }
+    }
+
+    /// The next subtree in the tree, which starts at the current
+    /// position, and its shift.
+    fn peek(&mut self) -> Option<(&'a NodeRef<T, BITS, P, M>, Shift)> {
+        loop {
+            let &(ref children, shift) = self.stack.last()?;
+            match children.as_slice().first() {
+                Some(child) => return Some((child, shift)),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    /// Steps over the subtree returned by `peek`.
+    fn skip(&mut self) {
+        self.stack.last_mut().unwrap().0.next();
+    }
+
+    /// Steps into the subtree returned by `peek`, or onto the tail if
+    /// the tree is done.
+    fn descend(&mut self) {
+        let (node, shift) = match self.peek() {
+            Some(next) => next,
+            None => {
+                self.leaf = self.tail;
+                self.tail = &[];
+                return;
+            }
+        };
+        self.skip();
+        match **node {
+            Node::Branch { ref children, .. } => self.stack.push((children.iter(), shift.dec(BITS))),
+            Node::Leaf { ref elements, .. } => self.leaf = elements,
+        }
+    }
+}
+
+impl<'a, T: PartialEq, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> Iterator for Diff<'a, T, BITS, P, M> {
+    type Item = DiffItem<&'a T>;
+
+    fn next(&mut self) -> Option<DiffItem<&'a T>> {
+        while self.index < self.common_len {
+            match (self.old.leaf.split_first(), self.new.leaf.split_first()) {
+                (Some((old, old_rest)), Some((new, new_rest))) => {
+                    self.old.leaf = old_rest;
+                    self.new.leaf = new_rest;
+                    self.index += 1;
+                    if old != new {
+                        return Some(DiffItem::Changed { index: self.index - 1, old, new });
+                    }
+                }
+                (Some(_), None) => self.new.descend(),
+                (None, Some(_)) => self.old.descend(),
+                (None, None) => {
+                    // Both sides are at the start of a subtree. Step over
+                    // them if they are the same one; otherwise step into
+                    // the bigger one (or both) to look for one that is.
+                    let (old, new) = match (self.old.peek(), self.new.peek()) {
+                        (Some(old), Some(new)) => (old, new),
+                        _ => {
+                            self.old.descend();
+                            self.new.descend();
+                            continue;
+                        }
+                    };
+                    if P::ptr_eq(old.0, new.0) {
+                        self.index += old.0.len(old.1);
+                        self.old.skip();
+                        self.new.skip();
+                        continue;
+                    }
+                    let (old_len, new_len) = (old.0.len(old.1), new.0.len(new.1));
+                    if old_len >= new_len {
+                        self.old.descend();
+                    }
+                    if new_len >= old_len {
+                        self.new.descend();
+                    }
+                }
+            }
+        }
+
+        let value = self.rest.next()?;
+        self.index += 1;
+        Some(if self.removed {
+            DiffItem::Removed { index: self.index - 1, value }
+        } else {
+            DiffItem::Appended { index: self.index - 1, value }
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let rest = self.rest.len();
+        (rest, Some(rest + self.common_len.saturating_sub(self.index)))
+    }
+}
+
+impl<'a, T: PartialEq, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> FusedIterator for Diff<'a, T, BITS, P, M> {}
@@ -0,0 +1,1110 @@
+//! A persistent hash map, `DHashMap`, stored as a hash array mapped
+//! trie (HAMT).
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::{FromIterator, FusedIterator};
+use std::mem;
+use std::ops;
+use std::slice;
+use std::vec;
+
+use super::{ArcK, SharedPointerKind};
+
+/// Number of hash bits consumed at each level of the trie.
+const BITS_PER_LEVEL: u32 = 5;
+
+/// A **persistent** hash map. Like `DVec`, it is cheap to clone: a clone
+/// shares the whole trie with the original, and updating either copies
+/// only the nodes on the path to the updated key.
+///
+/// ```rust
+/// # use dogged::DHashMap;
+/// let mut map1 = DHashMap::new();
+/// map1.insert("a", 1);
+/// let mut map2 = map1.clone();
+/// map2.insert("b", 2);
+/// assert_eq!(map1.len(), 1);
+/// assert_eq!(map2.get("b"), Some(&2));
+/// ```
+///
+/// The hasher is shared between clones as well, and nodes are shared
+/// through the same kind of pointer as for `DVec` (`Arc` by default).
+pub struct DHashMap<K, V, S = RandomState, P: SharedPointerKind = ArcK> {
+    root: NodeRef<K, V, P>,
+    hasher: P::Pointer<S>,
+}
+
+/// A node of the trie. There is one bit in `bitmap` for each of the
+/// values the hash bits at this level can take; the slots for the bits
+/// that are set are kept in order in `slots`. Apart from the root, a
+/// node never holds a single slot unless that slot is a branch, so
+/// every map has just one shape.
+struct Node<K, V, P: SharedPointerKind> {
+    bitmap: u32,
+    size: usize, // number of entries in this subtree
+    slots: Vec<Slot<K, V, P>>,
+}
+
+type NodeRef<K, V, P> = <P as SharedPointerKind>::Pointer<Node<K, V, P>>;
+
+enum Slot<K, V, P: SharedPointerKind> {
+    Leaf { hash: u64, key: K, value: V },
+    // Two or more keys whose hashes are equal in all 64 bits.
+    Collision { hash: u64, entries: Vec<(K, V)> },
+    Branch(NodeRef<K, V, P>),
+}
+
+/// The bit in a node's bitmap for `hash` at `shift`.
+#[inline]
+fn bit(hash: u64, shift: u32) -> u32 {
+    debug_assert!(shift < 64);
+    1 << ((hash >> shift) & ((1 << BITS_PER_LEVEL) - 1))
+}
+
+impl<K, V> DHashMap<K, V> {
+    pub fn new() -> Self {
+        DHashMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S, P: SharedPointerKind> DHashMap<K, V, S, P> {
+    pub fn with_hasher(hasher: S) -> Self {
+        DHashMap {
+            root: P::new(Node::empty()),
+            hasher: P::new(hasher),
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the entries of the map, in an
+    /// unspecified order.
+    pub fn iter(&self) -> Iter<'_, K, V, P> {
+        Iter {
+            stack: vec![self.root.slots.iter()],
+            collision: [].iter(),
+            len: self.len(),
+        }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, P> {
+        Keys { iter: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, P> {
+        Values { iter: self.iter() }
+    }
+
+    /// Returns true if the two maps share their whole trie, which
+    /// implies that they are equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        P::ptr_eq(&self.root, &other.root)
+    }
+
+    /// Checks the invariants of the trie.
+    #[cfg(test)]
+    pub(crate) fn assert_valid(&self) {
+        if let Err(err) = self.root.validate(0, 0, true) {
+            panic!("validation error {}", err);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher, P: SharedPointerKind> DHashMap<K, V, S, P> {
+    fn hash<Q: ?Sized + Hash>(&self, key: &Q) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    pub fn get<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<&V>
+        where K: Borrow<Q>
+    {
+        self.get_key_value(key).map(|(_, value)| value)
+    }
+
+    pub fn get_key_value<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>
+    {
+        self.root.get(self.hash(key), 0, key)
+    }
+
+    pub fn contains_key<Q: ?Sized + Hash + Eq>(&self, key: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        self.get_key_value(key).is_some()
+    }
+}
+
+impl<K, V, S, P> DHashMap<K, V, S, P>
+    where K: Hash + Eq + Clone, V: Clone, S: BuildHasher, P: SharedPointerKind
+{
+    /// Inserts `value` under `key`, returning the value it replaces,
+    /// if any. Only the nodes on the path to `key` are copied.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hash(&key);
+        self.insert_hashed(hash, key, value)
+    }
+
+    fn insert_hashed(&mut self, hash: u64, key: K, value: V) -> Option<V> {
+        P::make_mut(&mut self.root).insert(hash, 0, key, value)
+    }
+
+    /// Removes `key` from the map, returning its value if it was
+    /// present. Nodes are left shared if `key` is not in the map.
+    pub fn remove<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        let hash = self.hash(key);
+        self.remove_hashed(hash, key)
+    }
+
+    fn remove_hashed<Q: ?Sized + Eq>(&mut self, hash: u64, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        self.root.get(hash, 0, key)?;
+        P::make_mut(&mut self.root).remove(hash, 0, key)
+    }
+
+    pub fn get_mut<Q: ?Sized + Hash + Eq>(&mut self, key: &Q) -> Option<&mut V>
+        where K: Borrow<Q>
+    {
+        let hash = self.hash(key);
+        self.get_mut_hashed(hash, key)
+    }
+
+    fn get_mut_hashed<Q: ?Sized + Eq>(&mut self, hash: u64, key: &Q) -> Option<&mut V>
+        where K: Borrow<Q>
+    {
+        self.root.get(hash, 0, key)?;
+        P::make_mut(&mut self.root).get_mut(hash, key)
+    }
+
+    /// Returns true if the two maps hash keys with the same hasher, and
+    /// so can be combined a subtree at a time.
+    fn same_hasher(&self, other: &Self) -> bool {
+        P::ptr_eq(&self.hasher, &other.hasher)
+    }
+
+    /// Returns the entries of `self` and those of `other` whose keys
+    /// are not in `self`.
+    pub(crate) fn union(&self, other: &Self) -> Self {
+        if !self.same_hasher(other) {
+            let mut union = self.clone();
+            for (key, value) in other {
+                if !union.contains_key(key) {
+                    union.insert(key.clone(), value.clone());
+                }
+            }
+            return union;
+        }
+        DHashMap {
+            root: Node::<K, V, P>::union(&self.root, &other.root, 0),
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    /// Returns the entries of `self` whose keys are also in `other`.
+    pub(crate) fn intersection(&self, other: &Self) -> Self {
+        if !self.same_hasher(other) {
+            let mut intersection = self.clone();
+            for key in self.keys() {
+                if !other.contains_key(key) {
+                    intersection.remove(key);
+                }
+            }
+            return intersection;
+        }
+        DHashMap {
+            root: Node::<K, V, P>::intersection(&self.root, &other.root, 0),
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    /// Returns the entries of `self` whose keys are not in `other`.
+    pub(crate) fn difference(&self, other: &Self) -> Self {
+        if !self.same_hasher(other) {
+            let mut difference = self.clone();
+            for key in self.keys() {
+                if other.contains_key(key) {
+                    difference.remove(key);
+                }
+            }
+            return difference;
+        }
+        DHashMap {
+            root: Node::<K, V, P>::difference(&self.root, &other.root, 0),
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    /// Gets the entry for `key`, for in-place updates.
+    ///
+    /// ```rust
+    /// # use dogged::DHashMap;
+    /// let mut counts = DHashMap::new();
+    /// for word in "a b a".split(' ') {
+    ///     *counts.entry(word).or_insert(0) += 1;
+    /// }
+    /// assert_eq!(counts["a"], 2);
+    /// ```
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S, P> {
+        let hash = self.hash(&key);
+        if self.root.get(hash, 0, &key).is_some() {
+            Entry::Occupied(OccupiedEntry { map: self, hash, key })
+        } else {
+            Entry::Vacant(VacantEntry { map: self, hash, key })
+        }
+    }
+}
+
+impl<K, V, P: SharedPointerKind> Node<K, V, P> {
+    fn empty() -> Self {
+        Node { bitmap: 0, size: 0, slots: Vec::new() }
+    }
+
+    /// Checks that the keys in this node, which is at `shift`, all
+    /// have hashes starting with the `shift` bits of `prefix`.
+    #[cfg(test)]
+    fn validate(&self, shift: u32, prefix: u64, is_root: bool) -> Result<(), String> {
+        if self.bitmap.count_ones() as usize != self.slots.len() {
+            return Err(format!("bitmap {:#x} for {} slots", self.bitmap, self.slots.len()));
+        }
+        if !is_root && self.slots.len() < 2 && !self.slots.iter().any(Slot::is_branch) {
+            return Err(format!("lone leaf below the root at shift {}", shift));
+        }
+
+        let mut bits = self.bitmap;
+        let mut size = 0;
+        for slot in &self.slots {
+            let bit = bits & bits.wrapping_neg();
+            bits &= bits - 1;
+            let prefix = prefix | ((bit.trailing_zeros() as u64) << shift);
+            match *slot {
+                Slot::Leaf { hash, .. } | Slot::Collision { hash, .. } => {
+                    let mask = (1u64 << (shift + BITS_PER_LEVEL).min(63)) - 1;
+                    if hash & mask != prefix & mask {
+                        return Err(format!("hash {:#x} under prefix {:#x}", hash, prefix));
+                    }
+                }
+                Slot::Branch(ref child) => child.validate(shift + BITS_PER_LEVEL, prefix, false)?,
+            }
+            if let Slot::Collision { ref entries, .. } = *slot {
+                if entries.len() < 2 {
+                    return Err("collision with fewer than two entries".to_string());
+                }
+            }
+            size += slot.size();
+        }
+        if size != self.size {
+            return Err(format!("size {} for {} entries", self.size, size));
+        }
+        Ok(())
+    }
+
+    /// The position in `slots` of the slot for `bit`.
+    #[inline]
+    fn index(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+
+    /// Builds the subtree at `shift` holding the two slots `a` and `b`,
+    /// whose hashes differ.
+    fn pair(shift: u32, a: Slot<K, V, P>, a_hash: u64, b: Slot<K, V, P>, b_hash: u64) -> Self {
+        let (a_bit, b_bit) = (bit(a_hash, shift), bit(b_hash, shift));
+        let size = a.size() + b.size();
+        if a_bit == b_bit {
+            let child = Node::pair(shift + BITS_PER_LEVEL, a, a_hash, b, b_hash);
+            Node { bitmap: a_bit, size, slots: vec![Slot::Branch(P::new(child))] }
+        } else if a_bit < b_bit {
+            Node { bitmap: a_bit | b_bit, size, slots: vec![a, b] }
+        } else {
+            Node { bitmap: a_bit | b_bit, size, slots: vec![b, a] }
+        }
+    }
+
+    /// Looks `key` up in this node, which is at `shift`.
+    fn get<Q: ?Sized + Eq>(&self, hash: u64, shift: u32, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>
+    {
+        let mut node = self;
+        let mut shift = shift;
+        loop {
+            let bit = bit(hash, shift);
+            if node.bitmap & bit == 0 {
+                return None;
+            }
+            match node.slots[node.index(bit)] {
+                Slot::Branch(ref child) => {
+                    node = child;
+                    shift += BITS_PER_LEVEL;
+                }
+                ref slot => return slot.find(hash, key),
+            }
+        }
+    }
+}
+
+impl<K: Eq + Clone, V: Clone, P: SharedPointerKind> Node<K, V, P> {
+    fn insert(&mut self, hash: u64, shift: u32, key: K, value: V) -> Option<V> {
+        let old = self.insert_entry(hash, shift, key, value);
+        if old.is_none() {
+            self.size += 1;
+        }
+        old
+    }
+
+    fn insert_entry(&mut self, hash: u64, shift: u32, key: K, value: V) -> Option<V> {
+        let bit = bit(hash, shift);
+        let index = self.index(bit);
+        if self.bitmap & bit == 0 {
+            self.bitmap |= bit;
+            self.slots.insert(index, Slot::Leaf { hash, key, value });
+            return None;
+        }
+
+        match self.slots[index] {
+            Slot::Branch(ref mut child) => {
+                return P::make_mut(child).insert(hash, shift + BITS_PER_LEVEL, key, value);
+            }
+            Slot::Leaf { hash: h, key: ref k, value: ref mut v } if h == hash && *k == key => {
+                return Some(mem::replace(v, value));
+            }
+            Slot::Collision { hash: h, ref mut entries } if h == hash => {
+                match entries.iter_mut().find(|entry| entry.0 == key) {
+                    Some(entry) => return Some(mem::replace(&mut entry.1, value)),
+                    None => {
+                        entries.push((key, value));
+                        return None;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // The slot holds some other key(s), which now have to share it
+        // with `key`.
+        let placeholder = Slot::Collision { hash: 0, entries: Vec::new() };
+        let old = mem::replace(&mut self.slots[index], placeholder);
+        self.slots[index] = match old {
+            Slot::Leaf { hash: h, key: k, value: v } if h == hash => {
+                Slot::Collision { hash, entries: vec![(k, v), (key, value)] }
+            }
+            Slot::Leaf { hash: old_hash, .. } | Slot::Collision { hash: old_hash, .. } => {
+                let new = Slot::Leaf { hash, key, value };
+                let child = Node::pair(shift + BITS_PER_LEVEL, old, old_hash, new, hash);
+                Slot::Branch(P::new(child))
+            }
+            Slot::Branch(..) => unreachable!(),
+        };
+        None
+    }
+
+    fn remove<Q: ?Sized + Eq>(&mut self, hash: u64, shift: u32, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        let value = self.remove_entry(hash, shift, key);
+        if value.is_some() {
+            self.size -= 1;
+        }
+        value
+    }
+
+    fn remove_entry<Q: ?Sized + Eq>(&mut self, hash: u64, shift: u32, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        let bit = bit(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let index = self.index(bit);
+
+        match self.slots[index] {
+            Slot::Leaf { hash: h, key: ref k, .. } => {
+                if h != hash || k.borrow() != key {
+                    return None;
+                }
+            }
+            Slot::Collision { hash: h, ref mut entries } => {
+                if h != hash {
+                    return None;
+                }
+                let position = entries.iter().position(|entry| entry.0.borrow() == key)?;
+                let (_, value) = entries.swap_remove(position);
+                if entries.len() == 1 {
+                    let (key, value) = entries.pop().unwrap();
+                    self.slots[index] = Slot::Leaf { hash, key, value };
+                }
+                return Some(value);
+            }
+            Slot::Branch(ref mut child) => {
+                let child = P::make_mut(child);
+                let value = child.remove(hash, shift + BITS_PER_LEVEL, key);
+
+                // Keep the trie canonical: a lone leaf or collision
+                // moves up into the slot of the node that held it.
+                if child.slots.len() == 1 && !child.slots[0].is_branch() {
+                    self.slots[index] = child.slots.pop().unwrap();
+                }
+                return value;
+            }
+        }
+
+        self.bitmap &= !bit;
+        match self.slots.remove(index) {
+            Slot::Leaf { value, .. } => Some(value),
+            _ => unreachable!(),
+        }
+    }
+
+    fn get_mut<Q: ?Sized + Eq>(&mut self, hash: u64, key: &Q) -> Option<&mut V>
+        where K: Borrow<Q>
+    {
+        let mut p = self;
+        let mut shift = 0;
+        loop {
+            let bit = bit(hash, shift);
+            if p.bitmap & bit == 0 {
+                return None;
+            }
+            let index = p.index(bit);
+            let q = p; // FIXME
+            match q.slots[index] {
+                Slot::Leaf { hash: h, key: ref k, ref mut value } => {
+                    return if h == hash && k.borrow() == key {
+                        Some(value)
+                    } else {
+                        None
+                    };
+                }
+                Slot::Collision { hash: h, ref mut entries } => {
+                    if h != hash {
+                        return None;
+                    }
+                    return entries.iter_mut()
+                                  .find(|entry| entry.0.borrow() == key)
+                                  .map(|entry| &mut entry.1);
+                }
+                Slot::Branch(ref mut child) => {
+                    p = P::make_mut(child);
+                    shift += BITS_PER_LEVEL;
+                }
+            }
+        }
+    }
+}
+
+/// Set algebra on two tries built by the same hasher, so that equal
+/// keys are always found in the same place in both. Subtrees the two
+/// tries share are dealt with without being walked, and whenever the
+/// result of an operation on a subtree of `this` has as many entries as
+/// that subtree, the subtree itself is reused.
+impl<K: Eq + Clone, V: Clone, P: SharedPointerKind> Node<K, V, P> {
+    /// A node holding just `slot` under `bit`, to merge other entries
+    /// into the slot with `insert` and `remove`.
+    fn singleton(bit: u32, slot: Slot<K, V, P>) -> Self {
+        Node { bitmap: bit, size: slot.size(), slots: vec![slot] }
+    }
+
+    /// The slot for the result of an operation on the subtree of a
+    /// branch, keeping the trie canonical.
+    fn branch_slot(node: NodeRef<K, V, P>) -> Option<Slot<K, V, P>> {
+        match node.slots.len() {
+            0 => None,
+            1 if !node.slots[0].is_branch() => Some(node.slots[0].clone()),
+            _ => Some(Slot::Branch(node)),
+        }
+    }
+
+    /// The slot for entries that all have the same `hash`.
+    fn leaf_slot(hash: u64, mut entries: Vec<(K, V)>) -> Option<Slot<K, V, P>> {
+        match entries.len() {
+            0 => None,
+            1 => {
+                let (key, value) = entries.pop().unwrap();
+                Some(Slot::Leaf { hash, key, value })
+            }
+            _ => Some(Slot::Collision { hash, entries }),
+        }
+    }
+
+    /// Builds the node with the given slots, or returns
+    /// `this` if it holds the same entries.
+    fn rebuild(this: &NodeRef<K, V, P>, bitmap: u32, slots: Vec<Slot<K, V, P>>) -> NodeRef<K, V, P> {
+        let size = slots.iter().map(Slot::size).sum();
+        if size == this.size {
+            this.clone()
+        } else {
+            P::new(Node { bitmap, size, slots })
+        }
+    }
+
+    /// The entries of `this` and those of `other` whose keys are not in
+    /// `this`.
+    fn union(this: &NodeRef<K, V, P>, other: &NodeRef<K, V, P>, shift: u32) -> NodeRef<K, V, P> {
+        if P::ptr_eq(this, other) {
+            return this.clone();
+        }
+
+        let bitmap = this.bitmap | other.bitmap;
+        let mut slots = Vec::with_capacity(bitmap.count_ones() as usize);
+        let mut bits = bitmap;
+        while bits != 0 {
+            let bit = bits & bits.wrapping_neg();
+            bits &= bits - 1;
+            let slot = match (this.bitmap & bit != 0, other.bitmap & bit != 0) {
+                (true, false) => this.slots[this.index(bit)].clone(),
+                (false, true) => other.slots[other.index(bit)].clone(),
+                _ => {
+                    let (a, b) = (&this.slots[this.index(bit)], &other.slots[other.index(bit)]);
+                    Self::union_slots(a, b, bit, shift)
+                }
+            };
+            slots.push(slot);
+        }
+        Self::rebuild(this, bitmap, slots)
+    }
+
+    fn union_slots(a: &Slot<K, V, P>, b: &Slot<K, V, P>, bit: u32, shift: u32) -> Slot<K, V, P> {
+        match (a, b) {
+            (Slot::Branch(a), Slot::Branch(b)) => {
+                Slot::Branch(Self::union(a, b, shift + BITS_PER_LEVEL))
+            }
+            (_, Slot::Branch(..)) => {
+                // The entries of `a` replace those of `b`.
+                let (hash, entries) = a.entries();
+                let mut node = Self::singleton(bit, b.clone());
+                for (key, value) in entries {
+                    node.insert(hash, shift, key, value);
+                }
+                node.slots.pop().unwrap()
+            }
+            _ => {
+                let (hash, entries) = b.entries();
+                let mut node = Self::singleton(bit, a.clone());
+                for (key, value) in entries {
+                    if node.get(hash, shift, &key).is_none() {
+                        node.insert(hash, shift, key, value);
+                    }
+                }
+                node.slots.pop().unwrap()
+            }
+        }
+    }
+
+    /// The entries of `this` whose keys are also in `other`.
+    fn intersection(this: &NodeRef<K, V, P>, other: &NodeRef<K, V, P>, shift: u32) -> NodeRef<K, V, P> {
+        if P::ptr_eq(this, other) {
+            return this.clone();
+        }
+
+        let mut bitmap = 0;
+        let mut slots = vec![];
+        let mut bits = this.bitmap & other.bitmap;
+        while bits != 0 {
+            let bit = bits & bits.wrapping_neg();
+            bits &= bits - 1;
+            let (a, b) = (&this.slots[this.index(bit)], &other.slots[other.index(bit)]);
+            if let Some(slot) = Self::intersection_slots(a, b, shift) {
+                bitmap |= bit;
+                slots.push(slot);
+            }
+        }
+        Self::rebuild(this, bitmap, slots)
+    }
+
+    fn intersection_slots(a: &Slot<K, V, P>, b: &Slot<K, V, P>, shift: u32) -> Option<Slot<K, V, P>> {
+        match (a, b) {
+            (Slot::Branch(a), Slot::Branch(b)) => {
+                Self::branch_slot(Self::intersection(a, b, shift + BITS_PER_LEVEL))
+            }
+            (Slot::Branch(a), _) => {
+                let (hash, entries) = b.entries();
+                let entries = entries.into_iter().filter_map(|(key, _)| {
+                    a.get(hash, shift + BITS_PER_LEVEL, &key)
+                     .map(|(key, value)| (key.clone(), value.clone()))
+                }).collect();
+                Self::leaf_slot(hash, entries)
+            }
+            _ => {
+                let (hash, mut entries) = a.entries();
+                entries.retain(|entry| b.get(hash, shift, &entry.0).is_some());
+                Self::leaf_slot(hash, entries)
+            }
+        }
+    }
+
+    /// The entries of `this` whose keys are not in `other`.
+    fn difference(this: &NodeRef<K, V, P>, other: &NodeRef<K, V, P>, shift: u32) -> NodeRef<K, V, P> {
+        if P::ptr_eq(this, other) {
+            return P::new(Node::empty());
+        }
+
+        let mut bitmap = 0;
+        let mut slots = vec![];
+        let mut bits = this.bitmap;
+        while bits != 0 {
+            let bit = bits & bits.wrapping_neg();
+            bits &= bits - 1;
+            let a = &this.slots[this.index(bit)];
+            let slot = if other.bitmap & bit == 0 {
+                Some(a.clone())
+            } else {
+                Self::difference_slots(a, &other.slots[other.index(bit)], bit, shift)
+            };
+            if let Some(slot) = slot {
+                bitmap |= bit;
+                slots.push(slot);
+            }
+        }
+        Self::rebuild(this, bitmap, slots)
+    }
+
+    fn difference_slots(a: &Slot<K, V, P>, b: &Slot<K, V, P>, bit: u32, shift: u32) -> Option<Slot<K, V, P>> {
+        match (a, b) {
+            (Slot::Branch(a), Slot::Branch(b)) => {
+                Self::branch_slot(Self::difference(a, b, shift + BITS_PER_LEVEL))
+            }
+            (Slot::Branch(..), _) => {
+                let (hash, entries) = b.entries();
+                let mut node = Self::singleton(bit, a.clone());
+                for (key, _) in entries {
+                    if node.get(hash, shift, &key).is_some() {
+                        node.remove(hash, shift, &key);
+                    }
+                }
+                node.slots.pop()
+            }
+            _ => {
+                let (hash, mut entries) = a.entries();
+                entries.retain(|entry| b.get(hash, shift, &entry.0).is_none());
+                Self::leaf_slot(hash, entries)
+            }
+        }
+    }
+}
+
+impl<K, V, P: SharedPointerKind> Slot<K, V, P> {
+    fn is_branch(&self) -> bool {
+        matches!(*self, Slot::Branch(..))
+    }
+
+    fn size(&self) -> usize {
+        match *self {
+            Slot::Leaf { .. } => 1,
+            Slot::Collision { ref entries, .. } => entries.len(),
+            Slot::Branch(ref child) => child.size,
+        }
+    }
+
+    /// Looks `key` up in a leaf or collision slot.
+    fn find<Q: ?Sized + Eq>(&self, hash: u64, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>
+    {
+        match *self {
+            Slot::Leaf { hash: h, key: ref k, ref value } => {
+                if h == hash && k.borrow() == key {
+                    Some((k, value))
+                } else {
+                    None
+                }
+            }
+            Slot::Collision { hash: h, ref entries } => {
+                if h != hash {
+                    return None;
+                }
+                entries.iter()
+                       .find(|entry| entry.0.borrow() == key)
+                       .map(|entry| (&entry.0, &entry.1))
+            }
+            Slot::Branch(..) => unreachable!(),
+        }
+    }
+
+    /// Looks `key` up in a slot of a node at `shift`.
+    fn get<Q: ?Sized + Eq>(&self, hash: u64, shift: u32, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>
+    {
+        match *self {
+            Slot::Branch(ref child) => child.get(hash, shift + BITS_PER_LEVEL, key),
+            _ => self.find(hash, key),
+        }
+    }
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> Slot<K, V, P> {
+    /// The hash and a copy of the entries of a leaf or collision slot.
+    fn entries(&self) -> (u64, Vec<(K, V)>) {
+        match *self {
+            Slot::Leaf { hash, ref key, ref value } => (hash, vec![(key.clone(), value.clone())]),
+            Slot::Collision { hash, ref entries } => (hash, entries.clone()),
+            Slot::Branch(..) => unreachable!(),
+        }
+    }
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> Clone for Node<K, V, P> {
+    fn clone(&self) -> Self {
+        Node { bitmap: self.bitmap, size: self.size, slots: self.slots.clone() }
+    }
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> Clone for Slot<K, V, P> {
+    fn clone(&self) -> Self {
+        match *self {
+            Slot::Leaf { hash, ref key, ref value } => {
+                Slot::Leaf { hash, key: key.clone(), value: value.clone() }
+            }
+            Slot::Collision { hash, ref entries } => {
+                Slot::Collision { hash, entries: entries.clone() }
+            }
+            Slot::Branch(ref child) => Slot::Branch(child.clone()),
+        }
+    }
+}
+
+impl<K, V, S, P: SharedPointerKind> Clone for DHashMap<K, V, S, P> {
+    fn clone(&self) -> Self {
+        DHashMap {
+            root: self.root.clone(),
+            hasher: self.hasher.clone(),
+        }
+    }
+}
+
+impl<K, V, S: Default, P: SharedPointerKind> Default for DHashMap<K, V, S, P> {
+    fn default() -> Self {
+        DHashMap::with_hasher(S::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S, P: SharedPointerKind> fmt::Debug for DHashMap<K, V, S, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V, S, P> PartialEq for DHashMap<K, V, S, P>
+    where K: Hash + Eq, V: PartialEq, S: BuildHasher, P: SharedPointerKind
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() &&
+            self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K, V, S, P> Eq for DHashMap<K, V, S, P>
+    where K: Hash + Eq, V: Eq, S: BuildHasher, P: SharedPointerKind
+{}
+
+impl<K, Q, V, S, P> ops::Index<&Q> for DHashMap<K, V, S, P>
+    where K: Hash + Eq + Borrow<Q>, Q: ?Sized + Hash + Eq, S: BuildHasher, P: SharedPointerKind
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not found in DHashMap")
+    }
+}
+
+impl<K, V, S, P> FromIterator<(K, V)> for DHashMap<K, V, S, P>
+    where K: Hash + Eq + Clone, V: Clone, S: BuildHasher + Default, P: SharedPointerKind
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = DHashMap::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S, P> Extend<(K, V)> for DHashMap<K, V, S, P>
+    where K: Hash + Eq + Clone, V: Clone, S: BuildHasher, P: SharedPointerKind
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// A view into a single entry of a `DHashMap`, created by
+/// `DHashMap::entry`.
+pub enum Entry<'a, K: 'a, V: 'a, S: 'a, P: SharedPointerKind = ArcK> {
+    Occupied(OccupiedEntry<'a, K, V, S, P>),
+    Vacant(VacantEntry<'a, K, V, S, P>),
+}
+
+pub struct OccupiedEntry<'a, K: 'a, V: 'a, S: 'a, P: SharedPointerKind = ArcK> {
+    map: &'a mut DHashMap<K, V, S, P>,
+    hash: u64,
+    key: K,
+}
+
+pub struct VacantEntry<'a, K: 'a, V: 'a, S: 'a, P: SharedPointerKind = ArcK> {
+    map: &'a mut DHashMap<K, V, S, P>,
+    hash: u64,
+    key: K,
+}
+
+impl<'a, K, V, S, P> Entry<'a, K, V, S, P>
+    where K: Hash + Eq + Clone, V: Clone, S: BuildHasher, P: SharedPointerKind
+{
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+        where V: Default
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+}
+
+impl<'a, K, V, S, P> OccupiedEntry<'a, K, V, S, P>
+    where K: Hash + Eq + Clone, V: Clone, S: BuildHasher, P: SharedPointerKind
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn get(&self) -> &V {
+        self.map.root.get(self.hash, 0, &self.key).unwrap().1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.map.get_mut_hashed(self.hash, &self.key).unwrap()
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.map.get_mut_hashed(self.hash, &self.key).unwrap()
+    }
+
+    pub fn insert(&mut self, value: V) -> V {
+        mem::replace(self.get_mut(), value)
+    }
+
+    pub fn remove(self) -> V {
+        self.map.remove_hashed(self.hash, &self.key).unwrap()
+    }
+}
+
+impl<'a, K, V, S, P> VacantEntry<'a, K, V, S, P>
+    where K: Hash + Eq + Clone, V: Clone, S: BuildHasher, P: SharedPointerKind
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.map.insert_hashed(self.hash, self.key.clone(), value);
+        self.map.get_mut_hashed(self.hash, &self.key).unwrap()
+    }
+}
+
+/// An iterator over the entries of a `DHashMap`, created by
+/// `DHashMap::iter`.
+pub struct Iter<'a, K: 'a, V: 'a, P: SharedPointerKind = ArcK> {
+    // The slots left to visit in each node on the path to the current
+    // one, and the entries left in the current collision, if any.
+    stack: Vec<slice::Iter<'a, Slot<K, V, P>>>,
+    collision: slice::Iter<'a, (K, V)>,
+    len: usize,
+}
+
+impl<'a, K, V, P: SharedPointerKind> Iterator for Iter<'a, K, V, P> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            if let Some(entry) = self.collision.next() {
+                self.len -= 1;
+                return Some((&entry.0, &entry.1));
+            }
+            let slot = match self.stack.last_mut()?.next() {
+                Some(slot) => slot,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            match *slot {
+                Slot::Leaf { ref key, ref value, .. } => {
+                    self.len -= 1;
+                    return Some((key, value));
+                }
+                Slot::Collision { ref entries, .. } => self.collision = entries.iter(),
+                Slot::Branch(ref child) => self.stack.push(child.slots.iter()),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> ExactSizeIterator for Iter<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> FusedIterator for Iter<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> Clone for Iter<'a, K, V, P> {
+    fn clone(&self) -> Self {
+        Iter {
+            stack: self.stack.clone(),
+            collision: self.collision.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<'a, K, V, S, P: SharedPointerKind> IntoIterator for &'a DHashMap<K, V, S, P> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, P>;
+
+    fn into_iter(self) -> Iter<'a, K, V, P> {
+        self.iter()
+    }
+}
+
+/// An iterator over the keys of a `DHashMap`.
+pub struct Keys<'a, K: 'a, V: 'a, P: SharedPointerKind = ArcK> {
+    iter: Iter<'a, K, V, P>,
+}
+
+impl<'a, K, V, P: SharedPointerKind> Iterator for Keys<'a, K, V, P> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> ExactSizeIterator for Keys<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> FusedIterator for Keys<'a, K, V, P> {}
+
+/// An iterator over the values of a `DHashMap`.
+pub struct Values<'a, K: 'a, V: 'a, P: SharedPointerKind = ArcK> {
+    iter: Iter<'a, K, V, P>,
+}
+
+impl<'a, K, V, P: SharedPointerKind> Iterator for Values<'a, K, V, P> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.iter.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> ExactSizeIterator for Values<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> FusedIterator for Values<'a, K, V, P> {}
+
+/// An owning iterator over the entries of a `DHashMap`. Nodes that no
+/// other map refers to are taken apart; shared ones are cloned.
+pub struct IntoIter<K, V, P: SharedPointerKind = ArcK> {
+    stack: Vec<vec::IntoIter<Slot<K, V, P>>>,
+    collision: vec::IntoIter<(K, V)>,
+    len: usize,
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> Iterator for IntoIter<K, V, P> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if let Some(entry) = self.collision.next() {
+                self.len -= 1;
+                return Some(entry);
+            }
+            let slot = match self.stack.last_mut()?.next() {
+                Some(slot) => slot,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            match slot {
+                Slot::Leaf { key, value, .. } => {
+                    self.len -= 1;
+                    return Some((key, value));
+                }
+                Slot::Collision { entries, .. } => self.collision = entries.into_iter(),
+                Slot::Branch(child) => {
+                    self.stack.push(P::unwrap_or_clone(child).slots.into_iter());
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> ExactSizeIterator for IntoIter<K, V, P> {}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> FusedIterator for IntoIter<K, V, P> {}
+
+impl<K: Clone, V: Clone, S, P: SharedPointerKind> IntoIterator for DHashMap<K, V, S, P> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, P>;
+
+    fn into_iter(self) -> IntoIter<K, V, P> {
+        IntoIter {
+            len: self.len(),
+            stack: vec![P::unwrap_or_clone(self.root).slots.into_iter()],
+            collision: Vec::new().into_iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
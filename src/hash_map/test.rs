@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::DHashMap;
+use {ArcK, RcK};
+
+/// Hashes an integer key to `key % MODULUS`, so that keys collide
+/// in all 64 bits of their hash.
+#[derive(Default)]
+struct ModHasher(u64);
+
+const MODULUS: u64 = 7;
+
+impl Hasher for ModHasher {
+    fn finish(&self) -> u64 {
+        self.0 % MODULUS
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 << 8) | byte as u64;
+        }
+    }
+}
+
+/// Uses the key itself as its hash, so that tests can control the shape
+/// of the trie.
+#[derive(Default)]
+struct IdentityHasher(u64);
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("IdentityHasher only hashes u64 keys")
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0 = value;
+    }
+}
+
+type ModMap<V> = DHashMap<u64, V, BuildHasherDefault<ModHasher>>;
+type IdentityMap<V> = DHashMap<u64, V, BuildHasherDefault<IdentityHasher>>;
+
+#[test]
+fn insert_get_remove() {
+    let mut map = DHashMap::new();
+    assert!(map.is_empty());
+    assert_eq!(map.insert("a", 1), None);
+    assert_eq!(map.insert("b", 2), None);
+    assert_eq!(map.insert("a", 3), Some(1));
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get("a"), Some(&3));
+    assert_eq!(map["b"], 2);
+    assert_eq!(map.get("c"), None);
+
+    assert_eq!(map.remove("a"), Some(3));
+    assert_eq!(map.remove("a"), None);
+    assert_eq!(map.len(), 1);
+    assert!(!map.contains_key("a"));
+    assert!(map.contains_key("b"));
+}
+
+#[test]
+fn clone_is_unaffected_by_updates() {
+    const N: u64 = 2000;
+    let mut map: DHashMap<u64, u64> = (0..N).map(|i| (i, i)).collect();
+    let snapshot = map.clone();
+    assert!(map.ptr_eq(&snapshot));
+
+    for i in 0..N {
+        if i % 2 == 0 {
+            map.remove(&i);
+        } else {
+            *map.get_mut(&i).unwrap() += 1;
+        }
+    }
+    map.insert(N, N);
+
+    assert_eq!(map.len(), N as usize / 2 + 1);
+    assert_eq!(snapshot.len(), N as usize);
+    for i in 0..N {
+        assert_eq!(snapshot[&i], i);
+        assert_eq!(map.get(&i).cloned(), if i % 2 == 0 { None } else { Some(i + 1) });
+    }
+    assert!(!snapshot.contains_key(&N));
+}
+
+#[test]
+fn missing_keys_leave_nodes_shared() {
+    let mut map: DHashMap<u64, u64> = (0..100).map(|i| (i, i)).collect();
+    let snapshot = map.clone();
+    assert_eq!(map.remove(&100), None);
+    assert_eq!(map.get_mut(&100), None);
+    assert!(map.ptr_eq(&snapshot));
+}
+
+#[test]
+fn full_hash_collisions() {
+    const N: u64 = 100;
+    let mut map = ModMap::default();
+    for i in 0..N {
+        assert_eq!(map.insert(i, i), None);
+    }
+    for i in 0..N {
+        assert_eq!(map.insert(i, i * 2), Some(i));
+    }
+    assert_eq!(map.len(), N as usize);
+    assert_eq!(map.iter().count(), N as usize);
+
+    let snapshot = map.clone();
+    for i in 0..N {
+        assert_eq!(map.remove(&i), Some(i * 2));
+        for j in i + 1..N {
+            assert_eq!(map[&j], j * 2);
+        }
+    }
+    assert!(map.is_empty());
+    map.assert_valid();
+    snapshot.assert_valid();
+    assert_eq!(snapshot.len(), N as usize);
+    assert_eq!(snapshot[&(N - 1)], (N - 1) * 2);
+}
+
+#[test]
+fn removal_keeps_the_trie_canonical() {
+    // Keys differing only in their top bits end up at the bottom of the
+    // trie; after removing all but one, the map must have the same
+    // shape, and so the same equality, as a map built directly.
+    let keys: Vec<u64> = (0..16).map(|i| i << 60).collect();
+    let mut map = IdentityMap::default();
+    for &key in &keys {
+        map.insert(key, ());
+    }
+    for &key in &keys[1..] {
+        map.remove(&key);
+    }
+    map.assert_valid();
+    assert_eq!(map.root.slots.len(), 1);
+    assert!(!map.root.slots[0].is_branch());
+
+    let mut other = IdentityMap::default();
+    other.insert(keys[0], ());
+    assert_eq!(map, other);
+}
+
+#[test]
+fn entry() {
+    let mut map = DHashMap::new();
+    for word in "a b a c a b".split(' ') {
+        *map.entry(word).or_insert(0) += 1;
+    }
+    assert_eq!(map["a"], 3);
+    assert_eq!(map["b"], 2);
+    assert_eq!(map["c"], 1);
+
+    map.entry("a").and_modify(|count| *count = 10).or_default();
+    map.entry("d").and_modify(|count| *count = 10).or_default();
+    assert_eq!(map["a"], 10);
+    assert_eq!(map["d"], 0);
+
+    let snapshot = map.clone();
+    match map.entry("b") {
+        ::hash_map::Entry::Occupied(entry) => assert_eq!(entry.remove(), 2),
+        ::hash_map::Entry::Vacant(_) => panic!("`b` is in the map"),
+    }
+    match map.entry("e") {
+        ::hash_map::Entry::Occupied(_) => panic!("`e` is not in the map"),
+        ::hash_map::Entry::Vacant(entry) => *entry.insert(5) += 1,
+    }
+    assert_eq!(map.get("b"), None);
+    assert_eq!(map["e"], 6);
+    assert_eq!(snapshot["b"], 2);
+    assert_eq!(snapshot.get("e"), None);
+}
+
+#[test]
+fn iterators() {
+    const N: u64 = 1000;
+    let map: DHashMap<u64, u64> = (0..N).map(|i| (i, i * 3)).collect();
+    let snapshot = map.clone();
+
+    let mut entries: Vec<_> = map.iter().map(|(&k, &v)| (k, v)).collect();
+    entries.sort();
+    assert_eq!(entries, (0..N).map(|i| (i, i * 3)).collect::<Vec<_>>());
+    assert_eq!(map.iter().len(), N as usize);
+    assert_eq!(map.keys().sum::<u64>(), (0..N).sum::<u64>());
+    assert_eq!(map.values().sum::<u64>(), (0..N).map(|i| i * 3).sum::<u64>());
+
+    let mut owned: Vec<_> = map.into_iter().collect();
+    owned.sort();
+    assert_eq!(owned, entries);
+    assert_eq!(snapshot.len(), N as usize);
+}
+
+fn matches_std<S: BuildHasher + Default, P: ::SharedPointerKind>() {
+    let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+    let mut map = DHashMap::<u32, u32, S, P>::default();
+    let mut model = HashMap::new();
+    let mut snapshots = vec![];
+
+    for i in 0..20000 {
+        let key = rng.next_u32() % 500;
+        match rng.next_u32() % 4 {
+            0 => assert_eq!(map.remove(&key), model.remove(&key)),
+            1 => assert_eq!(map.get(&key), model.get(&key)),
+            _ => assert_eq!(map.insert(key, i), model.insert(key, i)),
+        }
+        assert_eq!(map.len(), model.len());
+        if i % 1000 == 0 {
+            snapshots.push((map.clone(), model.clone()));
+        }
+    }
+
+    for (map, model) in snapshots {
+        map.assert_valid();
+        assert_eq!(map.len(), model.len());
+        assert_eq!(map.iter().count(), model.len());
+        for (key, value) in &model {
+            assert_eq!(map.get(key), Some(value));
+        }
+    }
+}
+
+test_instances!(matches_std {
+    arc => [RandomState, ArcK],
+    rc => [RandomState, RcK],
+    colliding => [BuildHasherDefault<ModHasher>, ArcK],
+});
+
+#[test]
+fn equality() {
+    let a: DHashMap<u32, u32> = (0..100).map(|i| (i, i)).collect();
+    let mut b: DHashMap<u32, u32> = (0..100).rev().map(|i| (i, i)).collect();
+    assert_eq!(a, b);
+    b.insert(5, 6);
+    assert!(a != b);
+    b.remove(&5);
+    assert!(a != b);
+    b.insert(5, 5);
+    assert_eq!(a, b);
+
+    // A value need not equal itself, so neither need a clone.
+    let mut floats: DHashMap<u32, f64> = (0..100).map(|i| (i, i as f64)).collect();
+    floats.insert(5, f64::NAN);
+    assert!(floats != floats.clone());
+}
+
+macro_rules! insert {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use std::collections::HashMap;
+            use DHashMap;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut map = DHashMap::new();
+                    for i in 0 .. N {
+                        map.insert(i, i);
+                    }
+                    map
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut map = HashMap::new();
+                    for i in 0 .. N {
+                        map.insert(i, i);
+                    }
+                    map
+                });
+            }
+        }
+    }
+}
+
+insert!(insert_5000, 5000);
+insert!(insert_50000, 50000);
+
+macro_rules! get {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use std::collections::HashMap;
+            use DHashMap;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                let map: DHashMap<_, _> = (0 .. N).map(|i| (i, i)).collect();
+                b.iter(|| {
+                    for i in 0 .. N {
+                        assert_eq!(map[&i], i);
+                    }
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                let map: HashMap<_, _> = (0 .. N).map(|i| (i, i)).collect();
+                b.iter(|| {
+                    for i in 0 .. N {
+                        assert_eq!(map[&i], i);
+                    }
+                });
+            }
+        }
+    }
+}
+
+get!(get_5000, 5000);
+get!(get_50000, 50000);
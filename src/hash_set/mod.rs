@@ -0,0 +1,227 @@
+//! A persistent hash set, `DHashSet`, stored as a `DHashMap` to `()`.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::{FromIterator, FusedIterator};
+
+use super::{ArcK, SharedPointerKind};
+use hash_map::{self, DHashMap};
+
+/// A **persistent** hash set, cheap to clone like `DHashMap`.
+///
+/// `union`, `intersection` and `difference` work a subtree at a time
+/// when both sets use the same hasher, that is, when they descend from
+/// the same set by cloning. Subtrees that the two sets still share are
+/// then taken or dropped whole, without being walked, so combining two
+/// versions of a large set costs time in proportion to how much they
+/// differ. Sets with different hashers are combined an element at a
+/// time.
+///
+/// ```rust
+/// # use dogged::DHashSet;
+/// let base: DHashSet<u32> = (0..1000).collect();
+/// let mut a = base.clone();
+/// a.insert(1000);
+/// let mut b = base.clone();
+/// b.remove(&0);
+///
+/// assert_eq!(a.union(&b).len(), 1001);
+/// assert_eq!(a.intersection(&b).len(), 999);
+/// assert_eq!(a.difference(&b).len(), 2);
+/// ```
+pub struct DHashSet<T, S = RandomState, P: SharedPointerKind = ArcK> {
+    map: DHashMap<T, (), S, P>,
+}
+
+impl<T> DHashSet<T> {
+    pub fn new() -> Self {
+        DHashSet { map: DHashMap::new() }
+    }
+}
+
+impl<T, S, P: SharedPointerKind> DHashSet<T, S, P> {
+    pub fn with_hasher(hasher: S) -> Self {
+        DHashSet { map: DHashMap::with_hasher(hasher) }
+    }
+
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns an iterator over the elements of the set, in an
+    /// unspecified order.
+    pub fn iter(&self) -> Iter<'_, T, P> {
+        Iter { iter: self.map.keys() }
+    }
+
+    /// Returns true if the two sets share their whole trie, which
+    /// implies that they are equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.map.ptr_eq(&other.map)
+    }
+}
+
+impl<T: Hash + Eq, S: BuildHasher, P: SharedPointerKind> DHashSet<T, S, P> {
+    pub fn contains<Q: ?Sized + Hash + Eq>(&self, value: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.map.contains_key(value)
+    }
+
+    pub fn get<Q: ?Sized + Hash + Eq>(&self, value: &Q) -> Option<&T>
+        where T: Borrow<Q>
+    {
+        self.map.get_key_value(value).map(|(value, _)| value)
+    }
+}
+
+impl<T, S, P> DHashSet<T, S, P>
+    where T: Hash + Eq + Clone, S: BuildHasher, P: SharedPointerKind
+{
+    /// Adds `value` to the set, returning true if it was not there.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+
+    /// Removes `value` from the set, returning true if it was there.
+    pub fn remove<Q: ?Sized + Hash + Eq>(&mut self, value: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.map.remove(value).is_some()
+    }
+
+    /// Returns the elements that are in `self`, in `other`, or in both.
+    pub fn union(&self, other: &Self) -> Self {
+        DHashSet { map: self.map.union(&other.map) }
+    }
+
+    /// Returns the elements that are in both `self` and `other`.
+    pub fn intersection(&self, other: &Self) -> Self {
+        DHashSet { map: self.map.intersection(&other.map) }
+    }
+
+    /// Returns the elements that are in `self` but not in `other`.
+    pub fn difference(&self, other: &Self) -> Self {
+        DHashSet { map: self.map.difference(&other.map) }
+    }
+}
+
+impl<T, S, P: SharedPointerKind> Clone for DHashSet<T, S, P> {
+    fn clone(&self) -> Self {
+        DHashSet { map: self.map.clone() }
+    }
+}
+
+impl<T, S: Default, P: SharedPointerKind> Default for DHashSet<T, S, P> {
+    fn default() -> Self {
+        DHashSet { map: DHashMap::default() }
+    }
+}
+
+impl<T: fmt::Debug, S, P: SharedPointerKind> fmt::Debug for DHashSet<T, S, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T, S, P> PartialEq for DHashSet<T, S, P>
+    where T: Hash + Eq, S: BuildHasher, P: SharedPointerKind
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<T, S, P> Eq for DHashSet<T, S, P>
+    where T: Hash + Eq, S: BuildHasher, P: SharedPointerKind
+{}
+
+impl<T, S, P> FromIterator<T> for DHashSet<T, S, P>
+    where T: Hash + Eq + Clone, S: BuildHasher + Default, P: SharedPointerKind
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        DHashSet { map: iter.into_iter().map(|value| (value, ())).collect() }
+    }
+}
+
+impl<T, S, P> Extend<T> for DHashSet<T, S, P>
+    where T: Hash + Eq + Clone, S: BuildHasher, P: SharedPointerKind
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|value| (value, ())));
+    }
+}
+
+/// An iterator over the elements of a `DHashSet`, created by
+/// `DHashSet::iter`.
+pub struct Iter<'a, T: 'a, P: SharedPointerKind = ArcK> {
+    iter: hash_map::Keys<'a, T, (), P>,
+}
+
+impl<'a, T, P: SharedPointerKind> Iterator for Iter<'a, T, P> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.iter.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T, P: SharedPointerKind> ExactSizeIterator for Iter<'a, T, P> {}
+
+impl<'a, T, P: SharedPointerKind> FusedIterator for Iter<'a, T, P> {}
+
+impl<'a, T, S, P: SharedPointerKind> IntoIterator for &'a DHashSet<T, S, P> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, P>;
+
+    fn into_iter(self) -> Iter<'a, T, P> {
+        self.iter()
+    }
+}
+
+/// An owning iterator over the elements of a `DHashSet`.
+pub struct IntoIter<T, P: SharedPointerKind = ArcK> {
+    iter: hash_map::IntoIter<T, (), P>,
+}
+
+impl<T: Clone, P: SharedPointerKind> Iterator for IntoIter<T, P> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.iter.next().map(|(value, ())| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<T: Clone, P: SharedPointerKind> ExactSizeIterator for IntoIter<T, P> {}
+
+impl<T: Clone, P: SharedPointerKind> FusedIterator for IntoIter<T, P> {}
+
+impl<T: Clone, S, P: SharedPointerKind> IntoIterator for DHashSet<T, S, P> {
+    type Item = T;
+    type IntoIter = IntoIter<T, P>;
+
+    fn into_iter(self) -> IntoIter<T, P> {
+        IntoIter { iter: self.map.into_iter() }
+    }
+}
+
+#[cfg(test)]
+mod test;
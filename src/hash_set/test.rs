@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, BuildHasherDefault, Hasher};
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::DHashSet;
+use {ArcK, RcK, SharedPointerKind};
+
+/// Hashes an integer to itself modulo 64, so that many elements collide
+/// in all 64 bits of their hash.
+#[derive(Default)]
+struct ModHasher(u64);
+
+impl Hasher for ModHasher {
+    fn finish(&self) -> u64 {
+        self.0 % 64
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 << 8) | byte as u64;
+        }
+    }
+}
+
+fn check<T, S, P>(set: &DHashSet<T, S, P>, model: &HashSet<T>)
+    where T: ::std::hash::Hash + Eq + Clone + ::std::fmt::Debug, S: BuildHasher, P: SharedPointerKind
+{
+    set.map.assert_valid();
+    assert_eq!(set.len(), model.len());
+    assert_eq!(set.iter().count(), model.len());
+    for value in model {
+        assert!(set.contains(value), "{:?} is missing", value);
+    }
+}
+
+/// Makes two sets that share most of their structure, by making
+/// `edits` random changes to each of two clones of a common base.
+fn siblings<S, P>(base: &DHashSet<u32, S, P>, rng: &mut XorShiftRng, edits: usize)
+                  -> Vec<DHashSet<u32, S, P>>
+    where S: BuildHasher, P: SharedPointerKind
+{
+    (0..2).map(|_| {
+        let mut set = base.clone();
+        for _ in 0..edits {
+            let value = rng.next_u32() % 3000;
+            if rng.gen() {
+                set.insert(value);
+            } else {
+                set.remove(&value);
+            }
+        }
+        set
+    }).collect()
+}
+
+fn matches_std<S: BuildHasher + Default, P: SharedPointerKind>() {
+    let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+    for &edits in &[0, 1, 10, 100, 1000] {
+        let base: DHashSet<u32, S, P> = (0..2000).collect();
+        let sets = siblings(&base, &mut rng, edits);
+        let (a, b) = (&sets[0], &sets[1]);
+        let model_a: HashSet<u32> = a.iter().cloned().collect();
+        let model_b: HashSet<u32> = b.iter().cloned().collect();
+
+        check(&a.union(b), &model_a.union(&model_b).cloned().collect());
+        check(&a.intersection(b), &model_a.intersection(&model_b).cloned().collect());
+        check(&a.difference(b), &model_a.difference(&model_b).cloned().collect());
+        check(&b.difference(a), &model_b.difference(&model_a).cloned().collect());
+
+        // Combining unrelated sets falls back to elements.
+        let c: DHashSet<u32, S, P> = b.iter().cloned().collect();
+        check(&a.union(&c), &model_a.union(&model_b).cloned().collect());
+        check(&a.intersection(&c), &model_a.intersection(&model_b).cloned().collect());
+        check(&a.difference(&c), &model_a.difference(&model_b).cloned().collect());
+    }
+}
+
+test_instances!(matches_std {
+    arc => [RandomState, ArcK],
+    rc => [RandomState, RcK],
+    colliding => [BuildHasherDefault<ModHasher>, ArcK],
+});
+
+#[test]
+fn results_are_usable() {
+    let mut rng = XorShiftRng::from_seed([4, 5, 6, 7]);
+    let base: DHashSet<u32, BuildHasherDefault<ModHasher>> = (0..2000).collect();
+    let sets = siblings(&base, &mut rng, 200);
+    for mut set in [sets[0].union(&sets[1]),
+                        sets[0].intersection(&sets[1]),
+                        sets[0].difference(&sets[1])] {
+        let mut model: HashSet<u32> = set.iter().cloned().collect();
+        for value in 0..3000 {
+            if value % 3 == 0 {
+                assert_eq!(set.insert(value), model.insert(value));
+            } else {
+                assert_eq!(set.remove(&value), model.remove(&value));
+            }
+        }
+        check(&set, &model);
+    }
+}
+
+#[test]
+fn shared_subtrees_are_reused() {
+    let a: DHashSet<u32> = (0..5000).collect();
+    let mut b = a.clone();
+    b.remove(&0);
+
+    assert!(a.union(&a).ptr_eq(&a));
+    assert!(a.intersection(&a).ptr_eq(&a));
+    assert!(a.difference(&a).is_empty());
+
+    assert!(a.union(&b).ptr_eq(&a));
+    assert!(b.intersection(&a).ptr_eq(&b));
+    assert!(b.difference(&DHashSet::with_hasher(a.hasher().clone())).ptr_eq(&b));
+    assert_eq!(a.difference(&b).iter().collect::<Vec<_>>(), vec![&0]);
+}
+
+#[test]
+fn basics() {
+    let mut set = DHashSet::new();
+    assert!(set.insert("a"));
+    assert!(!set.insert("a"));
+    assert!(set.insert("b"));
+    let snapshot = set.clone();
+    assert!(set.remove("a"));
+    assert!(!set.remove("a"));
+    assert!(!set.contains("a"));
+    assert!(snapshot.contains("a"));
+    assert_eq!(set.get("b"), Some(&"b"));
+    assert_eq!(set.len(), 1);
+    assert_eq!(snapshot.len(), 2);
+
+    let mut values: Vec<_> = snapshot.into_iter().collect();
+    values.sort();
+    assert_eq!(values, vec!["a", "b"]);
+}
+
+macro_rules! union {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use std::collections::HashSet;
+            use DHashSet;
+            use test_crate;
+            const N: u32 = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                let base: DHashSet<u32> = (0 .. N).collect();
+                let mut x = base.clone();
+                x.insert(N);
+                let mut y = base.clone();
+                y.remove(&0);
+                b.iter(|| x.union(&y));
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                let x: HashSet<u32> = (0 .. N + 1).collect();
+                let y: HashSet<u32> = (1 .. N).collect();
+                b.iter(|| x.union(&y).cloned().collect::<HashSet<_>>());
+            }
+        }
+    }
+}
+
+union!(union_5000, 5000);
+union!(union_50000, 50000);
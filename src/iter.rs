@@ -0,0 +1,580 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::iter::FusedIterator;
+use std::slice;
+use std::vec;
+
+use super::{position, ArcK, DVec, Measure, Node, NodeRef, SharedPointerKind};
+
+/// A borrowing iterator over the elements of a `DVec`, created by
+/// `DVec::iter`. Rather than looking up each index from the root, it
+/// walks the tree once and hands out the elements of each leaf in
+/// turn, so it costs about as much as iterating over a slice.
+pub struct Iter<'a, T: 'a, const BITS: usize = 5, P: SharedPointerKind = ArcK, M: Measure<T> + 'a = ()> {
+    vec: &'a DVec<T, BITS, P, M>,
+    front: Cursor<'a, T, BITS, P, M>,
+    back: Cursor<'a, T, BITS, P, M>,
+    // `front.leaf` ends just before index `front_end` and `back.leaf`
+    // starts at index `back_start`; the elements in between have yet
+    // to be reached by either cursor. The leaves handed to the cursors
+    // are clipped so that the two never overlap. The front cursor
+    // starts out on the head of the vector and the back one on its
+    // tail.
+    front_end: usize,
+    back_start: usize,
+}
+
+/// A mutable iterator over the elements of a `DVec`, created by
+/// `DVec::iter_mut`. Each branch and leaf is un-shared (copied, if some
+/// other vector refers to it) once, when the iterator reaches it, rather
+/// than once per element as with `get_mut`. Like `get_mut`, it is
+/// only there for vectors without a measure.
+pub struct IterMut<'a, T: 'a, const BITS: usize = 5, P: SharedPointerKind = ArcK> {
+    front: slice::IterMut<'a, T>,
+    // The subtrees between the front and back leaves, in order, along
+    // with the number of elements they hold.
+    pending: VecDeque<&'a mut NodeRef<T, BITS, P>>,
+    pending_len: usize,
+    back: slice::IterMut<'a, T>,
+}
+
+/// An owning iterator over the elements of a `DVec`, created by its
+/// `IntoIterator` implementation. Nodes that no other vector refers to
+/// are taken apart and their elements moved out; only nodes that are
+/// still shared with another version get cloned.
+pub struct IntoIter<T, const BITS: usize = 5, P: SharedPointerKind = ArcK, M: Measure<T> = ()> {
+    front: vec::IntoIter<T>,
+    // The subtrees between the front and back leaves, in order, along
+    // with the number of elements they hold.
+    pending: VecDeque<NodeRef<T, BITS, P, M>>,
+    pending_len: usize,
+    back: vec::IntoIter<T>,
+}
+
+/// One end of an `Iter`: the elements left in the current leaf, plus
+/// for every level above it the children that still have to be
+/// visited (from the left for the front cursor, from the right for
+/// the back cursor).
+struct Cursor<'a, T: 'a, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> {
+    stack: Vec<slice::Iter<'a, NodeRef<T, BITS, P, M>>>,
+    leaf: slice::Iter<'a, T>,
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// Returns an iterator over the elements of the vector.
+    ///
+    /// ```rust
+    /// # use dogged::DVec;
+    /// let mut vec = DVec::new();
+    /// for i in 0..100 {
+    ///     vec.push(i);
+    /// }
+    /// assert_eq!(vec.iter().sum::<usize>(), 4950);
+    /// assert_eq!(vec.iter().rev().next(), Some(&99));
+    /// ```
+    pub fn iter(&self) -> Iter<'_, T, BITS, P, M> {
+        let roots = match self.root {
+            Some(ref root) => slice::from_ref(root),
+            None => &[],
+        };
+        Iter {
+            vec: self,
+            front: Cursor {
+                stack: vec![roots.iter()],
+                leaf: self.head.iter(),
+            },
+            back: Cursor {
+                stack: vec![roots.iter()],
+                leaf: self.tail.iter(),
+            },
+            front_end: self.head.len(),
+            back_start: self.head.len() + self.root_len.0,
+        }
+    }
+}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> IntoIterator for &'a DVec<T, BITS, P, M> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, BITS, P, M>;
+
+    fn into_iter(self) -> Iter<'a, T, BITS, P, M> {
+        self.iter()
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind> DVec<T, BITS, P> {
+    /// Returns an iterator that allows modifying each element of the
+    /// vector.
+    ///
+    /// ```rust
+    /// # use dogged::DVec;
+    /// let mut vec = DVec::new();
+    /// for i in 0..100 {
+    ///     vec.push(i);
+    /// }
+    /// let snapshot = vec.clone();
+    /// for element in vec.iter_mut() {
+    ///     *element *= 2;
+    /// }
+    /// assert_eq!(vec[99], 198);
+    /// assert_eq!(snapshot[99], 99);
+    /// ```
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, BITS, P> {
+        IterMut {
+            front: P::make_mut(&mut self.head).iter_mut(),
+            pending: self.root.iter_mut().collect(),
+            pending_len: self.root_len.0,
+            back: P::make_mut(&mut self.tail).iter_mut(),
+        }
+    }
+}
+
+impl<'a, T: Clone, const BITS: usize, P: SharedPointerKind> IntoIterator for &'a mut DVec<T, BITS, P> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T, BITS, P>;
+
+    fn into_iter(self) -> IterMut<'a, T, BITS, P> {
+        self.iter_mut()
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> IntoIterator for DVec<T, BITS, P, M> {
+    type Item = T;
+    type IntoIter = IntoIter<T, BITS, P, M>;
+
+    fn into_iter(self) -> IntoIter<T, BITS, P, M> {
+        IntoIter {
+            front: P::unwrap_or_clone(self.head).into_iter(),
+            pending: self.root.into_iter().collect(),
+            pending_len: self.root_len.0,
+            back: P::unwrap_or_clone(self.tail).into_iter(),
+        }
+    }
+}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> Cursor<'a, T, BITS, P, M> {
+    /// A front cursor whose next element is `vec[index]`, which must
+    /// be in the tree.
+    fn seek_front(vec: &'a DVec<T, BITS, P, M>, index: usize) -> Cursor<'a, T, BITS, P, M> {
+        let mut stack = vec![];
+        let mut p = &**vec.root.as_ref().unwrap();
+        let mut shift = vec.shift;
+        let mut index = index - vec.head.len();
+        loop {
+            match *p {
+                Node::Branch { ref children, ref sizes, .. } => {
+                    let (child, child_index) = position(sizes, shift, index);
+                    stack.push(children[child + 1..].iter());
+                    p = &children[child];
+                    index = child_index;
+                    shift = shift.dec(BITS);
+                }
+                Node::Leaf { ref elements, .. } => {
+                    return Cursor {
+                        stack,
+                        leaf: elements[index..].iter(),
+                    };
+                }
+            }
+        }
+    }
+
+    /// A back cursor whose next element is `vec[index]`, which must be
+    /// in the tree.
+    fn seek_back(vec: &'a DVec<T, BITS, P, M>, index: usize) -> Cursor<'a, T, BITS, P, M> {
+        let mut stack = vec![];
+        let mut p = &**vec.root.as_ref().unwrap();
+        let mut shift = vec.shift;
+        let mut index = index - vec.head.len();
+        loop {
+            match *p {
+                Node::Branch { ref children, ref sizes, .. } => {
+                    let (child, child_index) = position(sizes, shift, index);
+                    stack.push(children[..child].iter());
+                    p = &children[child];
+                    index = child_index;
+                    shift = shift.dec(BITS);
+                }
+                Node::Leaf { ref elements, .. } => {
+                    return Cursor {
+                        stack,
+                        leaf: elements[..index + 1].iter(),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Moves on to the next leaf to the right, if there is one in the
+    /// tree.
+    fn next_leaf(&mut self) -> Option<&'a [T]> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(node) => {
+                    match **node {
+                        Node::Leaf { ref elements, .. } => return Some(elements),
+                        Node::Branch { ref children, .. } => self.stack.push(children.iter()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves on to the next leaf to the left, if there is one.
+    fn next_leaf_back(&mut self) -> Option<&'a [T]> {
+        loop {
+            match self.stack.last_mut()?.next_back() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(node) => {
+                    match **node {
+                        Node::Leaf { ref elements, .. } => return Some(elements),
+                        Node::Branch { ref children, .. } => self.stack.push(children.iter()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> Iter<'a, T, BITS, P, M> {
+    /// Called when the front leaf is used up.
+    #[cold]
+    fn next_front_leaf(&mut self) -> Option<&'a T> {
+        if self.front_end == self.back_start {
+            // Only the back leaf is left.
+            let element = self.back.leaf.next();
+            if element.is_some() {
+                self.front_end += 1;
+                self.back_start += 1;
+            }
+            return element;
+        }
+
+        let leaf = self.front.next_leaf().unwrap();
+        let len = cmp::min(leaf.len(), self.back_start - self.front_end);
+        self.front.leaf = leaf[..len].iter();
+        self.front_end += len;
+        self.front.leaf.next()
+    }
+
+    /// Called when the back leaf is used up.
+    #[cold]
+    fn next_back_leaf(&mut self) -> Option<&'a T> {
+        if self.front_end == self.back_start {
+            // Only the front leaf is left.
+            let element = self.front.leaf.next_back();
+            if element.is_some() {
+                self.front_end -= 1;
+                self.back_start -= 1;
+            }
+            return element;
+        }
+
+        let leaf = self.back.next_leaf_back().unwrap();
+        let len = cmp::min(leaf.len(), self.back_start - self.front_end);
+        self.back.leaf = leaf[leaf.len() - len..].iter();
+        self.back_start -= len;
+        self.back.leaf.next_back()
+    }
+
+    /// Drops everything that is left.
+    fn exhaust(&mut self) {
+        self.front.leaf = [].iter();
+        self.back.leaf = [].iter();
+        self.front_end = self.back_start;
+    }
+}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> Iterator for Iter<'a, T, BITS, P, M> {
+    type Item = &'a T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a T> {
+        match self.front.leaf.next() {
+            Some(element) => Some(element),
+            None => self.next_front_leaf(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.leaf.len() + (self.back_start - self.front_end) +
+                  self.back.leaf.len();
+        (len, Some(len))
+    }
+
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    fn last(mut self) -> Option<&'a T> {
+        self.next_back()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<&'a T> {
+        let front_len = self.front.leaf.len();
+        if n < front_len {
+            return self.front.leaf.nth(n);
+        }
+        if n >= self.len() {
+            self.exhaust();
+            return None;
+        }
+
+        // Rather than skipping leaf by leaf, start over from the root
+        // at the element we want.
+        let target = self.front_end - front_len + n;
+        if target >= self.back_start {
+            let skip = target - self.back_start;
+            self.front.leaf = [].iter();
+            self.back.leaf = self.back.leaf.as_slice()[skip..].iter();
+            self.front_end = target;
+            self.back_start = target;
+            return self.next_front_leaf();
+        }
+
+        self.front = Cursor::seek_front(self.vec, target);
+        let leaf = self.front.leaf.as_slice();
+        let len = cmp::min(leaf.len(), self.back_start - target);
+        self.front.leaf = leaf[..len].iter();
+        self.front_end = target + len;
+        self.front.leaf.next()
+    }
+}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> DoubleEndedIterator for Iter<'a, T, BITS, P, M> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a T> {
+        match self.back.leaf.next_back() {
+            Some(element) => Some(element),
+            None => self.next_back_leaf(),
+        }
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<&'a T> {
+        let back_len = self.back.leaf.len();
+        if n < back_len {
+            return self.back.leaf.nth_back(n);
+        }
+        if n >= self.len() {
+            self.exhaust();
+            return None;
+        }
+
+        let target = self.back_start + back_len - 1 - n;
+        if target < self.front_end {
+            let keep = self.front.leaf.len() - (self.front_end - 1 - target);
+            self.back.leaf = [].iter();
+            self.front.leaf = self.front.leaf.as_slice()[..keep].iter();
+            self.front_end = target + 1;
+            self.back_start = target + 1;
+            return self.next_back_leaf();
+        }
+
+        self.back = Cursor::seek_back(self.vec, target);
+        let leaf = self.back.leaf.as_slice();
+        let len = cmp::min(leaf.len(), target + 1 - self.front_end);
+        self.back.leaf = leaf[leaf.len() - len..].iter();
+        self.back_start = target + 1 - len;
+        self.back.leaf.next_back()
+    }
+}
+
+impl<'a, T: Clone, const BITS: usize, P: SharedPointerKind> IterMut<'a, T, BITS, P> {
+    /// Takes the first (or, if `back` is set, the last) leaf out of
+    /// `pending`, un-sharing the nodes on the way.
+    fn take_leaf(&mut self, back: bool) -> Option<&'a mut [T]> {
+        loop {
+            let node = if back {
+                self.pending.pop_back()?
+            } else {
+                self.pending.pop_front()?
+            };
+
+            match *Node::<T, BITS, P, ()>::make_mut(node) {
+                Node::Leaf { ref mut elements, .. } => return Some(elements),
+                Node::Branch { ref mut children, .. } => {
+                    if back {
+                        self.pending.extend(children.iter_mut());
+                    } else {
+                        for child in children.iter_mut().rev() {
+                            self.pending.push_front(child);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[cold]
+    fn next_front_leaf(&mut self) -> Option<&'a mut T> {
+        match self.take_leaf(false) {
+            Some(elements) => {
+                self.pending_len -= elements.len();
+                self.front = elements.iter_mut();
+                self.front.next()
+            }
+            None => self.back.next(),
+        }
+    }
+
+    #[cold]
+    fn next_back_leaf(&mut self) -> Option<&'a mut T> {
+        match self.take_leaf(true) {
+            Some(elements) => {
+                self.pending_len -= elements.len();
+                self.back = elements.iter_mut();
+                self.back.next_back()
+            }
+            None => self.front.next_back(),
+        }
+    }
+}
+
+impl<'a, T: Clone, const BITS: usize, P: SharedPointerKind> Iterator for IterMut<'a, T, BITS, P> {
+    type Item = &'a mut T;
+
+    #[inline]
+    fn next(&mut self) -> Option<&'a mut T> {
+        match self.front.next() {
+            Some(element) => Some(element),
+            None => self.next_front_leaf(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.len() + self.pending_len + self.back.len();
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: Clone, const BITS: usize, P: SharedPointerKind> DoubleEndedIterator for IterMut<'a, T, BITS, P> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a mut T> {
+        match self.back.next_back() {
+            Some(element) => Some(element),
+            None => self.next_back_leaf(),
+        }
+    }
+}
+
+impl<'a, T: Clone, const BITS: usize, P: SharedPointerKind> ExactSizeIterator for IterMut<'a, T, BITS, P> {}
+
+impl<'a, T: Clone, const BITS: usize, P: SharedPointerKind> FusedIterator for IterMut<'a, T, BITS, P> {}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> IntoIter<T, BITS, P, M> {
+    /// Takes the first (or, if `back` is set, the last) leaf out of
+    /// `pending`, splitting up the branches in the way.
+    fn take_leaf(&mut self, back: bool) -> Option<Vec<T>> {
+        loop {
+            let node = if back {
+                self.pending.pop_back()?
+            } else {
+                self.pending.pop_front()?
+            };
+
+            let children = match P::try_unwrap(node) {
+                Ok(Node::Leaf { elements, .. }) => return Some(elements),
+                Ok(Node::Branch { children, .. }) => children,
+                Err(shared) => {
+                    match *shared {
+                        Node::Leaf { ref elements, .. } => return Some(elements.clone()),
+                        Node::Branch { ref children, .. } => children.clone(),
+                    }
+                }
+            };
+
+            if back {
+                self.pending.extend(children);
+            } else {
+                for child in children.into_iter().rev() {
+                    self.pending.push_front(child);
+                }
+            }
+        }
+    }
+
+    #[cold]
+    fn next_front_leaf(&mut self) -> Option<T> {
+        match self.take_leaf(false) {
+            Some(elements) => {
+                self.pending_len -= elements.len();
+                self.front = elements.into_iter();
+                self.front.next()
+            }
+            None => self.back.next(),
+        }
+    }
+
+    #[cold]
+    fn next_back_leaf(&mut self) -> Option<T> {
+        match self.take_leaf(true) {
+            Some(elements) => {
+                self.pending_len -= elements.len();
+                self.back = elements.into_iter();
+                self.back.next_back()
+            }
+            None => self.front.next_back(),
+        }
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Iterator for IntoIter<T, BITS, P, M> {
+    type Item = T;
+
+    #[inline]
+    fn next(&mut self) -> Option<T> {
+        match self.front.next() {
+            Some(element) => Some(element),
+            None => self.next_front_leaf(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.front.len() + self.pending_len + self.back.len();
+        (len, Some(len))
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DoubleEndedIterator for IntoIter<T, BITS, P, M> {
+    #[inline]
+    fn next_back(&mut self) -> Option<T> {
+        match self.back.next_back() {
+            Some(element) => Some(element),
+            None => self.next_back_leaf(),
+        }
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> ExactSizeIterator for IntoIter<T, BITS, P, M> {}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> FusedIterator for IntoIter<T, BITS, P, M> {}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> ExactSizeIterator for Iter<'a, T, BITS, P, M> {}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> FusedIterator for Iter<'a, T, BITS, P, M> {}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> Clone for Iter<'a, T, BITS, P, M> {
+    fn clone(&self) -> Self {
+        Iter {
+            vec: self.vec,
+            front: self.front.clone(),
+            back: self.back.clone(),
+            front_end: self.front_end,
+            back_start: self.back_start,
+        }
+    }
+}
+
+impl<'a, T, const BITS: usize, P: SharedPointerKind, M: Measure<T> + 'a> Clone for Cursor<'a, T, BITS, P, M> {
+    fn clone(&self) -> Self {
+        Cursor {
+            stack: self.stack.clone(),
+            leaf: self.leaf.clone(),
+        }
+    }
+}
@@ -1,6 +1,5 @@
 #![cfg_attr(test, feature(test))]
 
-#[cfg(test)]
 use std::cmp;
 
 #[cfg(test)]
@@ -9,84 +8,90 @@ extern crate test as test_crate;
 #[cfg(test)]
 extern crate rand;
 
-use std::cmp::{PartialOrd, Ordering};
-use std::fmt::Debug;
-use std::ops;
-use std::mem;
-use std::sync::Arc;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
 
-macro_rules! debug {
-    ($($t:tt)*) => {
-        // println!($($t)*);
-    }
-}
+#[cfg(feature = "serde")]
+extern crate serde;
 
-#[cfg(test)]
-const VALIDATE: bool = false;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
-#[cfg(not(small_branch))]
-const BRANCH_FACTOR: usize = 32;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_test;
+
+use std::cmp::{PartialOrd, Ordering};
+use std::iter::FromIterator;
+use std::ops::{self, Bound, RangeBounds};
+use std::fmt;
+use std::mem;
 
-#[cfg(not(small_branch))]
-const BITS_PER_LEVEL: usize = 5;
+use merkle::HashCache;
+use sum_vec::{combine_all, measure_all};
 
-#[cfg(not(small_branch))]
-macro_rules! no_children {
-    () => {
-        [None, None, None, None,
-         None, None, None, None,
-         None, None, None, None,
-         None, None, None, None,
-         None, None, None, None,
-         None, None, None, None,
-         None, None, None, None,
-         None, None, None, None]
-    }
-}
+/// Runs the generic test function `$test` once for each list of
+/// generic arguments, as a test named `$test::$name`.
+#[cfg(test)]
+macro_rules! test_instances {
+    ($test: ident { $($name: ident => [$($args: tt)*]),* $(,)* }) => {
+        mod $test {
+            use super::*;
 
-#[cfg(not(small_branch))]
-macro_rules! clone_arr {
-    ($source:expr) => {
-        {
-            let s = $source;
-            [
-                s[0x00].clone(), s[0x01].clone(), s[0x02].clone(), s[0x03].clone(),
-                s[0x04].clone(), s[0x05].clone(), s[0x06].clone(), s[0x07].clone(),
-                s[0x08].clone(), s[0x09].clone(), s[0x0A].clone(), s[0x0B].clone(),
-                s[0x0C].clone(), s[0x0D].clone(), s[0x0E].clone(), s[0x0F].clone(),
-                s[0x10].clone(), s[0x11].clone(), s[0x12].clone(), s[0x13].clone(),
-                s[0x14].clone(), s[0x15].clone(), s[0x16].clone(), s[0x17].clone(),
-                s[0x18].clone(), s[0x19].clone(), s[0x1A].clone(), s[0x1B].clone(),
-                s[0x1C].clone(), s[0x1D].clone(), s[0x1E].clone(), s[0x1F].clone(),
-            ]
+            $(
+                #[test]
+                fn $name() {
+                    $test::<$($args)*>();
+                }
+            )*
         }
     }
 }
 
-#[cfg(small_branch)]
-const BRANCH_FACTOR: usize = 4;
+pub mod hash_map;
+pub mod hash_set;
+#[cfg(feature = "mmap")]
+pub mod mapped;
+pub mod ord_map;
+pub mod ord_set;
+pub mod snapshot;
+pub mod string;
+pub mod sum_vec;
+mod diff;
+mod iter;
+mod merkle;
+#[cfg(feature = "serde")]
+mod serde_impl;
+mod shared;
+mod transient;
 
-#[cfg(small_branch)]
-const BITS_PER_LEVEL: usize = 2;
+pub use hash_map::DHashMap;
+pub use hash_set::DHashSet;
+#[cfg(feature = "mmap")]
+pub use mapped::MappedDVec;
+pub use ord_map::DOrdMap;
+pub use ord_set::DOrdSet;
+pub use string::DString;
+pub use sum_vec::{DSumVec, Measure};
+pub use diff::{Diff, DiffItem};
+pub use iter::{IntoIter, Iter, IterMut};
+pub use shared::{ArcK, RcK, SharedPointerKind};
+pub use transient::TransientDVec;
 
-#[cfg(small_branch)]
-macro_rules! no_children {
-    () => {
-        [None, None, None, None]
+macro_rules! debug {
+    ($($t:tt)*) => {
+        // println!($($t)*);
     }
 }
 
-#[cfg(small_branch)]
-macro_rules! clone_arr {
-    ($source:expr) => {
-        {
-            let s = $source;
-            [
-                s[0x00].clone(), s[0x01].clone(), s[0x02].clone(), s[0x03].clone(),
-            ]
-        }
-    }
-}
+#[cfg(test)]
+const VALIDATE: bool = false;
+
+/// When concatenating, we allow a level to hold up to this many more
+/// nodes than would strictly be needed to store its contents. This is
+/// the "search step" slack of an RRB tree: it bounds how far a lookup
+/// through a size table can be off from the radix guess, while letting
+/// us keep most existing nodes (and hence sharing) untouched.
+const EXTRA_SEARCH_STEPS: usize = 2;
 
 /// A **persistent** vector of `T` elements. Persistent collections
 /// change the trade-off relative to ordinary collections: they are
@@ -110,41 +115,110 @@ macro_rules! clone_arr {
 /// assert_eq!(vec1.len(), 1);
 /// assert_eq!(vec2.len(), 2);
 /// ```
-#[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
-pub struct DVec<T> {
-    root_len: Index, // number of things reachable from root (excluding tail)
-    shift: Shift, // depth * BITS_PER_LEVEL
-    root: Option<Arc<Node<T>>>,
-    tail: Vec<T>, // incomplete set of BITS_PER_LEVEL items at end of list
+///
+/// Each node of the tree has up to `1 << BITS` children or elements.
+/// The default of 32 suits most uses; a narrower tree makes updates
+/// copy less at the cost of deeper lookups. `DVec::new` always uses the
+/// default, so other widths are created with `DVec::default` or by
+/// collecting:
+///
+/// ```rust
+/// # use dogged::DVec;
+/// let narrow: DVec<u32, 2> = (0..100).collect();
+/// assert_eq!(narrow[99], 99);
+/// ```
+///
+/// Nodes are shared through `Arc` by default. `DVecLocal` uses `Rc`
+/// instead, which is cheaper to clone and update but cannot be sent
+/// to another thread.
+///
+/// Every node also caches a summary of its elements, as defined by the
+/// `Measure` `M`, which `DSumVec` uses for prefix sums and searches.
+/// The default measure, `()`, summarizes nothing and takes no room.
+pub struct DVec<T, const BITS: usize = 5, P: SharedPointerKind = ArcK, M: Measure<T> = ()> {
+    root_len: Index, // number of things reachable from root (excluding head and tail)
+    shift: Shift, // depth * BITS
+    root: Option<NodeRef<T, BITS, P, M>>,
+    head: P::Pointer<Vec<T>>, // incomplete leaf at start of list
+    tail: P::Pointer<Vec<T>>, // incomplete leaf at end of list
 }
 
+/// A `DVec` that can be shared between threads.
+pub type DVecSync<T, const BITS: usize = 5> = DVec<T, BITS, ArcK>;
+
+/// A `DVec` for single-threaded code, which avoids atomic reference
+/// counting.
+///
+/// ```rust
+/// # use dogged::DVecLocal;
+/// let mut vec: DVecLocal<u32> = DVecLocal::default();
+/// vec.push(22);
+/// let snapshot = vec.clone();
+/// vec[0] = 44;
+/// assert_eq!(snapshot[0], 22);
+/// ```
+pub type DVecLocal<T, const BITS: usize = 5> = DVec<T, BITS, RcK>;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 struct Shift(usize);
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 struct Index(usize);
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-enum Node<T> {
+/// A node in a relaxed radix balanced (RRB) tree. As long as a vector
+/// is only built with `push`, every branch is *dense*: all of its
+/// children but the last are completely full, and we can find the
+/// child holding an index just by looking at its bits. Concatenation
+/// may produce branches whose children are not full; those carry a
+/// size table instead.
+///
+/// With the `cache-hashes` feature, every node also has room for the
+/// hash of its elements, which `DVec::content_hash` fills in when
+/// asked. Anything that changes a node must get to it through
+/// `Node::make_mut`, which forgets it.
+///
+/// The summary of a node, on the other hand, is always up to date:
+/// whatever changes the elements or children of a node recomputes it
+/// with `Node::resummarize` before returning.
+enum Node<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> {
     Branch {
-        children: [Option<Arc<Node<T>>>; BRANCH_FACTOR],
+        children: Vec<NodeRef<T, BITS, P, M>>,
+        // `None` if the branch is dense; otherwise `sizes[i]` is the
+        // number of elements in `children[0..=i]`.
+        sizes: Option<Vec<usize>>,
+        hash: HashCache,
+        summary: M::Summary,
     },
     Leaf {
         elements: Vec<T>,
+        hash: HashCache,
+        summary: M::Summary,
     },
 }
 
-impl<T: Clone + Debug> DVec<T> {
+type NodeRef<T, const BITS: usize, P, M = ()> = <P as SharedPointerKind>::Pointer<Node<T, BITS, P, M>>;
+
+impl<T> DVec<T> {
+    /// Creates an empty vector with the default branching factor. Use
+    /// `DVec::default()` for other branching factors.
     pub fn new() -> Self {
-        DVec {
-            root_len: Index(0),
-            shift: Shift(0),
-            root: None,
-            tail: Vec::with_capacity(BRANCH_FACTOR),
-        }
+        DVec::default()
     }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    const BRANCH_FACTOR: usize = {
+        assert!(BITS >= 2 && BITS <= 16, "DVec needs between 2 and 16 BITS");
+        1 << BITS
+    };
 
     pub fn get(&self, index: usize) -> Option<&T> {
+        let head_len = self.head.len();
+        if index < head_len {
+            return Some(&self.head[index]);
+        }
+
+        let index = index - head_len;
         if index < self.root_len.0 {
             Some(self.root.as_ref().unwrap().get(self.shift, Index(index)))
         } else {
@@ -152,83 +226,671 @@ impl<T: Clone + Debug> DVec<T> {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.head.len() + self.root_len.0 + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if the two vectors share their tree, head and tail,
+    /// so that neither has changed since one was cloned from the other.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        let roots = match (&self.root, &other.root) {
+            (Some(a), Some(b)) => P::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        roots && P::ptr_eq(&self.head, &other.head) && P::ptr_eq(&self.tail, &other.tail)
+    }
+
+    #[cfg(not(test))]
+    fn validate(&self) {}
+
+    #[cfg(test)]
+    fn validate(&self) {
+        if VALIDATE {
+            self.assert_valid();
+        }
+    }
+
+    /// Checks the invariants of the tree regardless of `VALIDATE`;
+    /// tests for operations that reshape the tree call this directly.
+    #[cfg(test)]
+    fn assert_valid(&self) {
+        match self.root {
+            Some(ref root) => {
+                match root.validate(&mut vec![], self.shift) {
+                    Ok(len) => {
+                        assert!(self.root_len == len,
+                                "root_len is {:?} but tree holds {} elements",
+                                self.root_len,
+                                len)
+                    }
+                    Err(err) => panic!("validation error {}", err),
+                }
+                if let Node::Branch { ref children, .. } = **root {
+                    assert!(children.len() > 1,
+                            "root has a single child but shift is {:?}",
+                            self.shift);
+                }
+            }
+            None => {
+                assert!(self.root_len == 0, "no root but root_len is {:?}", self.root_len);
+                assert!(self.shift == 0, "no root but shift is {:?}", self.shift);
+            }
+        }
+        let head_len = self.head.len();
+        assert!(head_len < Self::BRANCH_FACTOR,
+                "head got too long: {:?}",
+                head_len);
+        let tail_len = self.tail.len();
+        assert!(tail_len < Self::BRANCH_FACTOR,
+                "tail got too long: {:?}",
+                tail_len);
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind> DVec<T, BITS, P> {
+    /// Only vectors without a measure hand out `&mut T`, as nothing
+    /// would bring the summaries above the element up to date after
+    /// it changes; measured vectors have `set` and `update` instead.
     pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let head_len = self.head.len();
+        if index < head_len {
+            return Some(&mut P::make_mut(&mut self.head)[index]);
+        }
+
+        let index = index - head_len;
         if index < self.root_len.0 {
-            Some(Arc::make_mut(self.root.as_mut().unwrap()).get_mut(self.shift, Index(index)))
+            Some(Node::<T, BITS, P, ()>::make_mut(self.root.as_mut().unwrap()).get_mut(self.shift, Index(index)))
         } else {
-            self.tail.get_mut(index - self.root_len.0)
+            P::make_mut(&mut self.tail).get_mut(index - self.root_len.0)
         }
     }
+}
 
-    pub fn len(&self) -> usize {
-        self.root_len.0 + self.tail.len()
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// Replaces the element at `index` with `element`, returning the
+    /// old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn set(&mut self, index: usize, element: T) -> T {
+        self.update(index, |old| mem::replace(old, element))
     }
 
-    pub fn push(&mut self, element: T) {
-        self.tail.push(element);
+    /// Calls `f` on the element at `index`, and then brings the
+    /// summaries above it up to date.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn update<R, F>(&mut self, index: usize, f: F) -> R
+        where F: FnOnce(&mut T) -> R
+    {
+        let len = self.len();
+        if index >= len {
+            panic!("index `{}` out of bounds in DVec of length `{}`", index, len);
+        }
+
+        let head_len = self.head.len();
+        if index < head_len {
+            return f(&mut P::make_mut(&mut self.head)[index]);
+        }
 
-        if self.tail.len() == BRANCH_FACTOR {
-            let tail = mem::replace(&mut self.tail, Vec::with_capacity(BRANCH_FACTOR));
-            self.push_tail(tail);
-            self.root_len.0 += BRANCH_FACTOR;
+        let index = index - head_len;
+        if index < self.root_len.0 {
+            Node::<T, BITS, P, M>::make_mut(self.root.as_mut().unwrap()).update(self.shift, Index(index), f)
+        } else {
+            f(&mut P::make_mut(&mut self.tail)[index - self.root_len.0])
         }
+    }
+
+    pub fn push(&mut self, element: T) {
+        let full = match P::get_mut(&mut self.tail) {
+            Some(tail) => {
+                tail.push(element);
+                if tail.len() < Self::BRANCH_FACTOR {
+                    return;
+                }
+                mem::replace(tail, Vec::with_capacity(Self::BRANCH_FACTOR))
+            }
+            None => {
+                // A clone shares the tail, so copy it, leaving room
+                // for it to fill up.
+                let mut tail = Vec::with_capacity(Self::BRANCH_FACTOR);
+                tail.extend_from_slice(&self.tail);
+                tail.push(element);
+                if tail.len() < Self::BRANCH_FACTOR {
+                    self.tail = P::new(tail);
+                    return;
+                }
+                self.tail = P::new(Vec::with_capacity(Self::BRANCH_FACTOR));
+                tail
+            }
+        };
 
+        self.push_tail(full);
         self.validate();
     }
 
+    /// Moves `tail` into the tree as its new last leaf. This is
+    /// normally a full leaf, but `append` also uses it to flush a
+    /// partial one.
     #[cold]
     fn push_tail(&mut self, tail: Vec<T>) {
-        // We just filled up the tail, therefore we should have an
-        // even multiple of BRANCH_FACTOR elements.
-        debug_assert!(self.root_len.0 % BRANCH_FACTOR == 0);
         debug!("---------------------------------------------------------------------------");
-        debug!("DVec::push_tail(tail={:?})", tail);
+        debug!("DVec::push_tail(tail_len={:?})", tail.len());
 
-        if let Some(root) = self.root.as_mut() {
-            // Find out the total capacity in the "leaf" tree.
-            let capacity = BRANCH_FACTOR << self.shift.0;
+        let len = tail.len();
+        let leaf = P::new(Node::leaf(tail));
+        self.root_len.0 += len;
 
+        if let Some(root) = self.root.as_mut() {
             // Still have room.
-            debug!("push_tail: self.root_len={:?} capacity={:?}", self.root_len, capacity);
-            if (self.root_len.0 + BRANCH_FACTOR) <= capacity {
-                Arc::make_mut(root).push_tail(self.shift, self.root_len, tail);
+            if root.has_room() {
+                Node::<T, BITS, P, M>::make_mut(root).push_leaf(self.shift, leaf, len);
                 return;
             }
 
             // Going to need to add another level.
-            let mut children = no_children!();
-            children[0] = Some(root.clone());
-            children[1] = Some(Node::branch_ladder(self.shift, tail));
-            *root = Arc::new(Node::Branch { children: children });
-            self.shift = self.shift.inc();
+            let children = vec![root.clone(), Node::<T, BITS, P, M>::branch_ladder(self.shift, leaf)];
+            self.shift = self.shift.inc(BITS);
+            *root = P::new(Node::<T, BITS, P, M>::new_branch(children, self.shift));
             return;
         }
 
-        debug_assert!(self.root_len == 0);
         debug_assert!(self.shift == 0);
-        self.root = Some(Arc::new(Node::Leaf { elements: tail }));
+        self.root = Some(leaf);
     }
 
-    #[cfg(not(test))]
-    fn validate(&self) {}
+    /// Removes the last element and returns it, or `None` if the
+    /// vector is empty. Subtrees shared with clones of this vector
+    /// are left untouched.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.tail.is_empty() {
+            if self.root_len.0 == 0 {
+                // Whatever is left is in the head.
+                if self.head.is_empty() {
+                    return None;
+                }
+                let element = P::make_mut(&mut self.head).pop();
+                self.validate();
+                return element;
+            }
+            self.pop_tail();
+        }
 
-    #[cfg(test)]
-    fn validate(&self) {
-        if VALIDATE {
-            if let Some(ref root) = self.root {
-                if let Err(err) = root.validate(&mut vec![], self.shift, self.root_len) {
-                    panic!("validation error {} with {:#?}", err, root);
+        let element = P::make_mut(&mut self.tail).pop();
+        self.validate();
+        element
+    }
+
+    /// Adds `element` at the front of the vector. Like `push`, this is
+    /// amortized O(1): elements gather in a head buffer, which only
+    /// once it fills up is concatenated onto the front of the tree as
+    /// a new leaf.
+    ///
+    /// Together with `pop_front` (or `push` and `pop`), this lets a
+    /// `DVec` serve as a double-ended queue that can be snapshotted at
+    /// any point:
+    ///
+    /// ```rust
+    /// # use dogged::DVec;
+    /// let mut queue = DVec::new();
+    /// for i in 0..100 {
+    ///     queue.push(i);
+    /// }
+    /// let snapshot = queue.clone();
+    /// queue.push_front(-1);
+    /// assert_eq!(queue.pop_front(), Some(-1));
+    /// assert_eq!(queue.pop_front(), Some(0));
+    /// assert_eq!(queue.len(), 99);
+    /// assert_eq!(snapshot[0], 0);
+    /// ```
+    pub fn push_front(&mut self, element: T) {
+        let head = P::make_mut(&mut self.head);
+        head.insert(0, element);
+        if head.len() == Self::BRANCH_FACTOR {
+            let head = mem::replace(head, Vec::with_capacity(Self::BRANCH_FACTOR));
+            self.push_head(head);
+        }
+        self.validate();
+    }
+
+    /// Moves `head` into the tree as its new first leaf. This is
+    /// normally a full leaf, but `append` also uses it to flush a
+    /// partial one.
+    #[cold]
+    fn push_head(&mut self, head: Vec<T>) {
+        debug!("DVec::push_head(head_len={:?})", head.len());
+
+        let len = head.len();
+        let leaf = P::new(Node::leaf(head));
+        self.root_len.0 += len;
+
+        if let Some(root) = self.root.as_mut() {
+            if root.has_room_front() {
+                Node::<T, BITS, P, M>::make_mut(root).push_leaf_front(self.shift, leaf, len);
+                return;
+            }
+
+            let children = vec![Node::<T, BITS, P, M>::branch_ladder(self.shift, leaf), root.clone()];
+            self.shift = self.shift.inc(BITS);
+            *root = P::new(Node::<T, BITS, P, M>::new_branch(children, self.shift));
+            return;
+        }
+
+        debug_assert!(self.shift == 0);
+        self.root = Some(leaf);
+    }
+
+    /// Removes the first element and returns it, or `None` if the
+    /// vector is empty. This is the mirror image of `pop`: the first
+    /// leaf of the tree is moved into the head when that runs out.
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.head.is_empty() {
+            if self.root_len.0 == 0 {
+                // Whatever is left is in the tail.
+                if self.tail.is_empty() {
+                    return None;
                 }
+                let element = P::make_mut(&mut self.tail).remove(0);
+                self.validate();
+                return Some(element);
+            }
+            self.pop_head();
+        }
+
+        let element = P::make_mut(&mut self.head).remove(0);
+        self.validate();
+        Some(element)
+    }
+
+    /// Moves the first leaf of the tree into the head, which must be
+    /// empty.
+    #[cold]
+    fn pop_head(&mut self) {
+        debug_assert!(self.head.is_empty());
+        debug_assert!(self.root_len.0 > 0);
+        debug!("DVec::pop_head(root_len={:?}, shift={:?})", self.root_len, self.shift);
+
+        let mut leaf = self.root.clone().unwrap();
+        loop {
+            leaf = match *leaf {
+                Node::Leaf { .. } => break,
+                Node::Branch { ref children, .. } => children[0].clone(),
+            };
+        }
+        let len = leaf.slots();
+
+        // Detach the leaf from the tree first, so that we hold the only
+        // reference to it unless it is shared with another version.
+        if len == self.root_len.0 {
+            self.root = None;
+            self.shift = Shift(0);
+        } else {
+            Node::<T, BITS, P, M>::make_mut(self.root.as_mut().unwrap()).drop_front(self.shift,
+                                                                  self.root_len,
+                                                                  Index(len));
+            self.collapse_root();
+        }
+        self.root_len.0 -= len;
+        self.head = P::new(Node::<T, BITS, P, M>::into_elements(leaf));
+    }
+
+    /// Shortens the vector to `len` elements, dropping the rest. Has
+    /// no effect if `len` is greater than or equal to the current
+    /// length.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len() {
+            return;
+        }
+
+        let head_len = self.head.len();
+        if len <= head_len {
+            P::make_mut(&mut self.head).truncate(len);
+            self.root = None;
+            self.shift = Shift(0);
+            self.root_len = Index(0);
+            Self::clear_buffer(&mut self.tail);
+            self.validate();
+            return;
+        }
+
+        let len = len - head_len;
+        if len >= self.root_len.0 {
+            P::make_mut(&mut self.tail).truncate(len - self.root_len.0);
+            self.validate();
+            return;
+        }
+
+        // The cut falls inside the tree, so the tail goes away entirely.
+        Self::clear_buffer(&mut self.tail);
+
+        Node::<T, BITS, P, M>::make_mut(self.root.as_mut().unwrap()).truncate(self.shift,
+                                                            self.root_len,
+                                                            Index(len));
+        self.root_len = Index(len);
+        self.collapse_root();
+
+        // If we cut through the middle of a leaf, make what is left of
+        // it the new tail; that way, pushing more elements keeps the
+        // tree dense.
+        if self.root.as_ref().unwrap().last_leaf_len() < Self::BRANCH_FACTOR {
+            self.pop_tail();
+        }
+
+        self.validate();
+    }
+
+    /// Moves the last leaf of the tree into the tail, which must be
+    /// empty.
+    #[cold]
+    fn pop_tail(&mut self) {
+        debug_assert!(self.tail.is_empty());
+        debug_assert!(self.root_len.0 > 0);
+        debug!("DVec::pop_tail(root_len={:?}, shift={:?})", self.root_len, self.shift);
+
+        let leaf = if self.shift.0 == 0 {
+            // The root is the only leaf.
+            self.root.take().unwrap()
+        } else {
+            Node::<T, BITS, P, M>::make_mut(self.root.as_mut().unwrap()).pop_leaf(self.shift)
+        };
+
+        self.tail = P::new(Node::<T, BITS, P, M>::into_elements(leaf));
+        self.root_len.0 -= self.tail.len();
+        self.collapse_root();
+    }
+
+    /// Moves all the elements of `other` onto the end of `self`. This
+    /// takes O(log n) time: the trees of both vectors are concatenated
+    /// and everything but the nodes along the seam remains shared with
+    /// `other` (and any clones of `self`).
+    ///
+    /// ```rust
+    /// # use dogged::DVec;
+    /// let mut vec1 = DVec::new();
+    /// let mut vec2 = DVec::new();
+    /// for i in 0..100 {
+    ///     vec1.push(i);
+    ///     vec2.push(i + 100);
+    /// }
+    /// vec1.append(vec2);
+    /// assert_eq!(vec1.len(), 200);
+    /// assert_eq!(vec1[150], 150);
+    /// ```
+    pub fn append(&mut self, other: DVec<T, BITS, P, M>) {
+        if other.is_empty() {
+            return;
+        }
+
+        if self.is_empty() {
+            *self = other;
+            return;
+        }
+
+        // With no tree on the other side, there are at most two
+        // leaves worth of elements to move.
+        let mut other = other;
+        if other.root.is_none() {
+            let elements = P::unwrap_or_clone(other.head).into_iter()
+                                                          .chain(P::unwrap_or_clone(other.tail));
+            for element in elements {
+                self.push(element);
+            }
+            return;
+        }
+
+        // Flush our tail and the other head into the trees, even if
+        // they are not full, so that the seam is between two trees.
+        let head = mem::replace(&mut other.head, P::new(Vec::new()));
+        if !head.is_empty() {
+            other.push_head(P::unwrap_or_clone(head));
+        }
+        let tail = mem::replace(&mut self.tail, other.tail);
+        if !tail.is_empty() {
+            self.push_tail(P::unwrap_or_clone(tail));
+        }
+
+        let other_root = other.root.unwrap();
+        match self.root {
+            Some(ref root) => {
+                let (root, shift) = Node::<T, BITS, P, M>::concat(root,
+                                                               self.shift,
+                                                               &other_root,
+                                                               other.shift);
+                self.root = Some(root);
+                self.shift = shift;
+            }
+            None => {
+                // We only had a head, which stays where it is.
+                self.root = Some(other_root);
+                self.shift = other.shift;
             }
-            let tail_len = self.tail.len();
-            assert!(tail_len < BRANCH_FACTOR,
-                    "tail got too long: {:?}",
-                    tail_len);
+        }
+        self.root_len.0 += other.root_len.0;
+        self.collapse_root();
+
+        self.validate();
+    }
+
+    /// Splits the vector in two at `at`: `self` keeps the elements
+    /// `[0, at)` and the elements `[at, len)` are returned. Both
+    /// halves share the untouched parts of the tree, so this takes
+    /// O(log n) time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_off(&mut self, at: usize) -> DVec<T, BITS, P, M> {
+        let len = self.len();
+        if at > len {
+            panic!("`at` split index (is {}) should be <= len (is {})", at, len);
+        }
+
+        let mut other = self.clone();
+        other.drop_front(at);
+        self.truncate(at);
+        other
+    }
+
+    /// Returns the two halves `[0, at)` and `[at, len)` of this vector
+    /// as new vectors, leaving `self` alone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    pub fn split_at(&self, at: usize) -> (DVec<T, BITS, P, M>, DVec<T, BITS, P, M>) {
+        let mut left = self.clone();
+        let right = left.split_off(at);
+        (left, right)
+    }
+
+    /// Returns a new vector holding the elements in `range`, sharing
+    /// structure with `self`.
+    ///
+    /// ```rust
+    /// # use dogged::DVec;
+    /// let mut vec = DVec::new();
+    /// for i in 0..100 {
+    ///     vec.push(i);
+    /// }
+    /// let slice = vec.slice(10..20);
+    /// assert_eq!(slice.len(), 10);
+    /// assert_eq!(slice[0], 10);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is decreasing or extends past the end of
+    /// the vector.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> DVec<T, BITS, P, M> {
+        let len = self.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        };
+        if start > end {
+            panic!("slice index starts at {} but ends at {}", start, end);
+        }
+        if end > len {
+            panic!("range end index {} out of range for DVec of length {}", end, len);
+        }
+
+        let mut slice = self.clone();
+        slice.truncate(end);
+        slice.drop_front(start);
+        slice
+    }
+
+    /// Inserts `element` at position `index`, shifting everything after
+    /// it one place to the right. Only O(log n) nodes are rebuilt: the
+    /// vector is split at `index` and put back together around the new
+    /// element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, element: T) {
+        let len = self.len();
+        if index > len {
+            panic!("insertion index (is {}) should be <= len (is {})", index, len);
+        }
+
+        let head_len = self.head.len();
+        if index <= head_len {
+            let head = P::make_mut(&mut self.head);
+            head.insert(index, element);
+            if head.len() == Self::BRANCH_FACTOR {
+                let head = mem::replace(head, Vec::with_capacity(Self::BRANCH_FACTOR));
+                self.push_head(head);
+            }
+            self.validate();
+            return;
+        }
+
+        if index - head_len >= self.root_len.0 {
+            let tail = P::make_mut(&mut self.tail);
+            tail.insert(index - head_len - self.root_len.0, element);
+            if tail.len() == Self::BRANCH_FACTOR {
+                let tail = mem::replace(tail, Vec::with_capacity(Self::BRANCH_FACTOR));
+                self.push_tail(tail);
+            }
+            self.validate();
+            return;
+        }
+
+        let rest = self.split_off(index);
+        self.push(element);
+        self.append(rest);
+    }
+
+    /// Removes and returns the element at position `index`, shifting
+    /// everything after it one place to the left. Like `insert`, this
+    /// rebuilds only O(log n) nodes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        let len = self.len();
+        if index >= len {
+            panic!("removal index (is {}) should be < len (is {})", index, len);
+        }
+
+        let head_len = self.head.len();
+        if index < head_len {
+            let element = P::make_mut(&mut self.head).remove(index);
+            self.validate();
+            return element;
+        }
+
+        if index - head_len >= self.root_len.0 {
+            let element = P::make_mut(&mut self.tail).remove(index - head_len - self.root_len.0);
+            self.validate();
+            return element;
+        }
+
+        let rest = self.split_off(index + 1);
+        let element = self.pop().unwrap();
+        self.append(rest);
+        element
+    }
+
+    /// Removes the first `count` elements, which must not exceed the
+    /// length of the vector.
+    fn drop_front(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        let head_len = self.head.len();
+        if count <= head_len {
+            P::make_mut(&mut self.head).drain(..count);
+            self.validate();
+            return;
+        }
+
+        Self::clear_buffer(&mut self.head);
+        let count = count - head_len;
+        if count >= self.root_len.0 {
+            P::make_mut(&mut self.tail).drain(..count - self.root_len.0);
+            self.root = None;
+            self.shift = Shift(0);
+            self.root_len = Index(0);
+            self.validate();
+            return;
+        }
+
+        Node::<T, BITS, P, M>::make_mut(self.root.as_mut().unwrap()).drop_front(self.shift,
+                                                              self.root_len,
+                                                              Index(count));
+        self.root_len.0 -= count;
+        self.collapse_root();
+
+        self.validate();
+    }
+
+    /// Empties the head or tail, without copying it first if it is
+    /// shared.
+    fn clear_buffer(buffer: &mut P::Pointer<Vec<T>>) {
+        match P::get_mut(buffer) {
+            Some(buffer) => buffer.clear(),
+            None => *buffer = P::new(Vec::with_capacity(Self::BRANCH_FACTOR)),
+        }
+    }
+
+    /// Removes levels from the top of the tree while the root has
+    /// only a single child.
+    fn collapse_root(&mut self) {
+        while self.shift.0 > 0 {
+            let child = match **self.root.as_ref().unwrap() {
+                Node::Branch { ref children, .. } => {
+                    if children.len() > 1 {
+                        return;
+                    }
+                    children[0].clone()
+                }
+                Node::Leaf { .. } => unreachable!(),
+            };
+            self.root = Some(child);
+            self.shift = self.shift.dec(BITS);
         }
     }
 }
 
-impl<T: Clone + Debug> ops::Index<usize> for DVec<T> {
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> ops::Index<usize> for DVec<T, BITS, P, M> {
     type Output = T;
 
     fn index(&self, index: usize) -> &T {
@@ -239,7 +901,7 @@ impl<T: Clone + Debug> ops::Index<usize> for DVec<T> {
     }
 }
 
-impl<T: Clone + Debug> ops::IndexMut<usize> for DVec<T> {
+impl<T: Clone, const BITS: usize, P: SharedPointerKind> ops::IndexMut<usize> for DVec<T, BITS, P> {
     fn index_mut(&mut self, index: usize) -> &mut T {
         let len = self.len();
         self.get_mut(index).unwrap_or_else(|| {
@@ -249,102 +911,472 @@ impl<T: Clone + Debug> ops::IndexMut<usize> for DVec<T> {
     }
 }
 
-impl Index {
-    fn child(self, shift: Shift) -> usize {
-        (self.0 >> shift.0) & (BRANCH_FACTOR - 1)
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Default for DVec<T, BITS, P, M> {
+    fn default() -> Self {
+        DVec {
+            root_len: Index(0),
+            shift: Shift(0),
+            root: None,
+            head: P::new(Vec::new()),
+            tail: P::new(Vec::with_capacity(Self::BRANCH_FACTOR)),
+        }
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> FromIterator<T> for DVec<T, BITS, P, M> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut transient = TransientDVec::default();
+        transient.extend(iter);
+        transient.into_parts().1
     }
-    fn leaf_child(self) -> usize {
-        self.0 & (BRANCH_FACTOR - 1)
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Extend<T> for DVec<T, BITS, P, M> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut transient = mem::take(self).transient();
+        transient.extend(iter);
+        *self = transient.persistent();
+    }
+}
+
+impl<'a, T: Copy, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Extend<&'a T> for DVec<T, BITS, P, M> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().cloned())
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> From<Vec<T>> for DVec<T, BITS, P, M> {
+    fn from(vec: Vec<T>) -> Self {
+        vec.into_iter().collect()
+    }
+}
+
+impl<'a, T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> From<&'a [T]> for DVec<T, BITS, P, M> {
+    fn from(slice: &'a [T]) -> Self {
+        let mut transient = TransientDVec::default();
+        transient.extend_from_slice(slice);
+        transient.persistent()
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Clone for DVec<T, BITS, P, M> {
+    fn clone(&self) -> Self {
+        DVec {
+            root_len: self.root_len,
+            shift: self.shift,
+            root: self.root.clone(),
+            head: self.head.clone(),
+            tail: self.tail.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug, const BITS: usize, P: SharedPointerKind, M: Measure<T>> fmt::Debug for DVec<T, BITS, P, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+// `PartialEq` and `PartialOrd` compare every element, as an element
+// need not equal itself (`f64::NAN` does not), so a subtree the two
+// vectors share proves nothing. `Ord` implies `Eq`, so `cmp` goes
+// through `diff` and skips shared subtrees.
+impl<T: PartialEq, const BITS: usize, P: SharedPointerKind, M: Measure<T>> PartialEq for DVec<T, BITS, P, M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && !self.known_to_differ(other) && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Eq for DVec<T, BITS, P, M> {}
+
+impl<T: PartialOrd, const BITS: usize, P: SharedPointerKind, M: Measure<T>> PartialOrd for DVec<T, BITS, P, M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Ord, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Ord for DVec<T, BITS, P, M> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for item in self.diff(other) {
+            match item {
+                DiffItem::Changed { old, new, .. } => {
+                    match old.cmp(new) {
+                        Ordering::Equal => {}
+                        ordering => return ordering,
+                    }
+                }
+                _ => break,
+            }
+        }
+        self.len().cmp(&other.len())
+    }
+}
+
+impl<T: PartialEq<U>, U, const BITS: usize, P: SharedPointerKind, M: Measure<T>> PartialEq<[U]> for DVec<T, BITS, P, M> {
+    fn eq(&self, other: &[U]) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a == b)
+    }
+}
+
+impl<'a, T: PartialEq<U>, U, const BITS: usize, P: SharedPointerKind, M: Measure<T>> PartialEq<&'a [U]> for DVec<T, BITS, P, M> {
+    fn eq(&self, other: &&'a [U]) -> bool {
+        *self == **other
+    }
+}
+
+impl<T: PartialEq<U>, U, const BITS: usize, P: SharedPointerKind, M: Measure<T>> PartialEq<Vec<U>> for DVec<T, BITS, P, M> {
+    fn eq(&self, other: &Vec<U>) -> bool {
+        *self == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, const N: usize, const BITS: usize, P: SharedPointerKind, M: Measure<T>> PartialEq<[U; N]> for DVec<T, BITS, P, M> {
+    fn eq(&self, other: &[U; N]) -> bool {
+        *self == other[..]
+    }
+}
+
+impl<T: PartialEq<U>, U, const BITS: usize, P: SharedPointerKind, M: Measure<U>> PartialEq<DVec<U, BITS, P, M>> for [T] {
+    fn eq(&self, other: &DVec<U, BITS, P, M>) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a == b)
+    }
+}
+
+impl<T: PartialEq<U>, U, const BITS: usize, P: SharedPointerKind, M: Measure<U>> PartialEq<DVec<U, BITS, P, M>> for Vec<T> {
+    fn eq(&self, other: &DVec<U, BITS, P, M>) -> bool {
+        self[..] == *other
+    }
+}
+
+impl<T: PartialEq<U>, U, const N: usize, const BITS: usize, P: SharedPointerKind, M: Measure<U>> PartialEq<DVec<U, BITS, P, M>> for [T; N] {
+    fn eq(&self, other: &DVec<U, BITS, P, M>) -> bool {
+        self[..] == *other
     }
 }
 
 impl Shift {
-    fn dec(self) -> Shift {
-        Shift(self.0 - BITS_PER_LEVEL)
+    fn dec(self, bits: usize) -> Shift {
+        Shift(self.0 - bits)
+    }
+
+    fn inc(self, bits: usize) -> Shift {
+        Shift(self.0 + bits)
+    }
+
+    /// Number of elements in a full child of a branch at this shift.
+    fn child_capacity(self) -> usize {
+        1 << self.0
+    }
+}
+
+/// For a branch at `shift` with the given size table, returns the
+/// child that holds `index` along with the index relative to that
+/// child.
+#[inline]
+fn position(sizes: &Option<Vec<usize>>, shift: Shift, index: usize) -> (usize, usize) {
+    match *sizes {
+        None => {
+            let child = index >> shift.0;
+            (child, index - (child << shift.0))
+        }
+        Some(ref sizes) => {
+            // Every child holds at most `1 << shift` elements, so the
+            // radix guess can only be too low.
+            let mut child = index >> shift.0;
+            while sizes[child] <= index {
+                child += 1;
+            }
+            let start = if child == 0 { 0 } else { sizes[child - 1] };
+            (child, index - start)
+        }
     }
+}
 
-    fn inc(self) -> Shift {
-        Shift(self.0 + BITS_PER_LEVEL)
+/// For a branch at `shift` holding `len` elements, returns the number
+/// of elements in its first `children` children.
+fn prefix_len(sizes: &Option<Vec<usize>>, shift: Shift, len: usize, children: usize) -> usize {
+    match *sizes {
+        _ if children == 0 => 0,
+        None => cmp::min(children << shift.0, len),
+        Some(ref sizes) => sizes[children - 1],
     }
 }
 
-impl<T: Clone + Debug> Node<T> {
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Clone for Node<T, BITS, P, M> {
+    fn clone(&self) -> Self {
+        match *self {
+            Node::Branch { ref children, ref sizes, ref hash, ref summary } => {
+                Node::Branch {
+                    children: children.clone(),
+                    sizes: sizes.clone(),
+                    hash: hash.clone(),
+                    summary: summary.clone(),
+                }
+            }
+            Node::Leaf { ref elements, ref hash, ref summary } => {
+                Node::Leaf { elements: elements.clone(), hash: hash.clone(), summary: summary.clone() }
+            }
+        }
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Node<T, BITS, P, M> {
+    const BRANCH_FACTOR: usize = 1 << BITS;
+
     #[cfg(test)]
-    pub fn validate(&self, path: &mut Vec<usize>, shift: Shift, len: Index) -> Result<(), String> {
-        // This is called just after a `push_tail`. The tree should be
-        // dense to the left.
+    pub fn validate(&self, path: &mut Vec<usize>, shift: Shift) -> Result<usize, String> {
+        // Returns the number of elements in this subtree.
         match *self {
-            Node::Branch { ref children } => {
+            Node::Branch { ref children, ref sizes, .. } => {
                 if shift.0 == 0 {
                     return Err(format!("encountered branch at path {:?} but shift is {:?}",
                                        path,
                                        shift));
                 }
+                if children.is_empty() || children.len() > Self::BRANCH_FACTOR {
+                    return Err(format!("branch at path {:?} has {} children",
+                                       path,
+                                       children.len()));
+                }
 
-                let mut children_iter = children.iter().enumerate();
-                let mut walked = 0;
-                while walked < len.0 {
-                    if let Some((i, child)) = children_iter.next() {
-                        match *child {
-                            Some(ref c) => {
-                                path.push(i);
-
-                                let max_in_child = BRANCH_FACTOR << (shift.0 - BITS_PER_LEVEL);
-                                let remaining = len.0 - walked;
-                                let child_len = cmp::min(remaining, max_in_child);
-                                if child_len == 0 {
-                                    return Err(format!("at path {:?}, empty child", path));
-                                }
-                                c.validate(path, shift.dec(), Index(child_len))?;
-                                walked += child_len;
-                                assert!(i == path.pop().unwrap());
+                let mut total = 0;
+                for (i, child) in children.iter().enumerate() {
+                    path.push(i);
+                    let child_len = child.validate(path, shift.dec(BITS))?;
+                    total += child_len;
+                    match *sizes {
+                        Some(ref sizes) => {
+                            if sizes.len() != children.len() || sizes[i] != total {
+                                return Err(format!("at path {:?}, size table {:?} is wrong",
+                                                   path,
+                                                   sizes));
                             }
-                            None => {
-                                return Err(format!("at path {:?}, found unexpected none at {}",
+                        }
+                        None => {
+                            if i + 1 < children.len() && child_len != shift.child_capacity() {
+                                return Err(format!("at path {:?}, dense branch has a child \
+                                                    with only {} elements",
                                                    path,
-                                                   i));
+                                                   child_len));
                             }
                         }
-                    } else {
-                        return Err(format!("at path {:?}, iterator ended early", path));
                     }
+                    assert!(i == path.pop().unwrap());
                 }
-
-                if let Some(c) = children_iter.find(|c| c.1.is_some()) {
-                    return Err(format!("node at path {:?} had unexected `some` ({})",
-                                       path,
-                                       c.0));
-                }
+                Ok(total)
             }
 
-            Node::Leaf { ref elements } => {
+            Node::Leaf { ref elements, .. } => {
                 if shift.0 != 0 {
                     return Err(format!("encountered leaf at path {:?} but shift is {:?}",
                                        path,
                                        shift));
                 }
-                if elements.len() != BRANCH_FACTOR {
-                    return Err(format!("encountered leaf at path {:?} with only {} elements",
+                if elements.is_empty() || elements.len() > Self::BRANCH_FACTOR {
+                    return Err(format!("encountered leaf at path {:?} with {} elements",
                                        path,
                                        elements.len()));
                 }
+                Ok(elements.len())
+            }
+        }
+    }
+
+    /// Builds a branch at `shift`, working out whether it is dense or
+    /// needs a size table.
+    fn new_branch(children: Vec<NodeRef<T, BITS, P, M>>, shift: Shift) -> Node<T, BITS, P, M> {
+        let child_shift = shift.dec(BITS);
+        let mut sizes = Vec::with_capacity(children.len());
+        let mut dense = true;
+        let mut total = 0;
+        for (i, child) in children.iter().enumerate() {
+            let len = child.len(child_shift);
+            if i + 1 < children.len() && len != shift.child_capacity() {
+                dense = false;
+            }
+            total += len;
+            sizes.push(total);
+        }
+
+        Node::Branch {
+            summary: combine_all::<T, M, _>(children.iter().map(|child| child.summary())),
+            children,
+            sizes: if dense { None } else { Some(sizes) },
+            hash: HashCache::new(),
+        }
+    }
+
+    /// Builds a branch whose children are all full but the last.
+    fn dense_branch(children: Vec<NodeRef<T, BITS, P, M>>) -> Node<T, BITS, P, M> {
+        Node::Branch {
+            summary: combine_all::<T, M, _>(children.iter().map(|child| child.summary())),
+            children,
+            sizes: None,
+            hash: HashCache::new(),
+        }
+    }
+
+    fn leaf(elements: Vec<T>) -> Node<T, BITS, P, M> {
+        Node::Leaf {
+            summary: measure_all::<T, M>(&elements),
+            elements,
+            hash: HashCache::new(),
+        }
+    }
+
+    fn summary(&self) -> &M::Summary {
+        match *self {
+            Node::Branch { ref summary, .. } | Node::Leaf { ref summary, .. } => summary,
+        }
+    }
+
+    /// Recomputes the summary of this node from its elements or from
+    /// the summaries of its children, which must be up to date.
+    fn resummarize(&mut self) {
+        match *self {
+            Node::Branch { ref children, ref mut summary, .. } => {
+                *summary = combine_all::<T, M, _>(children.iter().map(|child| child.summary()));
+            }
+            Node::Leaf { ref elements, ref mut summary, .. } => {
+                *summary = measure_all::<T, M>(elements);
             }
         }
-        Ok(())
     }
 
-    pub fn branch_ladder(shift: Shift, tail: Vec<T>) -> Arc<Node<T>> {
+    /// Number of elements in this subtree.
+    fn len(&self, shift: Shift) -> usize {
+        match *self {
+            Node::Leaf { ref elements, .. } => elements.len(),
+            Node::Branch { sizes: Some(ref sizes), .. } => *sizes.last().unwrap(),
+            Node::Branch { ref children, .. } => {
+                let last = children.len() - 1;
+                (last << shift.0) + children[last].len(shift.dec(BITS))
+            }
+        }
+    }
+
+    /// Number of elements (for a leaf) or children (for a branch)
+    /// stored directly in this node.
+    fn slots(&self) -> usize {
+        match *self {
+            Node::Leaf { ref elements, .. } => elements.len(),
+            Node::Branch { ref children, .. } => children.len(),
+        }
+    }
+
+    fn children(&self) -> &[NodeRef<T, BITS, P, M>] {
+        match *self {
+            Node::Branch { ref children, .. } => children,
+            Node::Leaf { .. } => unreachable!("leaf has no children"),
+        }
+    }
+
+    fn last_leaf_len(&self) -> usize {
+        let mut p = self;
+        loop {
+            match *p {
+                Node::Branch { ref children, .. } => p = children.last().unwrap(),
+                Node::Leaf { ref elements, .. } => return elements.len(),
+            }
+        }
+    }
+
+    /// True if another leaf can be appended to this subtree without
+    /// adding a level on top of it.
+    fn has_room(&self) -> bool {
+        let mut p = self;
+        loop {
+            match *p {
+                Node::Leaf { .. } => return false,
+                Node::Branch { ref children, .. } => {
+                    if children.len() < Self::BRANCH_FACTOR {
+                        return true;
+                    }
+                    p = children.last().unwrap();
+                }
+            }
+        }
+    }
+
+    /// True if another leaf can be prepended to this subtree without
+    /// adding a level on top of it.
+    fn has_room_front(&self) -> bool {
+        let mut p = self;
+        loop {
+            match *p {
+                Node::Leaf { .. } => return false,
+                Node::Branch { ref children, .. } => {
+                    if children.len() < Self::BRANCH_FACTOR {
+                        return true;
+                    }
+                    p = &children[0];
+                }
+            }
+        }
+    }
+
+    /// True if this subtree consists of exactly one leaf.
+    fn is_single_leaf(&self) -> bool {
+        let mut p = self;
+        loop {
+            match *p {
+                Node::Leaf { .. } => return true,
+                Node::Branch { ref children, .. } => {
+                    if children.len() > 1 {
+                        return false;
+                    }
+                    p = &children[0];
+                }
+            }
+        }
+    }
+
+    pub fn branch_ladder(shift: Shift, leaf: NodeRef<T, BITS, P, M>) -> NodeRef<T, BITS, P, M> {
         if shift.0 > 0 {
-            let mut children = no_children!();
-            children[0] = Some(Node::branch_ladder(shift.dec(), tail));
-            Arc::new(Node::Branch { children: children })
+            P::new(Self::dense_branch(vec![Self::branch_ladder(shift.dec(BITS), leaf)]))
         } else {
-            Arc::new(Node::Leaf { elements: tail })
+            leaf
+        }
+    }
+
+    pub fn get(&self, shift: Shift, index: Index) -> &T {
+        let mut p = self;
+        let mut shift = shift;
+        let mut index = index.0;
+        loop {
+            match *p {
+                Node::Branch { ref children, ref sizes, .. } => {
+                    debug_assert!(shift.0 > 0);
+                    let (child, child_index) = position(sizes, shift, index);
+                    p = &children[child];
+                    index = child_index;
+                    shift = shift.dec(BITS);
+                }
+
+                Node::Leaf { ref elements, .. } => {
+                    debug_assert!(shift.0 == 0);
+                    return &elements[index];
+                }
+            }
         }
     }
+}
 
-    pub fn push_tail(&mut self, shift: Shift, index: Index, tail: Vec<T>) {
-        debug!("push_tail(shift={:?}, index={:?})", shift, index);
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Node<T, BITS, P, M> {
+    /// Like `P::make_mut`, but also forgets the content hash of the
+    /// node, which the caller is about to change.
+    pub fn make_mut(node: &mut NodeRef<T, BITS, P, M>) -> &mut Self {
+        let node = P::make_mut(node);
+        match *node {
+            Node::Branch { ref mut hash, .. } | Node::Leaf { ref mut hash, .. } => hash.clear(),
+        }
+        node
+    }
+
+    /// Appends `leaf` (holding `leaf_len` elements) at the right edge
+    /// of this subtree, which must have room for it.
+    pub fn push_leaf(&mut self, shift: Shift, leaf: NodeRef<T, BITS, P, M>, leaf_len: usize) {
+        debug!("push_leaf(shift={:?}, leaf_len={:?})", shift, leaf_len);
         // Example 1.
         //
         // The vector has 96 elements, 32 of which are in the tail that we
@@ -352,18 +1384,12 @@ impl<T: Clone + Debug> Node<T> {
         //
         // A (shift = 5)
         // |
-        // +- B (Some(Leaf); elements 0..32)
-        // +- C (Some(Leaf); elements 32..64)
-        // +- D (None)
-        // +- ... (None; repeats 29 times)
+        // +- B (Leaf; elements 0..32)
+        // +- C (Leaf; elements 32..64)
         //
-        // We want to replace D with a new `Some(Leaf)`. Our inital
-        // shift will be 5 and our index will be 96 (32*3). Since the
-        // shift is equal to BITS_PER_LEVEL, we know that the
-        // immediate children are leaves, so our iteration is done.
-        // We compute the index of the *start* of the chunk (96-32 ==
-        // 64), which will be 2 -- that is the index where this set of
-        // leaves will go.
+        // Since the shift is equal to BITS, we know that
+        // the immediate children are leaves, so we just add the new
+        // leaf as a third child of A.
         //
         // Example 2.
         //
@@ -379,109 +1405,405 @@ impl<T: Clone + Debug> Node<T> {
         //     +- 001 (Leaf: elements 4..8)
         //     +- 002 (Leaf: elements 8..12)
         //     +- 003 (Leaf: elements 12..16)
-        // +- 01 (None)
         //
-        // We want to expand `01` to a subtree like:
+        // `00` has no room left, so we add a new child `01` to `0`,
+        // constructing multiple levels at once:
         //
-        // +- 01 (None)
+        // +- 01
         //     |
-        //     +- 000 (Leaf: elements 16..20)
-        //     +- ... (None)
-        //
-        // This case is a bit different from the first, because we
-        // encounter a `None` as we are walking down the tree, before
-        // we get to the leaf.
+        //     +- 010 (Leaf: elements 16..20)
+        match *self {
+            Node::Leaf { .. } => {
+                unreachable!("should not encounter a leaf w/ shift {:?}", shift)
+            }
+            Node::Branch { ref mut children, ref mut sizes, .. } => {
+                let child_shift = shift.dec(BITS);
+                if child_shift.0 > 0 && children.last().unwrap().has_room() {
+                    let last = children.last_mut().unwrap();
+                    Self::make_mut(last).push_leaf(child_shift, leaf, leaf_len);
+                    if let Some(ref mut sizes) = *sizes {
+                        *sizes.last_mut().unwrap() += leaf_len;
+                    }
+                } else {
+                    // We are adding a new child. A dense branch only
+                    // stays dense if the child that used to be last is
+                    // full.
+                    if sizes.is_none() &&
+                       children.last().unwrap().len(child_shift) != shift.child_capacity() {
+                        let mut total = 0;
+                        *sizes = Some(children.iter()
+                                              .map(|c| {
+                                                  total += c.len(child_shift);
+                                                  total
+                                              })
+                                              .collect());
+                    }
+                    if let Some(ref mut sizes) = *sizes {
+                        let total = *sizes.last().unwrap() + leaf_len;
+                        sizes.push(total);
+                    }
+                    debug!("creating branch ladder at child {}", children.len());
+                    children.push(Self::branch_ladder(child_shift, leaf));
+                }
+            }
+        }
+        self.resummarize();
+    }
 
-        let mut p = self;
-        let mut shift = shift;
-        loop {
-            debug!("shift={:?}", shift);
-            debug_assert!(shift.0 >= BITS_PER_LEVEL);
-            let q = p; // FIXME
-            match *q {
-                Node::Leaf { .. } => {
-                    unreachable!("should not encounter a leaf w/ shift {:?}", shift)
-                }
-                Node::Branch { ref mut children } => {
-                    let child = index.child(shift);
-                    shift = shift.dec(); // represents the shift of children[child] now
-
-                    if shift.0 == 0 {
-                        // children[child] is the final level; this is example 1
-                        debug_assert!(children[child].is_none());
-                        debug!("Node::push_tail: storing with child={:?}", child);
-                        children[child] = Some(Arc::new(Node::Leaf { elements: tail }));
-                        return;
+    /// Prepends `leaf` (holding `leaf_len` elements) at the left edge
+    /// of this subtree, which must have room for it. This mirrors
+    /// `push_leaf`, but since the new first child is rarely full, the
+    /// branches along the left edge usually end up with size tables.
+    pub fn push_leaf_front(&mut self, shift: Shift, leaf: NodeRef<T, BITS, P, M>, leaf_len: usize) {
+        debug!("push_leaf_front(shift={:?}, leaf_len={:?})", shift, leaf_len);
+        match *self {
+            Node::Leaf { .. } => {
+                unreachable!("should not encounter a leaf w/ shift {:?}", shift)
+            }
+            Node::Branch { ref mut children, ref mut sizes, .. } => {
+                let child_shift = shift.dec(BITS);
+                if child_shift.0 > 0 && children[0].has_room_front() {
+                    // A dense branch whose first child has room has no
+                    // other children, so it stays dense.
+                    Self::make_mut(&mut children[0]).push_leaf_front(child_shift, leaf, leaf_len);
+                    if let Some(ref mut sizes) = *sizes {
+                        for size in sizes.iter_mut() {
+                            *size += leaf_len;
+                        }
                     }
+                } else {
+                    // We are adding a new first child. A dense branch
+                    // only stays dense if both the new child and the
+                    // one that used to be first are full.
+                    if sizes.is_none() &&
+                       (leaf_len != shift.child_capacity() ||
+                        children[0].len(child_shift) != shift.child_capacity()) {
+                        let mut total = 0;
+                        *sizes = Some(children.iter()
+                                              .map(|c| {
+                                                  total += c.len(child_shift);
+                                                  total
+                                              })
+                                              .collect());
+                    }
+                    if let Some(ref mut sizes) = *sizes {
+                        for size in sizes.iter_mut() {
+                            *size += leaf_len;
+                        }
+                        sizes.insert(0, leaf_len);
+                    }
+                    debug!("creating branch ladder at child 0");
+                    children.insert(0, Self::branch_ladder(child_shift, leaf));
+                }
+            }
+        }
+        self.resummarize();
+    }
 
-                    // Load up the child and descend to that level (if
-                    // it is present). If not, we have example 2.
-                    debug!("Node::push_tail: shift={:?} index={:?} child={:?}",
-                           shift,
-                           index,
-                           child);
-                    if children[child].is_some() {
-                        let child = children[child].as_mut().unwrap();
-                        p = Arc::make_mut(child);
-                        continue;
+    /// Removes the last leaf of this subtree and returns it. Branches
+    /// that are left without any children are pruned; the caller is
+    /// responsible for collapsing the root.
+    pub fn pop_leaf(&mut self, shift: Shift) -> NodeRef<T, BITS, P, M> {
+        debug!("Node::pop_leaf(shift={:?})", shift);
+        let leaf = match *self {
+            Node::Leaf { .. } => {
+                unreachable!("should not encounter a leaf w/ shift {:?}", shift)
+            }
+            Node::Branch { ref mut children, ref mut sizes, .. } => {
+                // If the last child holds just one leaf, we can detach
+                // the whole subtree without copying any of it.
+                if children.last().unwrap().is_single_leaf() {
+                    if let Some(ref mut sizes) = *sizes {
+                        sizes.pop();
                     }
+                    let mut p = children.pop().unwrap();
+                    loop {
+                        p = match *p {
+                            Node::Leaf { .. } => break p,
+                            Node::Branch { ref children, .. } => children[0].clone(),
+                        };
+                    }
+                } else {
+                    let last = children.last_mut().unwrap();
+                    let leaf = Self::make_mut(last).pop_leaf(shift.dec(BITS));
+                    if let Some(ref mut sizes) = *sizes {
+                        *sizes.last_mut().unwrap() -= leaf.slots();
+                    }
+                    leaf
+                }
+            }
+        };
+        self.resummarize();
+        leaf
+    }
 
-                    // Example 2: have to construct multiple levels at once.
-                    debug!("creating branch ladder at child {}", child);
-                    children[child] = Some(Node::branch_ladder(shift, tail));
-                    debug!("result: {:?}", children);
-                    return;
+    /// Extracts the elements of a leaf. They are moved out if we hold
+    /// the only reference, and cloned otherwise.
+    pub fn into_elements(leaf: NodeRef<T, BITS, P, M>) -> Vec<T> {
+        match P::try_unwrap(leaf) {
+            Ok(Node::Leaf { elements, .. }) => elements,
+            Err(shared) => {
+                match *shared {
+                    Node::Leaf { ref elements, .. } => elements.clone(),
+                    Node::Branch { .. } => unreachable!("expected a leaf"),
                 }
             }
+            Ok(Node::Branch { .. }) => unreachable!("expected a leaf"),
         }
     }
 
-    pub fn get(&self, shift: Shift, index: Index) -> &T {
-        let mut p = self;
-        let mut shift = shift;
-        loop {
-            match *p {
-                Node::Branch { ref children } => {
-                    debug_assert!(shift.0 > 0);
-                    let child = index.child(shift);
-                    p = match children[child] {
-                        Some(ref c) => &*c,
-                        None => unreachable!(),
-                    };
-                    shift = shift.dec();
+    /// Drops everything in this subtree past the first `new_len`
+    /// elements. Both `old_len` and `new_len` are relative to this
+    /// subtree, and `new_len` must not be zero.
+    pub fn truncate(&mut self, shift: Shift, old_len: Index, new_len: Index) {
+        debug!("Node::truncate(shift={:?}, old_len={:?}, new_len={:?})", shift, old_len, new_len);
+        match *self {
+            Node::Leaf { ref mut elements, .. } => elements.truncate(new_len.0),
+            Node::Branch { ref mut children, ref mut sizes, .. } => {
+                let (last, keep) = position(sizes, shift, new_len.0 - 1);
+                let keep = keep + 1;
+                let child_old = prefix_len(sizes, shift, old_len.0, last + 1) -
+                                prefix_len(sizes, shift, old_len.0, last);
+
+                children.truncate(last + 1);
+                if let Some(ref mut sizes) = *sizes {
+                    sizes.truncate(last + 1);
+                    sizes[last] = new_len.0;
                 }
 
-                Node::Leaf { ref elements } => {
-                    debug_assert!(shift.0 == 0);
-                    debug_assert!(elements.len() == BRANCH_FACTOR);
-                    let child = index.leaf_child();
-                    return &elements[child];
+                // Only descend (and hence copy) if children[last]
+                // actually loses elements.
+                if keep < child_old {
+                    Self::make_mut(&mut children[last]).truncate(shift.dec(BITS),
+                                                                Index(child_old),
+                                                                Index(keep));
+                }
+            }
+        }
+        self.resummarize();
+    }
+
+    /// Drops the first `count` elements of this subtree, which holds
+    /// `old_len` elements. `count` must be less than `old_len`.
+    pub fn drop_front(&mut self, shift: Shift, old_len: Index, count: Index) {
+        debug!("Node::drop_front(shift={:?}, old_len={:?}, count={:?})", shift, old_len, count);
+        match *self {
+            Node::Leaf { ref mut elements, .. } => {
+                elements.drain(..count.0);
+            }
+            Node::Branch { ref mut children, ref mut sizes, .. } => {
+                let (first, skip) = position(sizes, shift, count.0);
+                let child_old = prefix_len(sizes, shift, old_len.0, first + 1) -
+                                prefix_len(sizes, shift, old_len.0, first);
+
+                // Unless we cut exactly at a child boundary, the first
+                // child is no longer full, so we need a size table.
+                if skip > 0 || sizes.is_some() {
+                    let new_sizes = (first..children.len())
+                        .map(|i| prefix_len(sizes, shift, old_len.0, i + 1) - count.0)
+                        .collect();
+                    *sizes = Some(new_sizes);
+                }
+                children.drain(..first);
+
+                if skip > 0 {
+                    Self::make_mut(&mut children[0]).drop_front(shift.dec(BITS),
+                                                               Index(child_old),
+                                                               Index(skip));
                 }
             }
         }
+        self.resummarize();
+    }
+
+    /// Concatenates the trees `left` and `right`, returning the new
+    /// root and its shift. Only nodes along the right edge of `left`
+    /// and the left edge of `right` are rebuilt; everything else is
+    /// shared with the inputs. The new root may have a single child.
+    pub fn concat(left: &NodeRef<T, BITS, P, M>,
+                  left_shift: Shift,
+                  right: &NodeRef<T, BITS, P, M>,
+                  right_shift: Shift)
+                  -> (NodeRef<T, BITS, P, M>, Shift) {
+        let shift = cmp::max(left_shift, right_shift).inc(BITS);
+        let merged = Self::concat_subtrees(left, left_shift, right, right_shift);
+        let mut roots = Self::rebalance(&[], merged, &[], shift);
+        debug_assert!(roots.len() == 1);
+        (roots.pop().unwrap(), shift)
+    }
+
+    /// Merges `left` and `right` into one or two nodes at the larger
+    /// of their two shifts.
+    fn concat_subtrees(left: &NodeRef<T, BITS, P, M>,
+                       left_shift: Shift,
+                       right: &NodeRef<T, BITS, P, M>,
+                       right_shift: Shift)
+                       -> Vec<NodeRef<T, BITS, P, M>> {
+        debug!("concat_subtrees(left_shift={:?}, right_shift={:?})", left_shift, right_shift);
+        if left_shift > right_shift {
+            let children = left.children();
+            let (last, init) = children.split_last().unwrap();
+            let merged = Self::concat_subtrees(last, left_shift.dec(BITS), right, right_shift);
+            Self::rebalance(init, merged, &[], left_shift)
+        } else if left_shift < right_shift {
+            let children = right.children();
+            let (first, rest) = children.split_first().unwrap();
+            let merged = Self::concat_subtrees(left, left_shift, first, right_shift.dec(BITS));
+            Self::rebalance(&[], merged, rest, right_shift)
+        } else if left_shift.0 == 0 {
+            // Two leaves; our caller will pack them if need be.
+            vec![left.clone(), right.clone()]
+        } else {
+            let (last, init) = left.children().split_last().unwrap();
+            let (first, rest) = right.children().split_first().unwrap();
+            let merged = Self::concat_subtrees(last, left_shift.dec(BITS), first, right_shift.dec(BITS));
+            Self::rebalance(init, merged, rest, left_shift)
+        }
     }
 
+    /// Takes the children `left ++ middle ++ right` (all at
+    /// `shift.dec(BITS)`), packs them so there are at most
+    /// `EXTRA_SEARCH_STEPS` more than needed, and returns one or two
+    /// nodes at `shift` holding them.
+    fn rebalance(left: &[NodeRef<T, BITS, P, M>],
+                 middle: Vec<NodeRef<T, BITS, P, M>>,
+                 right: &[NodeRef<T, BITS, P, M>],
+                 shift: Shift)
+                 -> Vec<NodeRef<T, BITS, P, M>> {
+        let mut nodes = Vec::with_capacity(left.len() + middle.len() + right.len());
+        nodes.extend(left.iter().cloned());
+        nodes.extend(middle);
+        nodes.extend(right.iter().cloned());
+
+        let plan = Self::concat_plan(&nodes);
+        let mut nodes = Self::execute_plan(nodes, &plan, shift.dec(BITS));
+
+        let mut result = Vec::with_capacity(2);
+        while nodes.len() > Self::BRANCH_FACTOR {
+            let rest = nodes.split_off(Self::BRANCH_FACTOR);
+            result.push(P::new(Self::new_branch(nodes, shift)));
+            nodes = rest;
+        }
+        result.push(P::new(Self::new_branch(nodes, shift)));
+        result
+    }
+
+    /// Computes how many slots each of `nodes` should hold after
+    /// rebalancing. Nodes that are full, or nearly so, are left alone;
+    /// sparse nodes get their slots spread over the nodes following
+    /// them until the search step invariant holds.
+    fn concat_plan(nodes: &[NodeRef<T, BITS, P, M>]) -> Vec<usize> {
+        let mut plan: Vec<usize> = nodes.iter().map(|n| n.slots()).collect();
+        let total: usize = plan.iter().sum();
+        let optimal = total.div_ceil(Self::BRANCH_FACTOR);
+
+        while plan.len() > optimal + EXTRA_SEARCH_STEPS {
+            let mut i = 0;
+            while plan[i] >= Self::BRANCH_FACTOR - EXTRA_SEARCH_STEPS / 2 {
+                i += 1;
+            }
+
+            // Spread the slots of node `i` over the nodes after it,
+            // until some node absorbs all that remains.
+            let mut remaining = plan[i];
+            while remaining > 0 {
+                let size = cmp::min(remaining + plan[i + 1], Self::BRANCH_FACTOR);
+                remaining = remaining + plan[i + 1] - size;
+                plan[i] = size;
+                i += 1;
+            }
+            plan.remove(i);
+        }
+
+        plan
+    }
+
+    /// Builds the nodes described by `plan` out of the slots of
+    /// `nodes`, which live at `shift`. Nodes that the plan leaves as
+    /// they were are reused as is.
+    fn execute_plan(nodes: Vec<NodeRef<T, BITS, P, M>>, plan: &[usize], shift: Shift) -> Vec<NodeRef<T, BITS, P, M>> {
+        let mut result = Vec::with_capacity(plan.len());
+        let mut index = 0;
+        let mut offset = 0;
+        for &size in plan {
+            if offset == 0 && nodes[index].slots() == size {
+                result.push(nodes[index].clone());
+                index += 1;
+                continue;
+            }
+
+            let node = if shift.0 == 0 {
+                let mut elements = Vec::with_capacity(size);
+                while elements.len() < size {
+                    let source = match *nodes[index] {
+                        Node::Leaf { ref elements, .. } => elements,
+                        Node::Branch { .. } => unreachable!(),
+                    };
+                    let count = cmp::min(size - elements.len(), source.len() - offset);
+                    elements.extend_from_slice(&source[offset..offset + count]);
+                    offset += count;
+                    if offset == source.len() {
+                        index += 1;
+                        offset = 0;
+                    }
+                }
+                Node::leaf(elements)
+            } else {
+                let mut children = Vec::with_capacity(size);
+                while children.len() < size {
+                    let source = nodes[index].children();
+                    let count = cmp::min(size - children.len(), source.len() - offset);
+                    children.extend_from_slice(&source[offset..offset + count]);
+                    offset += count;
+                    if offset == source.len() {
+                        index += 1;
+                        offset = 0;
+                    }
+                }
+                Self::new_branch(children, shift)
+            };
+            result.push(P::new(node));
+        }
+        debug_assert!(index == nodes.len());
+        result
+    }
+
+    /// Calls `f` on the element at `index` of this subtree, and then
+    /// recomputes the summaries on the path to it.
+    pub fn update<R, F>(&mut self, shift: Shift, index: Index, f: F) -> R
+        where F: FnOnce(&mut T) -> R
+    {
+        let result = match *self {
+            Node::Branch { ref mut children, ref sizes, .. } => {
+                let (child, child_index) = position(sizes, shift, index.0);
+                Self::make_mut(&mut children[child]).update(shift.dec(BITS), Index(child_index), f)
+            }
+            Node::Leaf { ref mut elements, .. } => f(&mut elements[index.0]),
+        };
+        self.resummarize();
+        result
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind> Node<T, BITS, P, ()> {
     pub fn get_mut(&mut self, shift: Shift, index: Index) -> &mut T {
         let mut p = self;
         let mut shift = shift;
+        let mut index = index.0;
         loop {
             let q = p; // FIXME
             match *q {
-                Node::Branch { ref mut children } => {
+                Node::Branch { ref mut children, ref sizes, .. } => {
                     debug_assert!(shift.0 > 0);
-                    let child = index.child(shift);
-                    p = match children[child] {
-                        Some(ref mut c) => Arc::make_mut(c),
-                        None => unreachable!(),
-                    };
-                    shift = shift.dec();
+                    let (child, child_index) = position(sizes, shift, index);
+                    p = Self::make_mut(&mut children[child]);
+                    index = child_index;
+                    shift = shift.dec(BITS);
                 }
 
-                Node::Leaf { ref mut elements } => {
+                Node::Leaf { ref mut elements, .. } => {
                     debug_assert!(shift.0 == 0);
-                    debug_assert!(elements.len() == BRANCH_FACTOR);
-                    let child = index.leaf_child();
-                    return &mut elements[child];
+                    return &mut elements[index];
                 }
             }
         }
@@ -512,14 +1834,5 @@ impl PartialOrd<usize> for Shift {
     }
 }
 
-impl<T: Clone> Clone for Node<T> {
-    fn clone(&self) -> Self {
-        match *self {
-            Node::Branch { ref children } => Node::Branch { children: clone_arr!(children) },
-            Node::Leaf { ref elements } => Node::Leaf { elements: elements.clone() },
-        }
-    }
-}
-
 #[cfg(test)]
 mod test;
@@ -0,0 +1,604 @@
+//! A read-only `DVec` that is used straight from a memory-mapped file.
+//!
+//! `DVec::write_image` writes a vector of `Plain` elements to a file
+//! in the layout of its tree, and `MappedDVec::open` maps that file
+//! into memory. Lookups and iteration then walk the tree in place, so
+//! opening even a very large vector costs nothing more than checking
+//! its header, and only the pages that are actually read get loaded.
+//!
+//! ```rust
+//! # use dogged::DVec;
+//! # use dogged::mapped::MappedDVec;
+//! # use std::fs::File;
+//! # let path = std::env::temp_dir().join(format!("dogged-doc-{}", std::process::id()));
+//! let vec: DVec<u64> = (0..100_000).map(|i| i * i).collect();
+//! vec.write_image(File::create(&path).unwrap()).unwrap();
+//!
+//! // Safe as long as nothing changes the file while it is mapped.
+//! let mapped: MappedDVec<u64> = unsafe { MappedDVec::open(&path).unwrap() };
+//! assert_eq!(mapped.len(), 100_000);
+//! assert_eq!(mapped[300], 90_000);
+//! assert!(mapped.iter().eq(vec.iter()));
+//! # std::fs::remove_file(&path).unwrap();
+//! ```
+//!
+//! An image is only meant to be read on the kind of machine that wrote
+//! it: everything is in native byte order, and the elements are copied
+//! byte for byte. It starts with a header of the magic bytes
+//! `DOGGEDMV`, a format version (currently 1) and the `BITS` of the
+//! vector as `u32`s, followed by `u64` words: a byte order mark, the
+//! size and alignment of an element, the offset of the root node, the
+//! number of elements below it and its shift, and the offsets and
+//! lengths of the head and tail. A leaf is a word holding the number
+//! of its elements, followed by the elements themselves. A branch is a
+//! word holding the number of its children, a word that is 1 if it has
+//! a size table, the offsets of its children, and then the size table
+//! if there is one. Every node starts at a multiple of 8, and elements
+//! are aligned as their type requires.
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::iter::FusedIterator;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops;
+use std::path::Path;
+use std::slice;
+
+use memmap2::Mmap;
+
+use super::{DVec, Node, NodeRef, SharedPointerKind};
+
+const MAGIC: &[u8; 8] = b"DOGGEDMV";
+const VERSION: u32 = 1;
+const BYTE_ORDER: u64 = 0x0102_0304_0506_0708;
+
+// The header is the magic bytes, the version and `BITS`, and then the
+// words listed here.
+const HEADER_LEN: usize = 16 + 8 * 10;
+const WORD_BYTE_ORDER: usize = 16;
+const WORD_SIZE: usize = 24;
+const WORD_ALIGN: usize = 32;
+const WORD_ROOT: usize = 40;
+const WORD_ROOT_LEN: usize = 48;
+const WORD_SHIFT: usize = 56;
+const WORD_HEAD: usize = 64;
+const WORD_HEAD_LEN: usize = 72;
+const WORD_TAIL: usize = 80;
+const WORD_TAIL_LEN: usize = 88;
+
+/// Types whose values can be copied to a file byte for byte, and read
+/// back straight from the bytes of a mapped file.
+///
+/// # Safety
+///
+/// An implementing type must have no padding bytes, every pattern of
+/// bits must be a valid value of it, and it must not hold pointers or
+/// references. This is the case for the primitive numeric types and
+/// arrays of them, and for `#[repr(C)]` structs made of those fields
+/// that happen to leave no room for padding.
+pub unsafe trait Plain: Copy + 'static {}
+
+macro_rules! plain {
+    ($($t: ty),*) => {
+        $(unsafe impl Plain for $t {})*
+    }
+}
+
+plain!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+
+/// Why `MappedDVec::open` turned a file down. Only the header is
+/// looked at then; damage to the nodes shows up later, as a panic in
+/// the lookup that reaches it.
+#[derive(Debug)]
+pub enum ImageError {
+    /// Opening the file or mapping it into memory failed.
+    Io(io::Error),
+    /// The file is not an image at all: it does not start with
+    /// `DOGGEDMV`.
+    BadMagic,
+    /// The header gives a format version other than 1.
+    UnsupportedVersion(u32),
+    /// The image is fine, but it does not suit the `MappedDVec` it is
+    /// opened as: the element size or alignment, `BITS` or the byte
+    /// order differs from what it was written with.
+    Mismatch(String),
+    /// The file is shorter than the offsets in its header imply.
+    Truncated,
+    /// A header word is out of range, such as a head or tail of a full
+    /// leaf or more, a shift that is not a multiple of `BITS`, or a
+    /// root offset that points outside the file.
+    Corrupt(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref err) => write!(f, "I/O error: {}", err),
+            ImageError::BadMagic => write!(f, "not a vector image"),
+            ImageError::UnsupportedVersion(version) => {
+                write!(f, "unsupported image version {}", version)
+            }
+            ImageError::Mismatch(ref message) => write!(f, "image does not match: {}", message),
+            ImageError::Truncated => write!(f, "image is truncated"),
+            ImageError::Corrupt(ref message) => write!(f, "image is corrupt: {}", message),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ImageError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        ImageError::Io(err)
+    }
+}
+
+/// Runs of elements start at a multiple of this.
+fn alignment<T>() -> usize {
+    cmp::max(8, mem::align_of::<T>())
+}
+
+fn align_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+fn as_bytes<T: Plain>(elements: &[T]) -> &[u8] {
+    // `Plain` types have no padding, so all of their bytes are
+    // initialized.
+    unsafe { slice::from_raw_parts(elements.as_ptr() as *const u8, mem::size_of_val(elements)) }
+}
+
+/// The `len` values of type `U` at `offset` in `bytes`, if they are in
+/// bounds and properly aligned.
+fn slice_at<U: Plain>(bytes: &[u8], offset: usize, len: usize) -> Option<&[U]> {
+    let end = len.checked_mul(mem::size_of::<U>())?.checked_add(offset)?;
+    if end > bytes.len() || !(bytes.as_ptr() as usize).wrapping_add(offset).is_multiple_of(mem::align_of::<U>()) {
+        return None;
+    }
+    // In bounds and aligned, and any bytes make a valid `U`.
+    Some(unsafe { slice::from_raw_parts(bytes.as_ptr().add(offset) as *const U, len) })
+}
+
+fn word_at(bytes: &[u8], offset: usize) -> Option<usize> {
+    slice_at::<u64>(bytes, offset, 1).map(|word| word[0] as usize)
+}
+
+/// Writes nodes one after the other, keeping track of their offsets
+/// from the start of the image.
+struct ImageWriter<W: Write> {
+    out: W,
+    position: usize,
+}
+
+impl<W: Write> ImageWriter<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len();
+        Ok(())
+    }
+
+    fn write_word(&mut self, word: usize) -> io::Result<()> {
+        self.write_bytes(&(word as u64).to_ne_bytes())
+    }
+
+    fn pad(&mut self, align: usize) -> io::Result<()> {
+        let padding = align_up(self.position, align) - self.position;
+        self.write_bytes(&vec![0; padding])
+    }
+
+    /// Writes a run of elements and returns its offset.
+    fn write_elements<T: Plain>(&mut self, elements: &[T]) -> io::Result<usize> {
+        self.pad(alignment::<T>())?;
+        let offset = self.position;
+        self.write_bytes(as_bytes(elements))?;
+        Ok(offset)
+    }
+
+    /// Writes the children of `node` and then `node` itself, and
+    /// returns its offset.
+    fn write_node<T: Plain, const BITS: usize, P: SharedPointerKind>(&mut self,
+                                                                    node: &NodeRef<T, BITS, P>)
+                                                                    -> io::Result<usize> {
+        match **node {
+            Node::Leaf { ref elements, .. } => {
+                self.pad(8)?;
+                let offset = self.position;
+                self.write_word(elements.len())?;
+                self.write_elements(elements)?;
+                Ok(offset)
+            }
+            Node::Branch { ref children, ref sizes, .. } => {
+                let offsets = children.iter()
+                                      .map(|child| self.write_node::<T, BITS, P>(child))
+                                      .collect::<io::Result<Vec<_>>>()?;
+                self.pad(8)?;
+                let offset = self.position;
+                self.write_word(children.len())?;
+                self.write_word(sizes.is_some() as usize)?;
+                for child in offsets {
+                    self.write_word(child)?;
+                }
+                for &size in sizes.iter().flatten() {
+                    self.write_word(size)?;
+                }
+                Ok(offset)
+            }
+        }
+    }
+}
+
+impl<T: Plain, const BITS: usize, P: SharedPointerKind> DVec<T, BITS, P> {
+    /// Writes the vector to `out` as an image that `MappedDVec::open`
+    /// can map. The image must be at the start of the file it is read
+    /// from, so `out` should usually be a freshly created `File`,
+    /// perhaps wrapped in a `BufWriter`.
+    pub fn write_image<W: Write + Seek>(&self, out: W) -> io::Result<()> {
+        let mut writer = ImageWriter { out, position: 0 };
+        let start = writer.out.stream_position()?;
+        // The header goes first but refers to everything else, so it
+        // is filled in at the end.
+        writer.write_bytes(&[0; HEADER_LEN])?;
+        let root = match self.root {
+            Some(ref root) => writer.write_node::<T, BITS, P>(root)?,
+            None => 0,
+        };
+        let head = writer.write_elements(&self.head)?;
+        let tail = writer.write_elements(&self.tail)?;
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_ne_bytes());
+        header.extend_from_slice(&(BITS as u32).to_ne_bytes());
+        let words = [BYTE_ORDER as usize,
+                     mem::size_of::<T>(),
+                     mem::align_of::<T>(),
+                     root,
+                     self.root_len.0,
+                     self.shift.0,
+                     head,
+                     self.head.len(),
+                     tail,
+                     self.tail.len()];
+        for &word in &words {
+            header.extend_from_slice(&(word as u64).to_ne_bytes());
+        }
+
+        let ImageWriter { mut out, position } = writer;
+        out.seek(SeekFrom::Start(start))?;
+        out.write_all(&header)?;
+        out.seek(SeekFrom::Start(start + position as u64))?;
+        out.flush()
+    }
+}
+
+/// The bytes an image is read from.
+enum Image {
+    Mapped(Mmap),
+    /// Words holding a copy of the image, and its length in bytes.
+    #[cfg(test)]
+    Owned(Vec<u64>, usize),
+}
+
+impl ops::Deref for Image {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Image::Mapped(ref map) => map,
+            #[cfg(test)]
+            Image::Owned(ref words, len) => &as_bytes(words)[..len],
+        }
+    }
+}
+
+/// A read-only vector whose tree lives in a memory-mapped image
+/// written by `DVec::write_image`. It has the same `get`, `len` and
+/// iteration as a `DVec`, without reading the image into memory first.
+///
+/// `open` only checks the header of the image. The nodes are checked
+/// as they are reached, and a corrupt node makes the lookup that
+/// reached it panic; it never makes it read outside of the image.
+pub struct MappedDVec<T: Plain, const BITS: usize = 5> {
+    image: Image,
+    root: [u64; 1],
+    root_len: usize,
+    shift: usize,
+    head: (usize, usize),
+    tail: (usize, usize),
+    marker: PhantomData<T>,
+}
+
+impl<T: Plain, const BITS: usize> MappedDVec<T, BITS> {
+    const BRANCH_FACTOR: usize = 1 << BITS;
+
+    /// Maps the image at `path` into memory and checks its header.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed, by this process or any other,
+    /// for as long as the vector is alive, since its elements are read
+    /// straight from the file's pages.
+    pub unsafe fn open<Q: AsRef<Path>>(path: Q) -> Result<Self, ImageError> {
+        let file = File::open(path)?;
+        Self::from_image(Image::Mapped(Mmap::map(&file)?))
+    }
+
+    /// Reads an image from a copy of `bytes` rather than a mapped file,
+    /// so that the tests can run where `mmap` is not available.
+    #[cfg(test)]
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        // Copy into words, so that the image is as aligned as a mapping.
+        let mut words = vec![0u64; bytes.len().div_ceil(8)];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+            let mut buf = [0; 8];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_ne_bytes(buf);
+        }
+        Self::from_image(Image::Owned(words, bytes.len()))
+    }
+
+    fn from_image(image: Image) -> Result<Self, ImageError> {
+        if image.len() < MAGIC.len() || image[..MAGIC.len()] != MAGIC[..] {
+            return Err(ImageError::BadMagic);
+        }
+        if image.len() < HEADER_LEN {
+            return Err(ImageError::Truncated);
+        }
+        let word = |offset| word_at(&image, offset).unwrap();
+        if word(WORD_BYTE_ORDER) != BYTE_ORDER as usize {
+            return Err(ImageError::Mismatch("written on a machine with another byte order".to_string()));
+        }
+        let version = slice_at::<u32>(&image, 8, 2).unwrap();
+        if version[0] != VERSION {
+            return Err(ImageError::UnsupportedVersion(version[0]));
+        }
+        if version[1] as usize != BITS {
+            return Err(ImageError::Mismatch(format!("written with BITS = {}, but read with {}", version[1], BITS)));
+        }
+        if word(WORD_SIZE) != mem::size_of::<T>() || word(WORD_ALIGN) != mem::align_of::<T>() {
+            return Err(ImageError::Mismatch(format!("elements have size {} and alignment {}, \
+                                                     but are read as size {} and alignment {}",
+                                                    word(WORD_SIZE),
+                                                    word(WORD_ALIGN),
+                                                    mem::size_of::<T>(),
+                                                    mem::align_of::<T>())));
+        }
+
+        let (head, head_len) = (word(WORD_HEAD), word(WORD_HEAD_LEN));
+        let (tail, tail_len) = (word(WORD_TAIL), word(WORD_TAIL_LEN));
+        for &(what, len) in &[("head", head_len), ("tail", tail_len)] {
+            if len >= Self::BRANCH_FACTOR {
+                return Err(ImageError::Corrupt(format!("{} has {} elements", what, len)));
+            }
+        }
+        // The tail is written last, so it ends where the image does.
+        let end = tail.checked_add(tail_len * mem::size_of::<T>());
+        if end.is_none_or(|end| end > image.len()) {
+            return Err(ImageError::Truncated);
+        }
+        if slice_at::<T>(&image, head, head_len).is_none() || slice_at::<T>(&image, tail, tail_len).is_none() {
+            return Err(ImageError::Corrupt("head or tail is out of place".to_string()));
+        }
+
+        let (root, root_len, shift) = (word(WORD_ROOT), word(WORD_ROOT_LEN), word(WORD_SHIFT));
+        if root_len > 0 {
+            if !shift.is_multiple_of(BITS) || shift + BITS >= usize::BITS as usize {
+                return Err(ImageError::Corrupt(format!("shift {} is not valid", shift)));
+            }
+            if word_at(&image, root).is_none() {
+                return Err(ImageError::Corrupt("root is out of place".to_string()));
+            }
+        }
+        if head_len.checked_add(root_len).and_then(|len| len.checked_add(tail_len)).is_none() {
+            return Err(ImageError::Corrupt(format!("root holds {} elements", root_len)));
+        }
+
+        Ok(MappedDVec {
+            image,
+            root: [root as u64],
+            root_len,
+            shift,
+            head: (head, head_len),
+            tail: (tail, tail_len),
+            marker: PhantomData,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.head.1 + self.root_len + self.tail.1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let head = self.slice(self.head.0, self.head.1);
+        if index < head.len() {
+            return Some(&head[index]);
+        }
+
+        let mut index = index - head.len();
+        if index >= self.root_len {
+            return self.slice(self.tail.0, self.tail.1).get(index - self.root_len);
+        }
+        let mut node = self.root[0] as usize;
+        let mut shift = self.shift;
+        while shift > 0 {
+            let children = self.children(node);
+            // The same search as `position`, on the size table stored
+            // after the children.
+            let mut child = index >> shift;
+            let mut start = child << shift;
+            if self.word(node + 8) != 0 {
+                let sizes: &[u64] = self.slice(node + 16 + 8 * children.len(), children.len());
+                while child < sizes.len() && sizes[child] as usize <= index {
+                    child += 1;
+                }
+                start = if child == 0 { 0 } else { sizes[child - 1] as usize };
+            }
+            if child >= children.len() || start > index {
+                self.corrupt(node);
+            }
+            node = children[child] as usize;
+            index -= start;
+            shift -= BITS;
+        }
+        match self.leaf(node).get(index) {
+            Some(element) => Some(element),
+            None => self.corrupt(node),
+        }
+    }
+
+    /// Returns an iterator over the elements of the vector.
+    pub fn iter(&self) -> Iter<'_, T, BITS> {
+        let roots = if self.root_len > 0 { &self.root[..] } else { &[] };
+        Iter {
+            vec: self,
+            stack: vec![(roots.iter(), self.shift)],
+            leaf: self.slice(self.head.0, self.head.1).iter(),
+            tail: self.slice(self.tail.0, self.tail.1),
+            remaining: self.len(),
+        }
+    }
+
+    fn word(&self, offset: usize) -> usize {
+        word_at(&self.image, offset).unwrap_or_else(|| self.corrupt(offset))
+    }
+
+    fn slice<U: Plain>(&self, offset: usize, len: usize) -> &[U] {
+        slice_at(&self.image, offset, len).unwrap_or_else(|| self.corrupt(offset))
+    }
+
+    /// The offsets of the children of the branch at `offset`.
+    fn children(&self, offset: usize) -> &[u64] {
+        let len = self.word(offset);
+        if len == 0 || len > Self::BRANCH_FACTOR {
+            self.corrupt(offset);
+        }
+        self.slice(offset + 16, len)
+    }
+
+    /// The elements of the leaf at `offset`.
+    fn leaf(&self, offset: usize) -> &[T] {
+        let len = self.word(offset);
+        if len == 0 || len > Self::BRANCH_FACTOR {
+            self.corrupt(offset);
+        }
+        self.slice(align_up(offset + 8, alignment::<T>()), len)
+    }
+
+    #[cold]
+    fn corrupt(&self, offset: usize) -> ! {
+        panic!("MappedDVec image is corrupt at offset {}", offset)
+    }
+}
+
+impl<T: Plain, const BITS: usize> ops::Index<usize> for MappedDVec<T, BITS> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        self.get(index).unwrap_or_else(|| {
+            panic!("index `{}` out of bounds in MappedDVec of length `{}`",
+                   index, self.len())
+        })
+    }
+}
+
+impl<T: Plain + fmt::Debug, const BITS: usize> fmt::Debug for MappedDVec<T, BITS> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over the elements of a `MappedDVec`, created by
+/// `MappedDVec::iter`. It follows the child offsets stored in the
+/// branches and reads every leaf once, rather than descending from the
+/// root for each element.
+pub struct Iter<'a, T: Plain, const BITS: usize = 5> {
+    vec: &'a MappedDVec<T, BITS>,
+    // The image offsets read out of the branches being walked, one
+    // run per branch, each with the shift of the nodes it points to.
+    // Nothing behind an offset is checked until it is popped.
+    stack: Vec<(slice::Iter<'a, u64>, usize)>,
+    // The rest of the leaf being read, borrowed from the image. The
+    // head comes first, as it is not reachable from the root.
+    leaf: slice::Iter<'a, T>,
+    tail: &'a [T],
+    // How many elements the header promises are still to come. The
+    // leaves have to add up to it, or the image is corrupt.
+    remaining: usize,
+}
+
+impl<'a, T: Plain, const BITS: usize> Iterator for Iter<'a, T, BITS> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(element) = self.leaf.next() {
+                if self.remaining == 0 {
+                    self.vec.corrupt(self.vec.root[0] as usize);
+                }
+                self.remaining -= 1;
+                return Some(element);
+            }
+            let (node, shift) = match self.stack.last_mut() {
+                Some(&mut (ref mut nodes, shift)) => match nodes.next() {
+                    Some(&node) => (node as usize, shift),
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                },
+                None if self.tail.is_empty() => {
+                    if self.remaining != 0 {
+                        self.vec.corrupt(self.vec.root[0] as usize);
+                    }
+                    return None;
+                }
+                None => {
+                    self.leaf = mem::take(&mut self.tail).iter();
+                    continue;
+                }
+            };
+            if shift == 0 {
+                self.leaf = self.vec.leaf(node).iter();
+            } else {
+                self.stack.push((self.vec.children(node).iter(), shift - BITS));
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T: Plain, const BITS: usize> ExactSizeIterator for Iter<'a, T, BITS> {}
+
+impl<'a, T: Plain, const BITS: usize> FusedIterator for Iter<'a, T, BITS> {}
+
+impl<'a, T: Plain, const BITS: usize> IntoIterator for &'a MappedDVec<T, BITS> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, BITS>;
+
+    fn into_iter(self) -> Iter<'a, T, BITS> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,185 @@
+use std::cmp;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{BufWriter, Cursor};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::{ImageError, MappedDVec, Plain, HEADER_LEN, WORD_BYTE_ORDER, WORD_ROOT};
+use test::damage;
+use {ArcK, DVec, RcK, SharedPointerKind};
+
+fn image<T: Plain, const BITS: usize, P: SharedPointerKind>(vec: &DVec<T, BITS, P>) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    vec.write_image(&mut out).unwrap();
+    out.into_inner()
+}
+
+/// A file in the temporary directory that is removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        TempFile(::std::env::temp_dir().join(format!("dogged-{}-{}", process::id(), name)))
+    }
+
+    fn write<T: Plain, const BITS: usize, P: SharedPointerKind>(name: &str, vec: &DVec<T, BITS, P>) -> TempFile {
+        let file = TempFile::new(name);
+        vec.write_image(BufWriter::new(File::create(&file.0).unwrap())).unwrap();
+        file
+    }
+
+    fn open<T: Plain, const BITS: usize>(&self) -> Result<MappedDVec<T, BITS>, ImageError> {
+        unsafe { MappedDVec::open(&self.0) }
+    }
+
+    fn update<F: FnOnce(&mut Vec<u8>)>(&self, f: F) {
+        let mut bytes = fs::read(&self.0).unwrap();
+        f(&mut bytes);
+        fs::write(&self.0, bytes).unwrap();
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn check<T, const BITS: usize, P>(vec: &DVec<T, BITS, P>)
+    where T: Plain + PartialEq + Debug, P: SharedPointerKind
+{
+    let mapped = MappedDVec::<T, BITS>::from_bytes(&image(vec)).unwrap();
+    assert_eq!(mapped.len(), vec.len());
+    for i in 0..vec.len() {
+        assert_eq!(mapped.get(i), vec.get(i));
+    }
+    assert_eq!(mapped.get(vec.len()), None);
+    assert_eq!(mapped.iter().len(), vec.len());
+    assert!(mapped.iter().eq(vec.iter()));
+    assert_eq!(format!("{:?}", mapped), format!("{:?}", vec));
+}
+
+fn matches_dvec<const BITS: usize, P: SharedPointerKind>() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    let mut vec = DVec::<u32, BITS, P>::default();
+    check(&vec);
+    for _ in 0..20 {
+        match rng.gen_range(0, 3) {
+            0 => {
+                let other: DVec<u32, BITS, P> = (0..rng.gen_range(0, 500)).collect();
+                vec.append(other);
+            }
+            1 => {
+                for _ in 0..rng.gen_range(0, 40) {
+                    vec.push_front(rng.gen());
+                }
+            }
+            _ => vec.extend((0..rng.gen_range(0, 1000)).map(|_| rng.gen::<u32>())),
+        }
+        check(&vec);
+    }
+}
+
+test_instances!(matches_dvec {
+    arc => [5, ArcK],
+    rc => [5, RcK],
+    narrow => [2, ArcK],
+});
+
+#[test]
+fn element_types() {
+    check(&(0..1000).map(|i| i as u8).collect::<DVec<u8, 3>>());
+    check(&(0..1000).map(|i| (i as u128) << 100).collect::<DVec<u128, 3>>());
+    check(&(0..1000).map(|i| i as f64 / 3.0).collect::<DVec<f64>>());
+    check(&(0..1000).map(|i| [i, i + 1, i + 2]).collect::<DVec<[u16; 3], 4>>());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn open_maps_the_file() {
+    let vec: DVec<u32, 3> = (0..1000).collect();
+    let file = TempFile::write("open", &vec);
+    let mapped: MappedDVec<u32, 3> = file.open().unwrap();
+    assert!(mapped.iter().eq(vec.iter()));
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn bad_header() {
+    let vec: DVec<u32> = (0..1000).collect();
+    let file = TempFile::write("bad-header", &vec);
+    let open = |file: &TempFile| file.open::<u32, 5>().err().unwrap();
+
+    assert!(matches!(file.open::<u64, 5>(), Err(ImageError::Mismatch(_))));
+    assert!(matches!(file.open::<u32, 4>(), Err(ImageError::Mismatch(_))));
+
+    let missing = TempFile::new("missing");
+    assert!(matches!(open(&missing), ImageError::Io(_)));
+
+    let empty = TempFile::new("empty");
+    File::create(&empty.0).unwrap();
+    assert!(matches!(open(&empty), ImageError::BadMagic));
+
+    file.update(|bytes| bytes.truncate(bytes.len() - 1));
+    assert!(matches!(open(&file), ImageError::Truncated));
+    file.update(|bytes| bytes.truncate(HEADER_LEN - 1));
+    assert!(matches!(open(&file), ImageError::Truncated));
+
+    let file = TempFile::write("bad-header", &vec);
+    file.update(|bytes| bytes[8..12].copy_from_slice(&7u32.to_ne_bytes()));
+    assert!(matches!(open(&file), ImageError::UnsupportedVersion(7)));
+    file.update(|bytes| bytes[WORD_BYTE_ORDER..WORD_BYTE_ORDER + 8].reverse());
+    assert!(matches!(open(&file), ImageError::Mismatch(_)));
+    file.update(|bytes| bytes[0] = b'X');
+    assert!(matches!(open(&file), ImageError::BadMagic));
+}
+
+#[test]
+#[should_panic(expected = "corrupt")]
+fn corrupt_node_panics() {
+    let vec: DVec<u32> = (0..1000).collect();
+    let mut bytes = image(&vec);
+    let mut root = [0; 8];
+    root.copy_from_slice(&bytes[WORD_ROOT..WORD_ROOT + 8]);
+    let root = u64::from_ne_bytes(root) as usize;
+    bytes[root..root + 8].copy_from_slice(&1000u64.to_ne_bytes());
+    let mapped = MappedDVec::<u32>::from_bytes(&bytes).unwrap();
+    mapped.get(500);
+}
+
+#[test]
+fn damaged_images_only_give_elements_of_the_image() {
+    let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+    let mut vec: DVec<u32, 2> = (0..300).collect();
+    vec.append((0..77).collect());
+    let bytes = image(&vec);
+
+    for _ in 0..if cfg!(miri) { 20 } else { 1000 } {
+        let mapped = match MappedDVec::<u32, 2>::from_bytes(&damage(&mut rng, &bytes)) {
+            Ok(mapped) => mapped,
+            Err(_) => continue,
+        };
+        // Lookups may panic on a corrupt node, but whatever they give
+        // back must be a whole, aligned element inside the image. A
+        // damaged shift can make the tree look far bigger than the
+        // image, so only look at as many elements as there were.
+        let check = |element: &u32| {
+            let offset = (element as *const u32 as usize).wrapping_sub(mapped.image.as_ptr() as usize);
+            assert!(offset.is_multiple_of(4) && offset + 4 <= mapped.image.len(), "offset {}", offset);
+            assert_eq!(mapped.image[offset..offset + 4], element.to_ne_bytes());
+        };
+        let len = cmp::min(mapped.len(), vec.len() + 10);
+        let mut elements = Vec::new();
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| elements.extend(mapped.iter().take(len))));
+        elements.iter().for_each(|&element| check(element));
+        for i in 0..len {
+            if let Ok(Some(element)) = panic::catch_unwind(|| mapped.get(i)) {
+                check(element);
+            }
+        }
+    }
+}
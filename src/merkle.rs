@@ -0,0 +1,232 @@
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "cache-hashes")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{DVec, Measure, Node, Shift, SharedPointerKind};
+
+/// A node's content hash is a polynomial in `BASE` whose coefficients
+/// are the hashes of its elements, modulo the Mersenne prime 2^61 - 1.
+/// Unlike a hash of the children's hashes, it only depends on the
+/// elements and not on how the tree happens to be split into nodes, so
+/// vectors built by `push` and by `append` hash alike. It can still be
+/// put together from the hashes of the children: for `left` followed
+/// by `right`, it is `hash(left) * BASE^len(right) + hash(right)`.
+const MODULUS: u64 = (1 << 61) - 1;
+const BASE: u64 = 0x1f2e_3d4c_5b6a_7988;
+
+/// The content hash of a node, once it has been computed. It is
+/// stored plus one, so that zero means it has not been computed since
+/// the node was last changed.
+///
+/// Without the `cache-hashes` feature this is empty, so that nodes
+/// are no bigger and updates no slower for vectors that are never
+/// hashed; `content_hash` then hashes every element each time.
+pub struct HashCache {
+    #[cfg(feature = "cache-hashes")]
+    hash: AtomicU64,
+}
+
+#[cfg(feature = "cache-hashes")]
+impl HashCache {
+    pub fn new() -> HashCache {
+        HashCache { hash: AtomicU64::new(0) }
+    }
+
+    fn get(&self) -> Option<u64> {
+        match self.hash.load(Ordering::Relaxed) {
+            0 => None,
+            hash => Some(hash - 1),
+        }
+    }
+
+    fn set(&self, hash: u64) {
+        self.hash.store(hash + 1, Ordering::Relaxed);
+    }
+
+    pub fn clear(&mut self) {
+        *self.hash.get_mut() = 0;
+    }
+}
+
+#[cfg(not(feature = "cache-hashes"))]
+impl HashCache {
+    pub fn new() -> HashCache {
+        HashCache {}
+    }
+
+    fn get(&self) -> Option<u64> {
+        None
+    }
+
+    fn set(&self, _hash: u64) {}
+
+    pub fn clear(&mut self) {}
+}
+
+impl Clone for HashCache {
+    #[cfg(feature = "cache-hashes")]
+    fn clone(&self) -> Self {
+        HashCache { hash: AtomicU64::new(self.hash.load(Ordering::Relaxed)) }
+    }
+
+    #[cfg(not(feature = "cache-hashes"))]
+    fn clone(&self) -> Self {
+        HashCache {}
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` gives the same result in
+/// every process, so content hashes can be compared across them.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn mul(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    add(product as u64 & MODULUS, (product >> 61) as u64)
+}
+
+fn add(a: u64, b: u64) -> u64 {
+    let sum = a + b;
+    if sum >= MODULUS { sum - MODULUS } else { sum }
+}
+
+/// `BASE^exponent`, the factor by which the hash of a run of elements
+/// is shifted when `exponent` more elements follow it.
+fn power(mut exponent: usize) -> u64 {
+    let (mut result, mut base) = (1, BASE);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+fn hash_elements<T: Hash>(elements: &[T]) -> u64 {
+    elements.iter().fold(0, |acc, element| {
+        let mut hasher = Fnv::new();
+        element.hash(&mut hasher);
+        add(mul(acc, BASE), hasher.finish() % MODULUS)
+    })
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Node<T, BITS, P, M> {
+    fn hash_cache(&self) -> &HashCache {
+        match *self {
+            Node::Branch { ref hash, .. } | Node::Leaf { ref hash, .. } => hash,
+        }
+    }
+}
+
+impl<T: Hash, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Node<T, BITS, P, M> {
+    /// Returns the content hash of this subtree, computing it (and
+    /// those of the subtrees below it) if it is not cached.
+    fn content_hash(&self, shift: Shift) -> u64 {
+        if let Some(hash) = self.hash_cache().get() {
+            return hash;
+        }
+
+        let hash = match *self {
+            Node::Leaf { ref elements, .. } => hash_elements(elements),
+            Node::Branch { ref children, ref sizes, .. } => {
+                let full = power(shift.child_capacity());
+                let last = children.len() - 1;
+                children.iter().enumerate().fold(0, |acc, (i, child)| {
+                    let shift_by = match *sizes {
+                        Some(ref sizes) if i == 0 => power(sizes[0]),
+                        Some(ref sizes) => power(sizes[i] - sizes[i - 1]),
+                        None if i < last => full,
+                        None => power(child.len(shift.dec(BITS))),
+                    };
+                    add(mul(acc, shift_by), child.content_hash(shift.dec(BITS)))
+                })
+            }
+        };
+        self.hash_cache().set(hash);
+        hash
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// True if the cached content hashes show that the two vectors
+    /// differ, which saves comparing their elements. This is only ever
+    /// known with the `cache-hashes` feature, when `content_hash` has
+    /// been called on both, and their trees hold the same range of
+    /// indices.
+    pub(crate) fn known_to_differ(&self, other: &DVec<T, BITS, P, M>) -> bool {
+        if self.head.len() != other.head.len() || self.root_len != other.root_len {
+            return false;
+        }
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => {
+                matches!((a.hash_cache().get(), b.hash_cache().get()), (Some(a), Some(b)) if a != b)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<T: Hash, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// Returns a hash of the elements of the vector, which is the same
+    /// for equal vectors no matter how they were built, and (on a given
+    /// platform) from one process to the next, so it can serve as the
+    /// key of a snapshot.
+    ///
+    /// With the `cache-hashes` feature, every node of the tree caches
+    /// the hash of its elements, so after the first call this takes
+    /// O(1) time, and after an update only the nodes on the path to the
+    /// change are hashed again. Otherwise it takes O(n) time.
+    ///
+    /// ```rust
+    /// # use dogged::DVec;
+    /// let mut vec: DVec<u32> = (0..100_000).collect();
+    /// let hash = vec.content_hash();
+    /// vec[500] = 0;
+    /// assert_ne!(vec.content_hash(), hash);
+    /// vec[500] = 500;
+    /// assert_eq!(vec.content_hash(), hash);
+    ///
+    /// // Appending gives a differently shaped tree, but the same hash.
+    /// let mut appended: DVec<u32> = (0..50_000).collect();
+    /// appended.append((50_000..100_000).collect());
+    /// assert_eq!(appended.content_hash(), hash);
+    /// ```
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = hash_elements(&self.head);
+        if let Some(ref root) = self.root {
+            hash = add(mul(hash, power(self.root_len.0)), root.content_hash(self.shift));
+        }
+        hash = add(mul(hash, power(self.tail.len())), hash_elements(&self.tail));
+
+        let mut hasher = Fnv::new();
+        hasher.write_u64(hash);
+        hasher.write_u64(self.len() as u64);
+        hasher.finish()
+    }
+}
+
+impl<T: Hash, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Hash for DVec<T, BITS, P, M> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.content_hash());
+    }
+}
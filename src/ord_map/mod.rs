@@ -0,0 +1,834 @@
+//! A persistent ordered map, `DOrdMap`, stored as a B-tree.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::{FromIterator, FusedIterator};
+use std::mem;
+use std::ops::{self, Bound, RangeBounds};
+use std::vec;
+
+use super::{ArcK, SharedPointerKind};
+
+/// Every node but the root holds between `MIN_ENTRIES` and
+/// `MAX_ENTRIES` entries.
+const MIN_ENTRIES: usize = 5;
+const MAX_ENTRIES: usize = 2 * MIN_ENTRIES + 1;
+
+/// A **persistent** ordered map. Like `DVec`, it is cheap to clone: a
+/// clone shares the whole tree with the original, and updating either
+/// copies only the O(log n) nodes on the path to the updated key.
+///
+/// ```rust
+/// # use dogged::DOrdMap;
+/// let mut map1 = DOrdMap::new();
+/// map1.insert(3, "c");
+/// map1.insert(1, "a");
+/// let mut map2 = map1.clone();
+/// map2.insert(2, "b");
+/// assert_eq!(map1.len(), 2);
+/// assert_eq!(map2.values().cloned().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+/// ```
+pub struct DOrdMap<K, V, P: SharedPointerKind = ArcK> {
+    root: NodeRef<K, V, P>,
+}
+
+/// A node of the B-tree. Internal nodes have one more child than they
+/// have entries; leaves have no children, and are all at the same
+/// depth.
+struct Node<K, V, P: SharedPointerKind> {
+    entries: Vec<(K, V)>,
+    children: Vec<NodeRef<K, V, P>>,
+    size: usize, // number of entries in this subtree
+}
+
+type NodeRef<K, V, P> = <P as SharedPointerKind>::Pointer<Node<K, V, P>>;
+
+/// A subtree together with its height, for `split_off`.
+type Tree<K, V, P> = (NodeRef<K, V, P>, usize);
+
+impl<K, V> DOrdMap<K, V> {
+    pub fn new() -> Self {
+        DOrdMap::default()
+    }
+}
+
+impl<K, V, P: SharedPointerKind> DOrdMap<K, V, P> {
+    pub fn len(&self) -> usize {
+        self.root.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an iterator over the entries of the map, in order of
+    /// their keys.
+    pub fn iter(&self) -> Iter<'_, K, V, P> {
+        let mut front = vec![];
+        let mut back = vec![];
+        Node::<K, V, P>::seek(&self.root, &mut front, |_| 0);
+        Node::<K, V, P>::seek(&self.root, &mut back, |node| node.entries.len());
+        Iter { front, back, len: self.len() }
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, P> {
+        Keys { iter: self.iter() }
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, P> {
+        Values { iter: self.iter() }
+    }
+
+    /// Returns the entry with the smallest key.
+    pub fn first(&self) -> Option<(&K, &V)> {
+        let mut node = &*self.root;
+        while let Some(child) = node.children.first() {
+            node = child;
+        }
+        node.entries.first().map(|entry| (&entry.0, &entry.1))
+    }
+
+    /// Returns the entry with the largest key.
+    pub fn last(&self) -> Option<(&K, &V)> {
+        let mut node = &*self.root;
+        while let Some(child) = node.children.last() {
+            node = child;
+        }
+        node.entries.last().map(|entry| (&entry.0, &entry.1))
+    }
+
+    /// Returns true if the two maps share their whole tree, which
+    /// implies that they are equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        P::ptr_eq(&self.root, &other.root)
+    }
+
+    /// Checks the invariants of the tree.
+    #[cfg(test)]
+    pub(crate) fn assert_valid(&self) where K: Ord {
+        if let Err(err) = self.root.validate(None, None, true) {
+            panic!("validation error {}", err);
+        }
+    }
+}
+
+impl<K: Ord, V, P: SharedPointerKind> DOrdMap<K, V, P> {
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&V>
+        where K: Borrow<Q>
+    {
+        self.get_key_value(key).map(|(_, value)| value)
+    }
+
+    pub fn get_key_value<Q: ?Sized + Ord>(&self, key: &Q) -> Option<(&K, &V)>
+        where K: Borrow<Q>
+    {
+        let mut node = &*self.root;
+        loop {
+            match node.search(key) {
+                Ok(i) => return Some((&node.entries[i].0, &node.entries[i].1)),
+                Err(_) if node.is_leaf() => return None,
+                Err(i) => node = &node.children[i],
+            }
+        }
+    }
+
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool
+        where K: Borrow<Q>
+    {
+        self.get_key_value(key).is_some()
+    }
+
+    /// Returns an iterator over the entries whose keys are in `range`,
+    /// in order of their keys.
+    ///
+    /// ```rust
+    /// # use dogged::DOrdMap;
+    /// let map: DOrdMap<u32, u32> = (0..100).map(|i| (i, i * i)).collect();
+    /// let squares: Vec<_> = map.range(3..6).map(|(_, &v)| v).collect();
+    /// assert_eq!(squares, vec![9, 16, 25]);
+    /// ```
+    pub fn range<Q, R>(&self, range: R) -> Iter<'_, K, V, P>
+        where K: Borrow<Q>, Q: ?Sized + Ord, R: RangeBounds<Q>
+    {
+        let mut front = vec![];
+        let mut back = vec![];
+        let start = Node::<K, V, P>::seek(&self.root, &mut front, |node| match range.start_bound() {
+            Bound::Included(key) => node.entries.partition_point(|e| e.0.borrow() < key),
+            Bound::Excluded(key) => node.entries.partition_point(|e| e.0.borrow() <= key),
+            Bound::Unbounded => 0,
+        });
+        let end = Node::<K, V, P>::seek(&self.root, &mut back, |node| match range.end_bound() {
+            Bound::Included(key) => node.entries.partition_point(|e| e.0.borrow() <= key),
+            Bound::Excluded(key) => node.entries.partition_point(|e| e.0.borrow() < key),
+            Bound::Unbounded => node.entries.len(),
+        });
+        Iter { front, back, len: end.saturating_sub(start) }
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, P: SharedPointerKind> DOrdMap<K, V, P> {
+    /// Inserts `value` under `key`, returning the value it replaces,
+    /// if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = P::make_mut(&mut self.root).insert(key, value);
+        if self.root.entries.len() > MAX_ENTRIES {
+            Node::<K, V, P>::split_root(&mut self.root);
+        }
+        old
+    }
+
+    /// Removes `key` from the map, returning its value if it was
+    /// present. Nodes are left shared if `key` is not in the map.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<V>
+        where K: Borrow<Q>
+    {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    pub fn remove_entry<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<(K, V)>
+        where K: Borrow<Q>
+    {
+        self.get_key_value(key)?;
+        let entry = P::make_mut(&mut self.root).remove(key);
+        Node::<K, V, P>::shrink_root(&mut self.root);
+        entry
+    }
+
+    pub fn get_mut<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<&mut V>
+        where K: Borrow<Q>
+    {
+        self.get_key_value(key)?;
+        let mut p = P::make_mut(&mut self.root);
+        loop {
+            let q = p; // FIXME
+            match q.search(key) {
+                Ok(i) => return Some(&mut q.entries[i].1),
+                Err(i) => p = P::make_mut(&mut q.children[i]),
+            }
+        }
+    }
+
+    /// Splits the map in two at `key`: `self` keeps the entries whose
+    /// keys are less than `key`, and the others are returned. Subtrees
+    /// away from the path to `key` are shared with the original map.
+    ///
+    /// ```rust
+    /// # use dogged::DOrdMap;
+    /// let mut map: DOrdMap<u32, ()> = (0..100).map(|i| (i, ())).collect();
+    /// let rest = map.split_off(&40);
+    /// assert_eq!(map.len(), 40);
+    /// assert_eq!(rest.first(), Some((&40, &())));
+    /// ```
+    pub fn split_off<Q: ?Sized + Ord>(&mut self, key: &Q) -> Self
+        where K: Borrow<Q>
+    {
+        let height = self.root.height();
+        let ((left, _), (right, _)) = Node::<K, V, P>::split_tree(&self.root, height, key);
+        self.root = left;
+        DOrdMap { root: right }
+    }
+}
+
+impl<K, V, P: SharedPointerKind> Node<K, V, P> {
+    fn from_parts(entries: Vec<(K, V)>, children: Vec<NodeRef<K, V, P>>) -> Self {
+        let size = entries.len() + children.iter().map(|child| child.size).sum::<usize>();
+        Node { entries, children, size }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn height(&self) -> usize {
+        let mut node = self;
+        let mut height = 0;
+        while let Some(child) = node.children.first() {
+            node = child;
+            height += 1;
+        }
+        height
+    }
+
+    fn search<Q: ?Sized + Ord>(&self, key: &Q) -> Result<usize, usize>
+        where K: Borrow<Q>
+    {
+        self.entries.binary_search_by(|entry| entry.0.borrow().cmp(key))
+    }
+
+    /// Walks down from `root`, taking the child at the position `pick`
+    /// returns in each node, and pushes each node and position onto
+    /// `stack`. Returns the number of entries before the position
+    /// reached.
+    fn seek<'a, F>(root: &'a NodeRef<K, V, P>, stack: &mut Vec<(&'a Self, usize)>, pick: F) -> usize
+        where F: Fn(&Self) -> usize
+    {
+        let mut node = &**root;
+        let mut rank = 0;
+        loop {
+            let i = pick(node);
+            stack.push((node, i));
+            rank += i;
+            if node.is_leaf() {
+                return rank;
+            }
+            rank += node.children[..i].iter().map(|child| child.size).sum::<usize>();
+            node = &node.children[i];
+        }
+    }
+
+    #[cfg(test)]
+    fn validate(&self, lower: Option<&K>, upper: Option<&K>, is_root: bool) -> Result<usize, String>
+        where K: Ord
+    {
+        // Returns the height of this subtree.
+        if self.entries.len() > MAX_ENTRIES || (!is_root && self.entries.len() < MIN_ENTRIES) {
+            return Err(format!("node with {} entries", self.entries.len()));
+        }
+        if !self.is_leaf() && self.children.len() != self.entries.len() + 1 {
+            return Err(format!("{} children for {} entries", self.children.len(), self.entries.len()));
+        }
+        let keys: Vec<_> = lower.into_iter()
+                                .chain(self.entries.iter().map(|entry| &entry.0))
+                                .chain(upper)
+                                .collect();
+        if keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("keys out of order".to_string());
+        }
+
+        let mut height = None;
+        for (i, child) in self.children.iter().enumerate() {
+            let lower = if i == 0 { lower } else { Some(&self.entries[i - 1].0) };
+            let upper = self.entries.get(i).map(|entry| &entry.0).or(upper);
+            let child_height = child.validate(lower, upper, false)?;
+            if height.is_some_and(|height| height != child_height) {
+                return Err("leaves at different depths".to_string());
+            }
+            height = Some(child_height);
+        }
+
+        let size = self.entries.len() + self.children.iter().map(|child| child.size).sum::<usize>();
+        if size != self.size {
+            return Err(format!("size {} for {} entries", self.size, size));
+        }
+        Ok(height.map_or(0, |height| height + 1))
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, P: SharedPointerKind> Node<K, V, P> {
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let i = match self.search(&key) {
+            Ok(i) => return Some(mem::replace(&mut self.entries[i].1, value)),
+            Err(i) => i,
+        };
+        if self.is_leaf() {
+            self.entries.insert(i, (key, value));
+            self.size += 1;
+            return None;
+        }
+
+        let child = P::make_mut(&mut self.children[i]);
+        let old = child.insert(key, value);
+        if old.is_none() {
+            self.size += 1;
+            if child.entries.len() > MAX_ENTRIES {
+                let (median, right) = child.split();
+                self.entries.insert(i, median);
+                self.children.insert(i + 1, P::new(right));
+            }
+        }
+        old
+    }
+
+    /// Removes `key`, which must be present.
+    fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<(K, V)>
+        where K: Borrow<Q>
+    {
+        let entry = match self.search(key) {
+            Ok(i) if self.is_leaf() => self.entries.remove(i),
+            Ok(i) => {
+                // Take the entry's place with the one just before it.
+                let previous = P::make_mut(&mut self.children[i]).remove_last();
+                let entry = mem::replace(&mut self.entries[i], previous);
+                self.fix_child(i);
+                entry
+            }
+            Err(_) if self.is_leaf() => return None,
+            Err(i) => {
+                let entry = P::make_mut(&mut self.children[i]).remove(key)?;
+                self.fix_child(i);
+                entry
+            }
+        };
+        self.size -= 1;
+        Some(entry)
+    }
+
+    fn remove_last(&mut self) -> (K, V) {
+        self.size -= 1;
+        if self.is_leaf() {
+            return self.entries.pop().unwrap();
+        }
+        let last = self.children.len() - 1;
+        let entry = P::make_mut(&mut self.children[last]).remove_last();
+        self.fix_child(last);
+        entry
+    }
+
+    /// Splits an overfull node in two around its median entry.
+    fn split(&mut self) -> ((K, V), Self) {
+        let middle = self.entries.len() / 2;
+        let right_entries = self.entries.split_off(middle + 1);
+        let median = self.entries.pop().unwrap();
+        let right_children = if self.is_leaf() { vec![] } else { self.children.split_off(middle + 1) };
+        let right = Node::from_parts(right_entries, right_children);
+        self.size -= 1 + right.size;
+        (median, right)
+    }
+
+    /// Tops up the child at `i` if it has too few entries, by merging
+    /// it with a sibling or moving entries over from one.
+    fn fix_child(&mut self, i: usize) {
+        if self.children[i].entries.len() < MIN_ENTRIES {
+            self.merge_children(if i > 0 { i - 1 } else { i });
+        }
+    }
+
+    /// Merges the children at `i` and `i + 1` and the entry between
+    /// them, and then splits the result again if it is overfull.
+    fn merge_children(&mut self, i: usize) {
+        let right = P::unwrap_or_clone(self.children.remove(i + 1));
+        let separator = self.entries.remove(i);
+        let left = P::make_mut(&mut self.children[i]);
+        left.entries.push(separator);
+        left.entries.extend(right.entries);
+        left.children.extend(right.children);
+        left.size += 1 + right.size;
+        if left.entries.len() > MAX_ENTRIES {
+            let (median, right) = left.split();
+            self.entries.insert(i, median);
+            self.children.insert(i + 1, P::new(right));
+        }
+    }
+
+    fn split_root(root: &mut NodeRef<K, V, P>) {
+        let (median, right) = P::make_mut(root).split();
+        let right = P::new(right);
+        let size = root.size + 1 + right.size;
+        let left = mem::replace(root, P::new(Node { entries: vec![median], children: vec![right], size }));
+        P::make_mut(root).children.insert(0, left);
+    }
+
+    fn shrink_root(root: &mut NodeRef<K, V, P>) {
+        while root.entries.is_empty() && !root.is_leaf() {
+            *root = root.children[0].clone();
+        }
+    }
+
+    fn insert_into((mut root, height): Tree<K, V, P>, key: K, value: V) -> Tree<K, V, P> {
+        P::make_mut(&mut root).insert(key, value);
+        if root.entries.len() > MAX_ENTRIES {
+            Self::split_root(&mut root);
+            return (root, height + 1);
+        }
+        (root, height)
+    }
+
+    /// A tree made of the given entries and children, which may not be
+    /// enough to fill a node.
+    fn tree(entries: &[(K, V)], children: &[NodeRef<K, V, P>], height: usize) -> Tree<K, V, P> {
+        if entries.is_empty() {
+            (children[0].clone(), height - 1)
+        } else {
+            (P::new(Node::from_parts(entries.to_vec(), children.to_vec())), height)
+        }
+    }
+
+    /// Splits the tree at `node` into the entries with keys less than
+    /// `key` and the rest.
+    fn split_tree<Q: ?Sized + Ord>(node: &NodeRef<K, V, P>, height: usize, key: &Q) -> (Tree<K, V, P>, Tree<K, V, P>)
+        where K: Borrow<Q>
+    {
+        let i = node.entries.partition_point(|entry| entry.0.borrow() < key);
+        if node.is_leaf() {
+            let left = Node::from_parts(node.entries[..i].to_vec(), vec![]);
+            let right = Node::from_parts(node.entries[i..].to_vec(), vec![]);
+            return ((P::new(left), 0), (P::new(right), 0));
+        }
+
+        let (left, right) = Self::split_tree(&node.children[i], height - 1, key);
+        let left = if i == 0 {
+            left
+        } else {
+            let rest = Self::tree(&node.entries[..i - 1], &node.children[..i], height);
+            Self::join(rest, node.entries[i - 1].clone(), left)
+        };
+        let right = if i == node.entries.len() {
+            right
+        } else {
+            let rest = Self::tree(&node.entries[i + 1..], &node.children[i + 1..], height);
+            Self::join(right, node.entries[i].clone(), rest)
+        };
+        (left, right)
+    }
+
+    /// Joins two trees and an entry whose key lies between theirs.
+    fn join(left: Tree<K, V, P>, (key, value): (K, V), right: Tree<K, V, P>) -> Tree<K, V, P> {
+        let ((mut left, left_height), (mut right, right_height)) = (left, right);
+        if left.size == 0 {
+            return Self::insert_into((right, right_height), key, value);
+        }
+        if right.size == 0 {
+            return Self::insert_into((left, left_height), key, value);
+        }
+
+        match left_height.cmp(&right_height) {
+            Ordering::Equal => {
+                let mut root = Self::from_parts(vec![(key, value)], vec![left, right]);
+                if root.children.iter().any(|child| child.entries.len() < MIN_ENTRIES) {
+                    root.merge_children(0);
+                }
+                let mut root = P::new(root);
+                Self::shrink_root(&mut root);
+                let height = root.height();
+                (root, height)
+            }
+            Ordering::Greater => {
+                if P::make_mut(&mut left).join_right(left_height, (key, value), right, right_height) {
+                    Self::split_root(&mut left);
+                    return (left, left_height + 1);
+                }
+                (left, left_height)
+            }
+            Ordering::Less => {
+                if P::make_mut(&mut right).join_left(right_height, left, (key, value), left_height) {
+                    Self::split_root(&mut right);
+                    return (right, right_height + 1);
+                }
+                (right, right_height)
+            }
+        }
+    }
+
+    /// Adds `entry` and the lower tree `right` at the end of this node,
+    /// which is at `height`. Returns true if this node is now overfull.
+    fn join_right(&mut self, height: usize, entry: (K, V), right: NodeRef<K, V, P>, right_height: usize) -> bool {
+        self.size += 1 + right.size;
+        let last = self.children.len() - 1;
+        if height == right_height + 1 {
+            self.entries.push(entry);
+            self.children.push(right);
+            self.fix_child(last + 1);
+        } else {
+            let child = P::make_mut(&mut self.children[last]);
+            if child.join_right(height - 1, entry, right, right_height) {
+                let (median, right) = child.split();
+                self.entries.push(median);
+                self.children.push(P::new(right));
+            }
+        }
+        self.entries.len() > MAX_ENTRIES
+    }
+
+    /// Adds the lower tree `left` and `entry` at the start of this
+    /// node, which is at `height`. Returns true if this node is now
+    /// overfull.
+    fn join_left(&mut self, height: usize, left: NodeRef<K, V, P>, entry: (K, V), left_height: usize) -> bool {
+        self.size += 1 + left.size;
+        if height == left_height + 1 {
+            self.entries.insert(0, entry);
+            self.children.insert(0, left);
+            self.fix_child(0);
+        } else {
+            let child = P::make_mut(&mut self.children[0]);
+            if child.join_left(height - 1, left, entry, left_height) {
+                let (median, right) = child.split();
+                self.entries.insert(0, median);
+                self.children.insert(1, P::new(right));
+            }
+        }
+        self.entries.len() > MAX_ENTRIES
+    }
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> Clone for Node<K, V, P> {
+    fn clone(&self) -> Self {
+        Node {
+            entries: self.entries.clone(),
+            children: self.children.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl<K, V, P: SharedPointerKind> Clone for DOrdMap<K, V, P> {
+    fn clone(&self) -> Self {
+        DOrdMap { root: self.root.clone() }
+    }
+}
+
+impl<K, V, P: SharedPointerKind> Default for DOrdMap<K, V, P> {
+    fn default() -> Self {
+        DOrdMap { root: P::new(Node::from_parts(vec![], vec![])) }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, P: SharedPointerKind> fmt::Debug for DOrdMap<K, V, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: PartialEq, V: PartialEq, P: SharedPointerKind> PartialEq for DOrdMap<K, V, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Eq, V: Eq, P: SharedPointerKind> Eq for DOrdMap<K, V, P> {}
+
+impl<K: PartialOrd, V: PartialOrd, P: SharedPointerKind> PartialOrd for DOrdMap<K, V, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<K: Ord, V: Ord, P: SharedPointerKind> Ord for DOrdMap<K, V, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<K, Q, V, P> ops::Index<&Q> for DOrdMap<K, V, P>
+    where K: Ord + Borrow<Q>, Q: ?Sized + Ord, P: SharedPointerKind
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("key not found in DOrdMap")
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, P: SharedPointerKind> FromIterator<(K, V)> for DOrdMap<K, V, P> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = DOrdMap::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Ord + Clone, V: Clone, P: SharedPointerKind> Extend<(K, V)> for DOrdMap<K, V, P> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+/// An iterator over the entries of a `DOrdMap`, in order of their
+/// keys, created by `DOrdMap::iter` and `DOrdMap::range`.
+pub struct Iter<'a, K: 'a, V: 'a, P: SharedPointerKind = ArcK> {
+    // The path to the next entry from each end. On the front path,
+    // `(node, i)` means that `node.entries[i..]` are still to come,
+    // after whatever is left of `node.children[i]`; on the back path,
+    // that `node.entries[..i]` are.
+    front: Vec<(&'a Node<K, V, P>, usize)>,
+    back: Vec<(&'a Node<K, V, P>, usize)>,
+    len: usize,
+}
+
+impl<'a, K, V, P: SharedPointerKind> Iterator for Iter<'a, K, V, P> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.len == 0 {
+            return None;
+        }
+        loop {
+            let (node, i) = *self.front.last().unwrap();
+            if i == node.entries.len() {
+                self.front.pop();
+                continue;
+            }
+            self.front.last_mut().unwrap().1 += 1;
+            if !node.is_leaf() {
+                Node::<K, V, P>::seek(&node.children[i + 1], &mut self.front, |_| 0);
+            }
+            self.len -= 1;
+            return Some((&node.entries[i].0, &node.entries[i].1));
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> DoubleEndedIterator for Iter<'a, K, V, P> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.len == 0 {
+            return None;
+        }
+        loop {
+            let (node, i) = *self.back.last().unwrap();
+            if i == 0 {
+                self.back.pop();
+                continue;
+            }
+            self.back.last_mut().unwrap().1 -= 1;
+            if !node.is_leaf() {
+                Node::<K, V, P>::seek(&node.children[i - 1], &mut self.back, |node| node.entries.len());
+            }
+            self.len -= 1;
+            return Some((&node.entries[i - 1].0, &node.entries[i - 1].1));
+        }
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> ExactSizeIterator for Iter<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> FusedIterator for Iter<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> Clone for Iter<'a, K, V, P> {
+    fn clone(&self) -> Self {
+        Iter {
+            front: self.front.clone(),
+            back: self.back.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> IntoIterator for &'a DOrdMap<K, V, P> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, P>;
+
+    fn into_iter(self) -> Iter<'a, K, V, P> {
+        self.iter()
+    }
+}
+
+/// An iterator over the keys of a `DOrdMap`, in order.
+pub struct Keys<'a, K: 'a, V: 'a, P: SharedPointerKind = ArcK> {
+    iter: Iter<'a, K, V, P>,
+}
+
+impl<'a, K, V, P: SharedPointerKind> Iterator for Keys<'a, K, V, P> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<&'a K> {
+        self.iter.next().map(|(key, _)| key)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> DoubleEndedIterator for Keys<'a, K, V, P> {
+    fn next_back(&mut self) -> Option<&'a K> {
+        self.iter.next_back().map(|(key, _)| key)
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> ExactSizeIterator for Keys<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> FusedIterator for Keys<'a, K, V, P> {}
+
+/// An iterator over the values of a `DOrdMap`, in order of their keys.
+pub struct Values<'a, K: 'a, V: 'a, P: SharedPointerKind = ArcK> {
+    iter: Iter<'a, K, V, P>,
+}
+
+impl<'a, K, V, P: SharedPointerKind> Iterator for Values<'a, K, V, P> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<&'a V> {
+        self.iter.next().map(|(_, value)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> DoubleEndedIterator for Values<'a, K, V, P> {
+    fn next_back(&mut self) -> Option<&'a V> {
+        self.iter.next_back().map(|(_, value)| value)
+    }
+}
+
+impl<'a, K, V, P: SharedPointerKind> ExactSizeIterator for Values<'a, K, V, P> {}
+
+impl<'a, K, V, P: SharedPointerKind> FusedIterator for Values<'a, K, V, P> {}
+
+/// An owning iterator over the entries of a `DOrdMap`, in order of
+/// their keys. Nodes that no other map refers to are taken apart;
+/// shared ones are cloned.
+pub struct IntoIter<K, V, P: SharedPointerKind = ArcK> {
+    stack: Vec<Remaining<K, V, P>>, // for each node on the path to the next entry
+    len: usize,
+}
+
+/// The entries and children left in a node, for `IntoIter`.
+type Remaining<K, V, P> = (vec::IntoIter<(K, V)>, vec::IntoIter<NodeRef<K, V, P>>);
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> IntoIter<K, V, P> {
+    /// Pushes the path to the first entry of `node`.
+    fn descend(&mut self, node: NodeRef<K, V, P>) {
+        let mut next = Some(node);
+        while let Some(node) = next {
+            let node = P::unwrap_or_clone(node);
+            let mut children = node.children.into_iter();
+            next = children.next();
+            self.stack.push((node.entries.into_iter(), children));
+        }
+    }
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> Iterator for IntoIter<K, V, P> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            let (entry, child) = {
+                let &mut (ref mut entries, ref mut children) = self.stack.last_mut()?;
+                (entries.next(), children.next())
+            };
+            match entry {
+                Some(entry) => {
+                    if let Some(child) = child {
+                        self.descend(child);
+                    }
+                    self.len -= 1;
+                    return Some(entry);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> ExactSizeIterator for IntoIter<K, V, P> {}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> FusedIterator for IntoIter<K, V, P> {}
+
+impl<K: Clone, V: Clone, P: SharedPointerKind> IntoIterator for DOrdMap<K, V, P> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, P>;
+
+    fn into_iter(self) -> IntoIter<K, V, P> {
+        let mut iter = IntoIter { stack: vec![], len: self.len() };
+        iter.descend(self.root);
+        iter
+    }
+}
+
+#[cfg(test)]
+mod test;
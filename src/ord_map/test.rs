@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::DOrdMap;
+use {ArcK, RcK, SharedPointerKind};
+
+fn check<P: SharedPointerKind>(map: &DOrdMap<u32, u32, P>, model: &BTreeMap<u32, u32>) {
+    map.assert_valid();
+    assert_eq!(map.len(), model.len());
+    assert!(map.iter().eq(model.iter()));
+    assert!(map.iter().rev().eq(model.iter().rev()));
+}
+
+#[test]
+fn insert_get_remove() {
+    let mut map = DOrdMap::new();
+    assert!(map.is_empty());
+    assert_eq!(map.first(), None);
+    assert_eq!(map.insert("b", 2), None);
+    assert_eq!(map.insert("a", 1), None);
+    assert_eq!(map.insert("b", 3), Some(2));
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["b"], 3);
+    assert_eq!(map.get("c"), None);
+    assert_eq!(map.first(), Some((&"a", &1)));
+    assert_eq!(map.last(), Some((&"b", &3)));
+
+    *map.get_mut("a").unwrap() += 10;
+    assert_eq!(map.remove("a"), Some(11));
+    assert_eq!(map.remove("a"), None);
+    assert_eq!(map.len(), 1);
+}
+
+fn matches_std<P: SharedPointerKind>() {
+    let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+    let mut map = DOrdMap::<u32, u32, P>::default();
+    let mut model = BTreeMap::new();
+    let mut snapshots = vec![];
+
+    for i in 0..20000 {
+        let key = rng.next_u32() % 1000;
+        match rng.next_u32() % 5 {
+            0 | 1 => assert_eq!(map.remove(&key), model.remove(&key)),
+            2 => assert_eq!(map.get(&key), model.get(&key)),
+            _ => assert_eq!(map.insert(key, i), model.insert(key, i)),
+        }
+        assert_eq!(map.len(), model.len());
+        if i % 1000 == 0 {
+            check(&map, &model);
+            snapshots.push((map.clone(), model.clone()));
+        }
+    }
+
+    for (map, model) in snapshots {
+        check(&map, &model);
+        assert_eq!(map.first(), model.iter().next());
+        assert_eq!(map.last(), model.iter().next_back());
+    }
+}
+
+test_instances!(matches_std {
+    arc => [ArcK],
+    rc => [RcK],
+});
+
+#[test]
+fn range() {
+    let map: DOrdMap<u32, u32> = (0..500).map(|i| (i * 2, i)).collect();
+    let model: BTreeMap<u32, u32> = map.iter().map(|(&k, &v)| (k, v)).collect();
+    let bounds = |key| vec![Bound::Included(key), Bound::Excluded(key), Bound::Unbounded];
+
+    for start in (0..1002).step_by(37) {
+        for end in (start..1002).step_by(41) {
+            for &lower in &bounds(start) {
+                for &upper in &bounds(end) {
+                    if start == end && (lower, upper) == (Bound::Excluded(start), Bound::Excluded(end)) {
+                        continue; // `BTreeMap::range` panics on this
+                    }
+                    let range = (lower, upper);
+                    assert_eq!(map.range(range).len(), model.range(range).count());
+                    assert!(map.range(range).eq(model.range(range)));
+                    assert!(map.range(range).rev().eq(model.range(range).rev()));
+                }
+            }
+        }
+    }
+    assert_eq!(map.range((Bound::Included(10), Bound::Excluded(4))).count(), 0);
+}
+
+#[test]
+fn iterate_from_both_ends() {
+    let map: DOrdMap<u32, ()> = (0..1000).map(|i| (i, ())).collect();
+    let mut iter = map.keys();
+    let mut expected = (0..1000).collect::<Vec<u32>>().into_iter();
+    let mut rng = XorShiftRng::from_seed([4, 5, 6, 7]);
+    while iter.len() > 0 {
+        assert_eq!(iter.len(), expected.len());
+        if rng.gen() {
+            assert_eq!(iter.next(), expected.next().as_ref());
+        } else {
+            assert_eq!(iter.next_back(), expected.next_back().as_ref());
+        }
+    }
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next_back(), None);
+}
+
+#[test]
+fn split_off_matches_std() {
+    for &len in &[0, 1, 10, 11, 12, 100, 1000] {
+        let map: DOrdMap<u32, u32> = (0..len).map(|i| (i * 2, i)).collect();
+        let model: BTreeMap<u32, u32> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        for key in 0..len * 2 + 2 {
+            let (mut left, mut model_left) = (map.clone(), model.clone());
+            let right = left.split_off(&key);
+            let model_right = model_left.split_off(&key);
+            check(&left, &model_left);
+            check(&right, &model_right);
+        }
+        check(&map, &model);
+    }
+}
+
+#[test]
+fn split_off_then_update() {
+    let mut rng = XorShiftRng::from_seed([8, 9, 10, 11]);
+    let mut map = DOrdMap::<u32, u32>::new();
+    let mut model = BTreeMap::new();
+    for i in 0..200 {
+        for _ in 0..50 {
+            let key = rng.next_u32() % 5000;
+            assert_eq!(map.insert(key, i), model.insert(key, i));
+        }
+        let key = rng.next_u32() % 5000;
+        let (right, model_right) = (map.split_off(&key), model.split_off(&key));
+        check(&map, &model);
+        check(&right, &model_right);
+        if rng.gen() {
+            map = right;
+            model = model_right;
+        }
+        for _ in 0..20 {
+            let key = rng.next_u32() % 5000;
+            assert_eq!(map.remove(&key), model.remove(&key));
+        }
+        check(&map, &model);
+    }
+}
+
+#[test]
+fn clones_are_unaffected() {
+    let mut map: DOrdMap<u32, u32> = (0..1000).map(|i| (i, i)).collect();
+    let snapshot = map.clone();
+    for i in 0..1000 {
+        if i % 3 == 0 {
+            map.remove(&i);
+        } else {
+            *map.get_mut(&i).unwrap() += 1;
+        }
+    }
+    let rest = map.split_off(&500);
+    assert_eq!(map.len() + rest.len(), 666);
+    assert!(snapshot.iter().map(|(&k, &v)| (k, v)).eq((0..1000).map(|i| (i, i))));
+
+    let mut unchanged = snapshot.clone();
+    assert_eq!(unchanged.remove(&1000), None);
+    assert_eq!(unchanged.get_mut(&1000), None);
+    assert!(unchanged.ptr_eq(&snapshot));
+}
+
+#[test]
+fn equality() {
+    let a: DOrdMap<u32, u32> = (0..1000).map(|i| (i, i)).collect();
+    let mut b: DOrdMap<u32, u32> = (0..1000).rev().map(|i| (i, i)).collect();
+    assert_eq!(a, b);
+    b.insert(5, 6);
+    assert!(a != b);
+    b.remove(&5);
+    assert!(a != b);
+
+    // A value need not equal itself, so neither need a clone.
+    let mut floats: DOrdMap<u32, f64> = (0..1000).map(|i| (i, i as f64)).collect();
+    floats.insert(5, f64::NAN);
+    assert!(floats != floats.clone());
+}
+
+#[test]
+fn into_iter() {
+    let map: DOrdMap<u32, u32> = (0..1000).rev().map(|i| (i, i * 2)).collect();
+    let snapshot = map.clone();
+    assert!(map.into_iter().eq((0..1000).map(|i| (i, i * 2))));
+    assert_eq!(snapshot.len(), 1000);
+    assert!(snapshot.into_iter().eq((0..1000).map(|i| (i, i * 2))));
+}
+
+macro_rules! insert {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use std::collections::BTreeMap;
+            use DOrdMap;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut map = DOrdMap::new();
+                    for i in 0 .. N {
+                        map.insert(i, i);
+                    }
+                    map
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut map = BTreeMap::new();
+                    for i in 0 .. N {
+                        map.insert(i, i);
+                    }
+                    map
+                });
+            }
+        }
+    }
+}
+
+insert!(insert_5000, 5000);
+insert!(insert_50000, 50000);
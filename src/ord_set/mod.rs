@@ -0,0 +1,225 @@
+//! A persistent ordered set, `DOrdSet`, stored as a `DOrdMap` to `()`.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::iter::{FromIterator, FusedIterator};
+use std::ops::RangeBounds;
+
+use super::{ArcK, SharedPointerKind};
+use ord_map::{self, DOrdMap};
+
+/// A **persistent** ordered set, cheap to clone like `DOrdMap`.
+///
+/// ```rust
+/// # use dogged::DOrdSet;
+/// let mut set: DOrdSet<u32> = (0..10).rev().collect();
+/// let snapshot = set.clone();
+/// let high = set.split_off(&5);
+/// assert_eq!(set.iter().cloned().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+/// assert_eq!(high.first(), Some(&5));
+/// assert_eq!(snapshot.len(), 10);
+/// ```
+pub struct DOrdSet<T, P: SharedPointerKind = ArcK> {
+    map: DOrdMap<T, (), P>,
+}
+
+impl<T> DOrdSet<T> {
+    pub fn new() -> Self {
+        DOrdSet::default()
+    }
+}
+
+impl<T, P: SharedPointerKind> DOrdSet<T, P> {
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns an iterator over the elements of the set, in order.
+    pub fn iter(&self) -> Iter<'_, T, P> {
+        Iter { iter: self.map.iter() }
+    }
+
+    /// Returns the smallest element.
+    pub fn first(&self) -> Option<&T> {
+        self.map.first().map(|(value, _)| value)
+    }
+
+    /// Returns the largest element.
+    pub fn last(&self) -> Option<&T> {
+        self.map.last().map(|(value, _)| value)
+    }
+
+    /// Returns true if the two sets share their whole tree, which
+    /// implies that they are equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.map.ptr_eq(&other.map)
+    }
+}
+
+impl<T: Ord, P: SharedPointerKind> DOrdSet<T, P> {
+    pub fn contains<Q: ?Sized + Ord>(&self, value: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.map.contains_key(value)
+    }
+
+    pub fn get<Q: ?Sized + Ord>(&self, value: &Q) -> Option<&T>
+        where T: Borrow<Q>
+    {
+        self.map.get_key_value(value).map(|(value, _)| value)
+    }
+
+    /// Returns an iterator over the elements in `range`, in order.
+    pub fn range<Q, R>(&self, range: R) -> Iter<'_, T, P>
+        where T: Borrow<Q>, Q: ?Sized + Ord, R: RangeBounds<Q>
+    {
+        Iter { iter: self.map.range(range) }
+    }
+}
+
+impl<T: Ord + Clone, P: SharedPointerKind> DOrdSet<T, P> {
+    /// Adds `value` to the set, returning true if it was not there.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+
+    /// Removes `value` from the set, returning true if it was there.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, value: &Q) -> bool
+        where T: Borrow<Q>
+    {
+        self.map.remove(value).is_some()
+    }
+
+    /// Splits the set in two at `value`: `self` keeps the elements
+    /// less than `value`, and the others are returned.
+    pub fn split_off<Q: ?Sized + Ord>(&mut self, value: &Q) -> Self
+        where T: Borrow<Q>
+    {
+        DOrdSet { map: self.map.split_off(value) }
+    }
+}
+
+impl<T, P: SharedPointerKind> Clone for DOrdSet<T, P> {
+    fn clone(&self) -> Self {
+        DOrdSet { map: self.map.clone() }
+    }
+}
+
+impl<T, P: SharedPointerKind> Default for DOrdSet<T, P> {
+    fn default() -> Self {
+        DOrdSet { map: DOrdMap::default() }
+    }
+}
+
+impl<T: fmt::Debug, P: SharedPointerKind> fmt::Debug for DOrdSet<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq, P: SharedPointerKind> PartialEq for DOrdSet<T, P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<T: Eq, P: SharedPointerKind> Eq for DOrdSet<T, P> {}
+
+impl<T: PartialOrd, P: SharedPointerKind> PartialOrd for DOrdSet<T, P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Ord, P: SharedPointerKind> Ord for DOrdSet<T, P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Ord + Clone, P: SharedPointerKind> FromIterator<T> for DOrdSet<T, P> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        DOrdSet { map: iter.into_iter().map(|value| (value, ())).collect() }
+    }
+}
+
+impl<T: Ord + Clone, P: SharedPointerKind> Extend<T> for DOrdSet<T, P> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|value| (value, ())));
+    }
+}
+
+/// An iterator over the elements of a `DOrdSet`, in order, created by
+/// `DOrdSet::iter` and `DOrdSet::range`.
+pub struct Iter<'a, T: 'a, P: SharedPointerKind = ArcK> {
+    iter: ord_map::Iter<'a, T, (), P>,
+}
+
+impl<'a, T, P: SharedPointerKind> Iterator for Iter<'a, T, P> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.iter.next().map(|(value, _)| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, T, P: SharedPointerKind> DoubleEndedIterator for Iter<'a, T, P> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        self.iter.next_back().map(|(value, _)| value)
+    }
+}
+
+impl<'a, T, P: SharedPointerKind> ExactSizeIterator for Iter<'a, T, P> {}
+
+impl<'a, T, P: SharedPointerKind> FusedIterator for Iter<'a, T, P> {}
+
+impl<'a, T, P: SharedPointerKind> IntoIterator for &'a DOrdSet<T, P> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, P>;
+
+    fn into_iter(self) -> Iter<'a, T, P> {
+        self.iter()
+    }
+}
+
+/// An owning iterator over the elements of a `DOrdSet`, in order.
+pub struct IntoIter<T, P: SharedPointerKind = ArcK> {
+    iter: ord_map::IntoIter<T, (), P>,
+}
+
+impl<T: Clone, P: SharedPointerKind> Iterator for IntoIter<T, P> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.iter.next().map(|(value, ())| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<T: Clone, P: SharedPointerKind> ExactSizeIterator for IntoIter<T, P> {}
+
+impl<T: Clone, P: SharedPointerKind> FusedIterator for IntoIter<T, P> {}
+
+impl<T: Clone, P: SharedPointerKind> IntoIterator for DOrdSet<T, P> {
+    type Item = T;
+    type IntoIter = IntoIter<T, P>;
+
+    fn into_iter(self) -> IntoIter<T, P> {
+        IntoIter { iter: self.map.into_iter() }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,49 @@
+use std::collections::BTreeSet;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::DOrdSet;
+
+#[test]
+fn matches_std() {
+    let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+    let mut set = DOrdSet::new();
+    let mut model = BTreeSet::new();
+    for _ in 0..10000 {
+        let value = rng.next_u32() % 1000;
+        if rng.gen() {
+            assert_eq!(set.insert(value), model.insert(value));
+        } else {
+            assert_eq!(set.remove(&value), model.remove(&value));
+        }
+    }
+    set.map.assert_valid();
+    assert_eq!(set.len(), model.len());
+    assert!(set.iter().eq(model.iter()));
+    assert_eq!(set.first(), model.iter().next());
+    assert_eq!(set.last(), model.iter().next_back());
+    assert!(set.range(100..200).eq(model.range(100..200)));
+    assert!(set.range(..=500).rev().eq(model.range(..=500).rev()));
+
+    let high = set.split_off(&600);
+    let model_high = model.split_off(&600);
+    assert!(set.iter().eq(model.iter()));
+    assert!(high.iter().eq(model_high.iter()));
+    assert!(high.clone().into_iter().eq(model_high.into_iter()));
+}
+
+#[test]
+fn basics() {
+    let mut set = DOrdSet::new();
+    assert!(set.insert("b"));
+    assert!(!set.insert("b"));
+    assert!(set.insert("a"));
+    let snapshot = set.clone();
+    assert!(set.remove("a"));
+    assert!(!set.contains("a"));
+    assert!(snapshot.contains("a"));
+    assert_eq!(set.get("b"), Some(&"b"));
+    assert_eq!(snapshot.iter().cloned().collect::<Vec<_>>(), vec!["a", "b"]);
+    assert!(snapshot < set);
+    assert_eq!(set, vec!["b"].into_iter().collect());
+}
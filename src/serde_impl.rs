@@ -0,0 +1,45 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use super::{DVec, SharedPointerKind, TransientDVec};
+
+/// A `DVec` is serialized as a plain sequence of its elements, just like
+/// a `Vec`, so the two can stand in for one another in serialized data.
+impl<T: Serialize, const BITS: usize, P: SharedPointerKind> Serialize for DVec<T, BITS, P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for element in self {
+            seq.serialize_element(element)?;
+        }
+        seq.end()
+    }
+}
+
+/// Elements are gathered in a `TransientDVec`, which builds the tree
+/// bottom-up rather than copying the path to the tail for every one.
+impl<'de, T: Deserialize<'de>, const BITS: usize, P: SharedPointerKind> Deserialize<'de> for DVec<T, BITS, P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(DVecVisitor(PhantomData))
+    }
+}
+
+struct DVecVisitor<T, const BITS: usize, P: SharedPointerKind>(PhantomData<DVec<T, BITS, P>>);
+
+impl<'de, T: Deserialize<'de>, const BITS: usize, P: SharedPointerKind> Visitor<'de> for DVecVisitor<T, BITS, P> {
+    type Value = DVec<T, BITS, P>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut transient = TransientDVec::default();
+        while let Some(element) = seq.next_element()? {
+            transient.push(element);
+        }
+        Ok(transient.into_parts().1)
+    }
+}
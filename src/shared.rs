@@ -0,0 +1,96 @@
+use std::ops::Deref;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The kind of reference-counted pointer a `DVec` uses to share nodes
+/// between versions. `ArcK` makes vectors that can be sent between
+/// threads; `RcK` avoids atomic operations in single-threaded code.
+pub trait SharedPointerKind: 'static {
+    type Pointer<T>: Deref<Target = T> + Clone;
+
+    fn new<T>(value: T) -> Self::Pointer<T>;
+
+    /// Returns a mutable reference to the value, cloning it first if
+    /// some other pointer refers to it.
+    fn make_mut<T: Clone>(this: &mut Self::Pointer<T>) -> &mut T;
+
+    /// Returns a mutable reference to the value if this is the only
+    /// pointer to it.
+    fn get_mut<T>(this: &mut Self::Pointer<T>) -> Option<&mut T>;
+
+    /// Returns the value if this is the only pointer to it.
+    fn try_unwrap<T>(this: Self::Pointer<T>) -> Result<T, Self::Pointer<T>>;
+
+    /// Returns the value, cloning it if some other pointer refers to it.
+    fn unwrap_or_clone<T: Clone>(this: Self::Pointer<T>) -> T {
+        Self::try_unwrap(this).unwrap_or_else(|shared| (*shared).clone())
+    }
+
+    fn ptr_eq<T>(this: &Self::Pointer<T>, other: &Self::Pointer<T>) -> bool;
+}
+
+/// Atomically reference-counted nodes, using `Arc`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ArcK;
+
+/// Non-atomically reference-counted nodes, using `Rc`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RcK;
+
+impl SharedPointerKind for ArcK {
+    type Pointer<T> = Arc<T>;
+
+    #[inline]
+    fn new<T>(value: T) -> Arc<T> {
+        Arc::new(value)
+    }
+
+    #[inline]
+    fn make_mut<T: Clone>(this: &mut Arc<T>) -> &mut T {
+        Arc::make_mut(this)
+    }
+
+    #[inline]
+    fn get_mut<T>(this: &mut Arc<T>) -> Option<&mut T> {
+        Arc::get_mut(this)
+    }
+
+    #[inline]
+    fn try_unwrap<T>(this: Arc<T>) -> Result<T, Arc<T>> {
+        Arc::try_unwrap(this)
+    }
+
+    #[inline]
+    fn ptr_eq<T>(this: &Arc<T>, other: &Arc<T>) -> bool {
+        Arc::ptr_eq(this, other)
+    }
+}
+
+impl SharedPointerKind for RcK {
+    type Pointer<T> = Rc<T>;
+
+    #[inline]
+    fn new<T>(value: T) -> Rc<T> {
+        Rc::new(value)
+    }
+
+    #[inline]
+    fn make_mut<T: Clone>(this: &mut Rc<T>) -> &mut T {
+        Rc::make_mut(this)
+    }
+
+    #[inline]
+    fn get_mut<T>(this: &mut Rc<T>) -> Option<&mut T> {
+        Rc::get_mut(this)
+    }
+
+    #[inline]
+    fn try_unwrap<T>(this: Rc<T>) -> Result<T, Rc<T>> {
+        Rc::try_unwrap(this)
+    }
+
+    #[inline]
+    fn ptr_eq<T>(this: &Rc<T>, other: &Rc<T>) -> bool {
+        Rc::ptr_eq(this, other)
+    }
+}
@@ -0,0 +1,461 @@
+//! A binary format for storing many versions of a `DVec` together.
+//!
+//! Versions of a vector that were cloned from one another share most of
+//! their nodes, and a `SnapshotWriter` writes each of those nodes only
+//! once, no matter how many of the vectors it writes refer to it. A
+//! `SnapshotReader` gives the vectors back in the order they were
+//! written, sharing nodes in the same way.
+//!
+//! ```rust
+//! # use dogged::DVec;
+//! # use dogged::snapshot::{SnapshotReader, SnapshotWriter};
+//! let mut vec: DVec<u32> = (0..10_000).collect();
+//! let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+//! for i in 0..100 {
+//!     vec[i * 100] = 0;
+//!     writer.write(&vec).unwrap();
+//! }
+//! let bytes = writer.finish().unwrap();
+//! // Far less than 100 copies of 10,000 elements.
+//! assert!(bytes.len() < 200_000);
+//!
+//! let snapshots: Vec<DVec<u32>> = SnapshotReader::new(&bytes[..]).unwrap()
+//!                                                                 .collect::<Result<_, _>>()
+//!                                                                 .unwrap();
+//! assert_eq!(snapshots.len(), 100);
+//! assert_eq!(snapshots[99], vec);
+//! ```
+//!
+//! A snapshot starts with a header of the magic bytes `DOGGEDSN`, a
+//! format version (a little-endian `u32`, currently 1) and the `BITS`
+//! of the vectors. Then come records, each a tag byte followed by its
+//! fields: a leaf (its elements), a branch (the ids of its children),
+//! a vector (its head, the id of its root, and its tail), and one to
+//! mark the end. Nodes get consecutive ids in the order they are
+//! written, and only ever refer to nodes written before them. Counts
+//! and ids are LEB128 varints, and elements are written by their
+//! `Element` implementation.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::{ArcK, DVec, Index, Node, NodeRef, Shift, SharedPointerKind};
+
+const MAGIC: &[u8; 8] = b"DOGGEDSN";
+const VERSION: u32 = 1;
+
+const TAG_END: u8 = 0;
+const TAG_LEAF: u8 = 1;
+const TAG_BRANCH: u8 = 2;
+const TAG_VECTOR: u8 = 3;
+
+/// An error from reading or writing a snapshot.
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The underlying reader or writer failed.
+    Io(io::Error),
+    /// The input does not start with the snapshot magic bytes.
+    BadMagic,
+    /// The input is in a version of the format this crate cannot read.
+    UnsupportedVersion(u32),
+    /// The input ended in the middle of a snapshot.
+    Truncated,
+    /// The input is not a valid snapshot; the message says why.
+    Corrupt(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Io(ref err) => write!(f, "I/O error: {}", err),
+            SnapshotError::BadMagic => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Corrupt(ref message) => write!(f, "snapshot is corrupt: {}", message),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SnapshotError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            SnapshotError::Truncated
+        } else {
+            SnapshotError::Io(err)
+        }
+    }
+}
+
+/// How the elements of a vector are written to and read from a
+/// snapshot. Numbers are written in little-endian order, and strings as
+/// their length followed by their UTF-8 bytes.
+pub trait Element: Sized {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()>;
+
+    fn read_from<R: Read>(input: &mut R) -> Result<Self, SnapshotError>;
+}
+
+macro_rules! number_element {
+    ($($t: ty),*) => {
+        $(
+            impl Element for $t {
+                fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+                    out.write_all(&self.to_le_bytes())
+                }
+
+                fn read_from<R: Read>(input: &mut R) -> Result<Self, SnapshotError> {
+                    let mut bytes = [0; std::mem::size_of::<$t>()];
+                    input.read_exact(&mut bytes)?;
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    }
+}
+
+number_element!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+impl Element for usize {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (*self as u64).write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> Result<Self, SnapshotError> {
+        let value = u64::read_from(input)?;
+        if value > usize::MAX as u64 {
+            return Err(SnapshotError::Corrupt(format!("{} does not fit in a usize", value)));
+        }
+        Ok(value as usize)
+    }
+}
+
+impl Element for isize {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (*self as i64).write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> Result<Self, SnapshotError> {
+        let value = i64::read_from(input)?;
+        if value < isize::MIN as i64 || value > isize::MAX as i64 {
+            return Err(SnapshotError::Corrupt(format!("{} does not fit in an isize", value)));
+        }
+        Ok(value as isize)
+    }
+}
+
+impl Element for bool {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (*self as u8).write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> Result<Self, SnapshotError> {
+        match u8::read_from(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(SnapshotError::Corrupt(format!("{} is not a bool", byte))),
+        }
+    }
+}
+
+impl Element for char {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        (*self as u32).write_to(out)
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> Result<Self, SnapshotError> {
+        let value = u32::read_from(input)?;
+        std::char::from_u32(value)
+            .ok_or_else(|| SnapshotError::Corrupt(format!("{:#x} is not a char", value)))
+    }
+}
+
+impl Element for String {
+    fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write_varint(out, self.len() as u64)?;
+        out.write_all(self.as_bytes())
+    }
+
+    fn read_from<R: Read>(input: &mut R) -> Result<Self, SnapshotError> {
+        let len = read_varint(input)?;
+        // Don't trust `len` with an allocation before the bytes are
+        // actually there.
+        let mut bytes = vec![];
+        input.take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            return Err(SnapshotError::Truncated);
+        }
+        String::from_utf8(bytes).map_err(|err| SnapshotError::Corrupt(err.to_string()))
+    }
+}
+
+fn write_varint<W: Write>(out: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read>(input: &mut R) -> Result<u64, SnapshotError> {
+    let mut value = 0;
+    for i in 0..10 {
+        let byte = u8::read_from(input)?;
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(SnapshotError::Corrupt("varint is too long".to_string()))
+}
+
+/// Reads a count that must be at most `max`.
+fn read_count<R: Read>(input: &mut R, max: usize, what: &str) -> Result<usize, SnapshotError> {
+    let count = read_varint(input)?;
+    if count > max as u64 {
+        return Err(SnapshotError::Corrupt(format!("{} has {} entries, at most {} allowed", what, count, max)));
+    }
+    Ok(count as usize)
+}
+
+fn write_elements<W: Write, T: Element>(out: &mut W, elements: &[T]) -> io::Result<()> {
+    write_varint(out, elements.len() as u64)?;
+    for element in elements {
+        element.write_to(out)?;
+    }
+    Ok(())
+}
+
+fn read_elements<R: Read, T: Element>(input: &mut R, max: usize, what: &str) -> Result<Vec<T>, SnapshotError> {
+    let count = read_count(input, max, what)?;
+    (0..count).map(|_| T::read_from(input)).collect()
+}
+
+/// Writes `DVec`s to a snapshot, each node only once. Since it writes
+/// many small records, `out` should usually be buffered.
+pub struct SnapshotWriter<W: Write, T, const BITS: usize = 5, P: SharedPointerKind = ArcK> {
+    out: W,
+    // The ids of the nodes written so far, by address. The writer holds
+    // on to the nodes, so that none of those addresses can be reused.
+    ids: HashMap<usize, u64>,
+    written: Vec<NodeRef<T, BITS, P>>,
+}
+
+impl<W: Write, T: Element, const BITS: usize, P: SharedPointerKind> SnapshotWriter<W, T, BITS, P> {
+    /// Starts a snapshot by writing its header to `out`.
+    pub fn new(mut out: W) -> Result<Self, SnapshotError> {
+        out.write_all(MAGIC)?;
+        VERSION.write_to(&mut out)?;
+        (BITS as u8).write_to(&mut out)?;
+        Ok(SnapshotWriter {
+            out,
+            ids: HashMap::new(),
+            written: vec![],
+        })
+    }
+
+    /// Writes `vec`, along with any of its nodes that have not been
+    /// written yet.
+    pub fn write(&mut self, vec: &DVec<T, BITS, P>) -> Result<(), SnapshotError> {
+        let root = match vec.root {
+            Some(ref root) => self.write_node(root)? + 1,
+            None => 0,
+        };
+        self.out.write_all(&[TAG_VECTOR])?;
+        write_elements(&mut self.out, &vec.head)?;
+        write_varint(&mut self.out, root)?;
+        write_elements(&mut self.out, &vec.tail)?;
+        Ok(())
+    }
+
+    /// Ends the snapshot, and returns the writer it was written to.
+    pub fn finish(mut self) -> Result<W, SnapshotError> {
+        self.out.write_all(&[TAG_END])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Writes `node` and then its children, unless they have been
+    /// written already, and returns its id.
+    fn write_node(&mut self, node: &NodeRef<T, BITS, P>) -> Result<u64, SnapshotError> {
+        let address = &**node as *const Node<T, BITS, P, ()> as usize;
+        if let Some(&id) = self.ids.get(&address) {
+            return Ok(id);
+        }
+
+        match **node {
+            Node::Leaf { ref elements, .. } => {
+                self.out.write_all(&[TAG_LEAF])?;
+                write_elements(&mut self.out, elements)?;
+            }
+            Node::Branch { ref children, .. } => {
+                let ids = children.iter()
+                                  .map(|child| self.write_node(child))
+                                  .collect::<Result<Vec<_>, _>>()?;
+                self.out.write_all(&[TAG_BRANCH])?;
+                write_varint(&mut self.out, ids.len() as u64)?;
+                for id in ids {
+                    write_varint(&mut self.out, id)?;
+                }
+            }
+        }
+        let id = self.written.len() as u64;
+        self.ids.insert(address, id);
+        self.written.push(node.clone());
+        Ok(id)
+    }
+}
+
+/// Reads the `DVec`s of a snapshot back, in the order they were
+/// written. It is also an iterator over them.
+///
+/// Everything read is checked, so that corrupt input gives an error
+/// rather than a vector that panics when used.
+pub struct SnapshotReader<R: Read, T, const BITS: usize = 5, P: SharedPointerKind = ArcK> {
+    input: R,
+    // The nodes read so far, by id, with their shift and length.
+    nodes: Vec<(NodeRef<T, BITS, P>, Shift, usize)>,
+    done: bool,
+}
+
+impl<R: Read, T: Element, const BITS: usize, P: SharedPointerKind> SnapshotReader<R, T, BITS, P> {
+    /// Reads and checks the header of a snapshot.
+    pub fn new(mut input: R) -> Result<Self, SnapshotError> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => SnapshotError::BadMagic,
+            _ => SnapshotError::Io(err),
+        })?;
+        if magic != *MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = u32::read_from(&mut input)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let bits = u8::read_from(&mut input)?;
+        if bits as usize != BITS {
+            return Err(SnapshotError::Corrupt(format!("written with BITS = {}, but read with {}", bits, BITS)));
+        }
+        Ok(SnapshotReader {
+            input,
+            nodes: vec![],
+            done: false,
+        })
+    }
+
+    /// Reads the next vector, or returns `None` at the end of the
+    /// snapshot.
+    pub fn read(&mut self) -> Result<Option<DVec<T, BITS, P>>, SnapshotError> {
+        if self.done {
+            return Ok(None);
+        }
+        let branch_factor = 1 << BITS;
+        loop {
+            match u8::read_from(&mut self.input)? {
+                TAG_END => {
+                    self.done = true;
+                    return Ok(None);
+                }
+                TAG_LEAF => {
+                    let elements: Vec<T> = read_elements(&mut self.input, branch_factor, "leaf")?;
+                    if elements.is_empty() {
+                        return Err(SnapshotError::Corrupt("empty leaf".to_string()));
+                    }
+                    let len = elements.len();
+                    let leaf = Node::leaf(elements);
+                    self.nodes.push((P::new(leaf), Shift(0), len));
+                }
+                TAG_BRANCH => {
+                    let count = read_count(&mut self.input, branch_factor, "branch")?;
+                    let mut children = Vec::with_capacity(count);
+                    let mut shift = None;
+                    let mut len = 0;
+                    for _ in 0..count {
+                        let id = read_varint(&mut self.input)?;
+                        let (child, child_shift, child_len) = self.node(id)?;
+                        if shift.is_some() && shift != Some(child_shift) {
+                            return Err(SnapshotError::Corrupt("branch with children at different depths".to_string()));
+                        }
+                        shift = Some(child_shift);
+                        children.push(child);
+                        len += child_len;
+                    }
+                    let shift = match shift {
+                        Some(child_shift) if child_shift.0 + 2 * BITS < usize::BITS as usize => child_shift.inc(BITS),
+                        Some(_) => return Err(SnapshotError::Corrupt("tree is too deep".to_string())),
+                        None => return Err(SnapshotError::Corrupt("branch without children".to_string())),
+                    };
+                    let branch = Node::<T, BITS, P, ()>::new_branch(children, shift);
+                    self.nodes.push((P::new(branch), shift, len));
+                }
+                TAG_VECTOR => {
+                    let head = read_elements(&mut self.input, branch_factor - 1, "head")?;
+                    let root = match read_varint(&mut self.input)? {
+                        0 => None,
+                        id => Some(self.node(id - 1)?),
+                    };
+                    let tail = read_elements(&mut self.input, branch_factor - 1, "tail")?;
+                    let (root, shift, root_len) = match root {
+                        Some((root, shift, len)) => {
+                            if let Node::Branch { ref children, .. } = *root {
+                                if children.len() == 1 {
+                                    return Err(SnapshotError::Corrupt("root has a single child".to_string()));
+                                }
+                            }
+                            (Some(root), shift, len)
+                        }
+                        None => (None, Shift(0), 0),
+                    };
+                    return Ok(Some(DVec {
+                        root_len: Index(root_len),
+                        shift,
+                        root,
+                        head: P::new(head),
+                        tail: P::new(tail),
+                    }));
+                }
+                tag => return Err(SnapshotError::Corrupt(format!("unknown record tag {}", tag))),
+            }
+        }
+    }
+
+    fn node(&self, id: u64) -> Result<(NodeRef<T, BITS, P>, Shift, usize), SnapshotError> {
+        if id >= self.nodes.len() as u64 {
+            return Err(SnapshotError::Corrupt(format!("reference to unknown node {}", id)));
+        }
+        let (ref node, shift, len) = self.nodes[id as usize];
+        Ok((node.clone(), shift, len))
+    }
+}
+
+impl<R: Read, T: Element, const BITS: usize, P: SharedPointerKind> Iterator for SnapshotReader<R, T, BITS, P> {
+    type Item = Result<DVec<T, BITS, P>, SnapshotError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.read();
+        if result.is_err() {
+            // Nothing after an error can be trusted.
+            self.done = true;
+        }
+        result.transpose()
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,179 @@
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::{Element, SnapshotError, SnapshotReader, SnapshotWriter, MAGIC};
+use test::damage;
+use {ArcK, DVec, Node, RcK, SharedPointerKind};
+
+fn write_all<T, const BITS: usize, P>(vecs: &[DVec<T, BITS, P>]) -> Vec<u8>
+    where T: Element, P: SharedPointerKind
+{
+    let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+    for vec in vecs {
+        writer.write(vec).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+fn read_all<T, const BITS: usize, P>(bytes: &[u8]) -> Result<Vec<DVec<T, BITS, P>>, SnapshotError>
+    where T: Element, P: SharedPointerKind
+{
+    SnapshotReader::new(bytes)?.collect()
+}
+
+/// Versions of a vector made by a random mix of edits, so that they
+/// share some of their nodes and have relaxed trees.
+fn versions<const BITS: usize, P: SharedPointerKind>(seed: u32) -> Vec<DVec<u32, BITS, P>> {
+    let mut rng = XorShiftRng::from_seed([seed, 1, 2, 3]);
+    let mut vec = DVec::<u32, BITS, P>::default();
+    let mut versions = vec![vec.clone()];
+    for _ in 0..60 {
+        match rng.gen_range(0, 6) {
+            0 => {
+                let other: DVec<u32, BITS, P> = (0..rng.gen_range(0, 300)).collect();
+                vec.append(other);
+            }
+            1 => {
+                for i in 0..rng.gen_range(0, 50) {
+                    vec.push_front(i);
+                }
+            }
+            2 => {
+                let len = vec.len();
+                vec.truncate(len - len / 4);
+            }
+            3 if !vec.is_empty() => {
+                for _ in 0..5 {
+                    let index = rng.gen_range(0, vec.len());
+                    vec[index] = rng.gen();
+                }
+            }
+            _ => vec.extend(0..rng.gen_range(0, 200)),
+        }
+        versions.push(vec.clone());
+    }
+    versions
+}
+
+fn round_trip<const BITS: usize, P: SharedPointerKind>() {
+    for seed in 0..5 {
+        let vecs = versions::<BITS, P>(seed);
+        let bytes = write_all(&vecs);
+        let read: Vec<DVec<u32, BITS, P>> = read_all(&bytes).unwrap();
+        assert_eq!(read.len(), vecs.len());
+        for (read, vec) in read.iter().zip(&vecs) {
+            read.assert_valid();
+            assert_eq!(read, vec);
+        }
+        // The nodes are shared as they were, so writing the vectors
+        // again gives exactly the same snapshot.
+        assert_eq!(write_all(&read), bytes);
+    }
+}
+
+test_instances!(round_trip {
+    arc => [5, ArcK],
+    rc => [5, RcK],
+    narrow => [2, ArcK],
+});
+
+#[test]
+fn round_trip_strings() {
+    let mut vec: DVec<String, 3> = (0..500).map(|i| i.to_string()).collect();
+    let mut vecs = vec![vec.clone()];
+    vec[250] = "snowman ☃".to_string();
+    vec.push(String::new());
+    vecs.push(vec);
+
+    let read: Vec<DVec<String, 3>> = read_all(&write_all(&vecs)).unwrap();
+    assert_eq!(read, vecs);
+}
+
+#[test]
+fn shared_nodes_are_written_once() {
+    let base: DVec<u64> = (0..100_000).collect();
+    let mut edited = base.clone();
+    edited[50_000] = 0;
+
+    let alone = write_all(::std::slice::from_ref(&base)).len();
+    let both = write_all(&[base, edited]);
+    // The second vector only adds the path to the edited leaf.
+    assert!(both.len() < alone + 1000, "{} bytes for one, {} for both", alone, both.len());
+
+    let read: Vec<DVec<u64>> = read_all(&both).unwrap();
+    let children = |vec: &DVec<u64>| match **vec.root.as_ref().unwrap() {
+        Node::Branch { ref children, .. } => children.clone(),
+        Node::Leaf { .. } => panic!("root is a leaf"),
+    };
+    let (first, second) = (children(&read[0]), children(&read[1]));
+    assert_eq!(first.len(), second.len());
+    let shared = first.iter().zip(&second).filter(|&(a, b)| ArcK::ptr_eq(a, b)).count();
+    assert_eq!(shared, first.len() - 1);
+}
+
+#[test]
+fn bad_header() {
+    let bytes = write_all(&[(0..10).collect::<DVec<u32>>()]);
+    let error = |bytes: &[u8]| SnapshotReader::<_, u32>::new(bytes).err().unwrap();
+
+    assert!(matches!(error(&[]), SnapshotError::BadMagic));
+    assert!(matches!(error(b"DOGGED"), SnapshotError::BadMagic));
+    assert!(matches!(error(b"NOTASNAPSHOT...."), SnapshotError::BadMagic));
+
+    let mut future = bytes.clone();
+    future[MAGIC.len()] = 2;
+    assert!(matches!(error(&future), SnapshotError::UnsupportedVersion(2)));
+
+    let wrong_bits = SnapshotReader::<_, u32, 4>::new(&bytes[..]).err().unwrap();
+    assert!(matches!(wrong_bits, SnapshotError::Corrupt(_)));
+}
+
+#[test]
+fn truncated_input() {
+    let bytes = write_all(&versions::<2, ArcK>(7));
+    for len in MAGIC.len()..bytes.len() {
+        match read_all::<u32, 2, ArcK>(&bytes[..len]) {
+            Err(SnapshotError::Truncated) => {}
+            other => panic!("{} bytes read as {:?}", len, other.map(|vecs| vecs.len())),
+        }
+    }
+}
+
+#[test]
+fn corrupt_input() {
+    let header = write_all::<u32, 5, ArcK>(&[]);
+    let header = &header[..header.len() - 1];
+    let corrupt = |records: &[u8]| {
+        let bytes = [header, records].concat();
+        match read_all::<u32, 5, ArcK>(&bytes) {
+            Err(SnapshotError::Corrupt(_)) => {}
+            other => panic!("{:?} read as {:?}", records, other.map(|vecs| vecs.len())),
+        }
+    };
+
+    corrupt(&[9]); // unknown tag
+    corrupt(&[1, 0]); // empty leaf
+    corrupt(&[1, 33]); // leaf too long
+    corrupt(&[2, 0]); // branch without children
+    corrupt(&[2, 1, 0]); // child that was never written
+    corrupt(&[1, 1, 0, 0, 0, 0, 3, 0, 2, 0]); // vector whose root was never written
+    corrupt(&[1, 1, 0, 0, 0, 0, 2, 1, 0, 2, 2, 0, 1]); // children at different depths
+    corrupt(&[1, 1, 0, 0, 0, 0, 2, 1, 0, 3, 0, 2, 0]); // root with a single child
+    corrupt(&[3, 32]); // head too long
+    corrupt(&[3, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]); // varint too long
+}
+
+#[test]
+fn damaged_input_never_panics() {
+    let mut rng = XorShiftRng::from_seed([9, 8, 7, 6]);
+    let bytes = write_all(&versions::<2, RcK>(3));
+    for _ in 0..500 {
+        let damaged = damage(&mut rng, &bytes);
+        // Whatever is read must be a valid vector.
+        if let Ok(vecs) = read_all::<u32, 2, RcK>(&damaged) {
+            for vec in vecs {
+                vec.assert_valid();
+                assert_eq!(vec.iter().count(), vec.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,610 @@
+//! A persistent rope, `DString`, stored as a `DVec` of UTF-8 chunks.
+
+use std::cmp::Ordering;
+use std::fmt::{self, Write};
+use std::iter::{self, FusedIterator};
+use std::ops::{Add, Bound, RangeBounds};
+use std::str;
+
+use super::{ArcK, DVec, Iter, Measure, SharedPointerKind};
+
+/// Every chunk holds at most `MAX_BYTES` bytes of text. Edits merge
+/// chunks of less than `MIN_BYTES` with a neighbour, so that the text
+/// does not end up spread over lots of tiny chunks. Tests use much
+/// smaller chunks and a narrower tree, so that short strings already
+/// make deep trees.
+#[cfg(not(test))]
+const MAX_BYTES: usize = 1024;
+#[cfg(test)]
+const MAX_BYTES: usize = 16;
+const MIN_BYTES: usize = MAX_BYTES / 4;
+
+#[cfg(not(test))]
+const TREE_BITS: usize = 5;
+#[cfg(test)]
+const TREE_BITS: usize = 2;
+
+/// A **persistent** string, or rope. Like `DVec`, it is cheap to clone,
+/// and a clone shares its whole tree with the original; edits copy
+/// only the O(log n) nodes on the path to the text they change.
+///
+/// Positions are given in chars. The text is kept as a `DVec` of
+/// chunks whose nodes cache the number of bytes, chars and lines
+/// below them, so inserting and removing text as well as converting
+/// between chars, bytes and lines all take O(log n) time.
+///
+/// ```rust
+/// # use dogged::DString;
+/// let mut text: DString = DString::from("fn main() {\n}\n");
+/// let snapshot = text.clone();
+/// text.insert(12, "    println!(\"hi\");\n");
+/// assert_eq!(text.len_lines(), 4);
+/// assert_eq!(text.line_col(16), (1, 4));
+/// assert_eq!(text.line(1).to_string(), "    println!(\"hi\");\n");
+/// assert_eq!(snapshot, "fn main() {\n}\n");
+/// ```
+pub struct DString<P: SharedPointerKind = ArcK> {
+    // Never holds an empty chunk.
+    chunks: DVec<Chunk, TREE_BITS, P, TextMeasure>,
+}
+
+/// The counts cached for a run of text.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Summary {
+    bytes: usize,
+    chars: usize,
+    newlines: usize,
+}
+
+/// A piece of the text along with its counts, so that measuring it
+/// does not have to look at the text again.
+#[derive(Clone)]
+struct Chunk {
+    text: String,
+    summary: Summary,
+}
+
+/// The `Measure` that sums up the counts of the chunks.
+struct TextMeasure;
+
+impl Measure<Chunk> for TextMeasure {
+    type Summary = Summary;
+
+    fn identity() -> Summary {
+        Summary::default()
+    }
+
+    fn measure(chunk: &Chunk) -> Summary {
+        chunk.summary
+    }
+
+    fn combine(left: &Summary, right: &Summary) -> Summary {
+        *left + *right
+    }
+}
+
+impl DString {
+    pub fn new() -> Self {
+        DString::default()
+    }
+}
+
+impl<P: SharedPointerKind> DString<P> {
+    pub fn len_bytes(&self) -> usize {
+        self.chunks.summary().bytes
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.chunks.summary().chars
+    }
+
+    /// Returns the number of lines, which is one more than the number
+    /// of `'\n'` characters: a string ending in a newline ends with an
+    /// empty line.
+    pub fn len_lines(&self) -> usize {
+        self.chunks.summary().newlines + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Returns an iterator over the chunks of text that make up the
+    /// string, in order.
+    pub fn chunks(&self) -> Chunks<'_, P> {
+        Chunks { iter: self.chunks.iter() }
+    }
+
+    pub fn chars(&self) -> Chars<'_, P> {
+        Chars {
+            chunks: self.chunks(),
+            chars: "".chars(),
+        }
+    }
+
+    /// Returns the byte offset of the char at `char_idx`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `char_idx > len_chars`.
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        self.check_char(char_idx);
+        match self.chunk_at_char(char_idx) {
+            Some((chunk, before)) => before.bytes + byte_offset(&chunk.text, char_idx - before.chars),
+            None => 0,
+        }
+    }
+
+    /// Returns the line that the char at `char_idx` is on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `char_idx > len_chars`.
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        self.check_char(char_idx);
+        match self.chunk_at_char(char_idx) {
+            Some((chunk, before)) => {
+                let end = byte_offset(&chunk.text, char_idx - before.chars);
+                before.newlines + count_newlines(&chunk.text[..end])
+            }
+            None => 0,
+        }
+    }
+
+    /// Returns the char index at which `line` starts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line >= len_lines`.
+    pub fn line_to_char(&self, line: usize) -> usize {
+        if line >= self.len_lines() {
+            panic!("line index {} out of range for DString with {} lines", line, self.len_lines());
+        }
+        if line == 0 {
+            return 0;
+        }
+
+        // Find the newline that ends the line before.
+        let index = self.chunks.search(|through| through.newlines >= line).unwrap();
+        let before = self.chunks.prefix(index);
+        let text = &self.chunks[index].text;
+        let (newline, _) = text.match_indices('\n').nth(line - before.newlines - 1).unwrap();
+        before.chars + text[..newline + 1].chars().count()
+    }
+
+    /// Returns the line and column, both counted from zero, of the char
+    /// at `char_idx`. The column is in chars.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `char_idx > len_chars`.
+    pub fn line_col(&self, char_idx: usize) -> (usize, usize) {
+        let line = self.char_to_line(char_idx);
+        (line, char_idx - self.line_to_char(line))
+    }
+
+    /// Returns the text of `line`, including the newline that ends it
+    /// (if any), sharing structure with `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `line >= len_lines`.
+    pub fn line(&self, line: usize) -> DString<P> {
+        let start = self.line_to_char(line);
+        let end = if line + 1 < self.len_lines() {
+            self.line_to_char(line + 1)
+        } else {
+            self.len_chars()
+        };
+        self.slice(start..end)
+    }
+
+    /// Returns true if the two strings share their whole tree, which
+    /// implies that they are equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.chunks.ptr_eq(&other.chunks)
+    }
+
+    /// Inserts `text` before the char at `char_idx`. Small insertions
+    /// go straight into the chunk holding `char_idx`; larger ones split
+    /// the chunks at `char_idx` and put them back together around new
+    /// chunks made from `text`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `char_idx > len_chars`.
+    pub fn insert(&mut self, char_idx: usize, text: &str) {
+        let len = self.len_chars();
+        if char_idx > len {
+            panic!("insertion index (is {}) should be <= len (is {})", char_idx, len);
+        }
+        if text.is_empty() {
+            return;
+        }
+
+        let index = match self.chunks.search(|through| through.chars >= char_idx) {
+            Some(index) => index,
+            None => {
+                *self = DString::from(text);
+                return;
+            }
+        };
+        let offset = char_idx - self.chunks.prefix(index).chars;
+        if self.chunks[index].text.len() + text.len() <= MAX_BYTES {
+            self.chunks.update(index, |chunk| {
+                let at = byte_offset(&chunk.text, offset);
+                chunk.text.insert_str(at, text);
+                chunk.summary = Summary::of(&chunk.text);
+            });
+            return;
+        }
+
+        let rest = self.chunks.split_off(index + 1);
+        let chunk = self.chunks.pop().unwrap().text;
+        let at = byte_offset(&chunk, offset);
+        let joined = [&chunk[..at], text, &chunk[at..]].concat();
+        self.splice(joined, rest);
+    }
+
+    /// Appends `text` at the end of the string.
+    pub fn push_str(&mut self, text: &str) {
+        let len = self.len_chars();
+        self.insert(len, text);
+    }
+
+    /// Removes the chars in `range`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is decreasing or extends past the end of
+    /// the string.
+    pub fn remove<R: RangeBounds<usize>>(&mut self, range: R) {
+        let (start, end) = self.char_range(range);
+        if start == end {
+            return;
+        }
+
+        let first = self.chunks.search(|through| through.chars >= start).unwrap();
+        let last = self.chunks.search(|through| through.chars >= end).unwrap();
+        let before = self.chunks.prefix(first);
+        let chunk = &self.chunks[first].text;
+        let from = byte_offset(chunk, start - before.chars);
+        if first == last {
+            let to = byte_offset(chunk, end - before.chars);
+            let left = chunk.len() - (to - from);
+            if left >= MIN_BYTES || (left > 0 && self.chunks.len() == 1) {
+                self.chunks.update(first, |chunk| {
+                    chunk.text.replace_range(from..to, "");
+                    chunk.summary = Summary::of(&chunk.text);
+                });
+                return;
+            }
+        }
+
+        let rest = self.chunks.split_off(last + 1);
+        let removed = self.chunks.split_off(first);
+        let last_chunk = &removed[last - first].text;
+        let to = byte_offset(last_chunk, end - before.chars - removed.prefix(last - first).chars);
+        let joined = [&removed[0].text[..from], &last_chunk[to..]].concat();
+        self.splice(joined, rest);
+    }
+
+    /// Moves all the text of `other` onto the end of `self`, sharing
+    /// all but O(log n) nodes of both trees.
+    pub fn append(&mut self, other: DString<P>) {
+        match self.chunks.pop() {
+            Some(last) => self.splice(last.text, other.chunks),
+            None => *self = other,
+        }
+    }
+
+    /// Splits the string in two at `char_idx`: `self` keeps the chars
+    /// before it and the rest are returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `char_idx > len_chars`.
+    pub fn split_off(&mut self, char_idx: usize) -> DString<P> {
+        self.check_char(char_idx);
+        let index = match self.chunks.search(|through| through.chars >= char_idx) {
+            Some(index) => index,
+            None => return DString::default(),
+        };
+        let offset = char_idx - self.chunks.prefix(index).chars;
+
+        let mut rest = self.chunks.split_off(index + 1);
+        let mut chunk = self.chunks.pop().unwrap().text;
+        let right = chunk.split_off(byte_offset(&chunk, offset));
+        if !chunk.is_empty() {
+            self.chunks.push(Chunk::new(chunk));
+        }
+        if !right.is_empty() {
+            rest.push_front(Chunk::new(right));
+        }
+        DString { chunks: rest }
+    }
+
+    /// Returns a new string holding the chars in `range`, sharing
+    /// structure with `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is decreasing or extends past the end of
+    /// the string.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> DString<P> {
+        let (start, end) = self.char_range(range);
+        let mut slice = self.clone();
+        slice.split_off(end);
+        slice.split_off(start)
+    }
+
+    /// Returns the chunk holding the char at `char_idx`, and the counts
+    /// for the chunks before it, or `None` if the string is empty. An
+    /// index between two chunks goes to the first of them.
+    fn chunk_at_char(&self, char_idx: usize) -> Option<(&Chunk, Summary)> {
+        let index = self.chunks.search(|through| through.chars >= char_idx)?;
+        Some((&self.chunks[index], self.chunks.prefix(index)))
+    }
+
+    /// Puts `text` as new chunks between the chunks of `self` and those
+    /// of `rest`. A short `text`, or a short first chunk of `rest`, is
+    /// merged with its neighbour first.
+    fn splice(&mut self, text: String, rest: DVec<Chunk, TREE_BITS, P, TextMeasure>) {
+        let mut text = text;
+        let mut rest = rest;
+        if text.len() < MIN_BYTES || rest.get(0).is_some_and(|next| next.text.len() < MIN_BYTES) {
+            if let Some(next) = rest.pop_front() {
+                text.push_str(&next.text);
+            }
+        }
+        if text.len() < MIN_BYTES {
+            if let Some(previous) = self.chunks.pop() {
+                text.insert_str(0, &previous.text);
+            }
+        }
+        self.chunks.extend(split_chunks(&text));
+        self.chunks.append(rest);
+    }
+
+    fn check_char(&self, char_idx: usize) {
+        if char_idx > self.len_chars() {
+            panic!("char index {} out of range for DString of length {}", char_idx, self.len_chars());
+        }
+    }
+
+    fn char_range<R: RangeBounds<usize>>(&self, range: R) -> (usize, usize) {
+        let len = self.len_chars();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        };
+        if start > end {
+            panic!("slice index starts at {} but ends at {}", start, end);
+        }
+        if end > len {
+            panic!("range end index {} out of range for DString of length {}", end, len);
+        }
+        (start, end)
+    }
+
+    /// Checks the invariants of the tree and of the chunks in it.
+    #[cfg(test)]
+    pub(crate) fn assert_valid(&self) {
+        self.chunks.assert_valid();
+        self.chunks.assert_summaries();
+        for chunk in &self.chunks {
+            assert!(!chunk.text.is_empty() && chunk.text.len() <= MAX_BYTES,
+                    "chunk with {} bytes",
+                    chunk.text.len());
+            assert!(chunk.summary == Summary::of(&chunk.text),
+                    "chunk {:?} has counts {:?}",
+                    chunk.text,
+                    chunk.summary);
+        }
+    }
+}
+
+impl Summary {
+    fn of(text: &str) -> Summary {
+        Summary {
+            bytes: text.len(),
+            chars: text.chars().count(),
+            newlines: count_newlines(text),
+        }
+    }
+}
+
+impl Add for Summary {
+    type Output = Summary;
+
+    fn add(self, other: Summary) -> Summary {
+        Summary {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            newlines: self.newlines + other.newlines,
+        }
+    }
+}
+
+impl Chunk {
+    fn new(text: String) -> Chunk {
+        Chunk {
+            summary: Summary::of(&text),
+            text,
+        }
+    }
+}
+
+fn count_newlines(text: &str) -> usize {
+    text.bytes().filter(|&b| b == b'\n').count()
+}
+
+/// Returns the byte offset of the char at `char_offset` in `text`.
+fn byte_offset(text: &str, char_offset: usize) -> usize {
+    text.char_indices().nth(char_offset).map_or(text.len(), |(offset, _)| offset)
+}
+
+/// How much of `remaining` to put into the next chunk, so that no chunk
+/// holds more than `MAX_BYTES` or (unless it is the only one) less
+/// than `MIN_BYTES`: rather than leaving too little for the last chunk,
+/// the last two split what is left evenly.
+fn piece_len(remaining: usize) -> usize {
+    if remaining <= MAX_BYTES {
+        remaining
+    } else if remaining < MAX_BYTES + MIN_BYTES {
+        remaining / 2
+    } else {
+        MAX_BYTES
+    }
+}
+
+/// Cuts `text` into chunks, at char boundaries.
+fn split_chunks(text: &str) -> impl Iterator<Item = Chunk> + '_ {
+    let mut rest = text;
+    iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut len = piece_len(rest.len());
+        while !rest.is_char_boundary(len) {
+            len -= 1;
+        }
+        let (chunk, others) = rest.split_at(len);
+        rest = others;
+        Some(Chunk::new(chunk.to_string()))
+    })
+}
+
+impl<P: SharedPointerKind> Clone for DString<P> {
+    fn clone(&self) -> Self {
+        DString { chunks: self.chunks.clone() }
+    }
+}
+
+impl<P: SharedPointerKind> Default for DString<P> {
+    fn default() -> Self {
+        DString { chunks: DVec::default() }
+    }
+}
+
+impl<'a, P: SharedPointerKind> From<&'a str> for DString<P> {
+    fn from(text: &'a str) -> Self {
+        DString { chunks: split_chunks(text).collect() }
+    }
+}
+
+impl<P: SharedPointerKind> From<String> for DString<P> {
+    fn from(text: String) -> Self {
+        DString::from(&text[..])
+    }
+}
+
+impl<P: SharedPointerKind> fmt::Display for DString<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: SharedPointerKind> fmt::Debug for DString<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_char('"')?;
+        for chunk in self.chunks() {
+            write!(f, "{}", chunk.escape_debug())?;
+        }
+        f.write_char('"')
+    }
+}
+
+impl<P: SharedPointerKind> PartialEq for DString<P> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len_bytes() == other.len_bytes() &&
+        (self.ptr_eq(other) || self.chunks().flat_map(str::bytes).eq(other.chunks().flat_map(str::bytes)))
+    }
+}
+
+impl<P: SharedPointerKind> Eq for DString<P> {}
+
+impl<P: SharedPointerKind> PartialEq<str> for DString<P> {
+    fn eq(&self, other: &str) -> bool {
+        self.len_bytes() == other.len() && self.chunks().flat_map(str::bytes).eq(other.bytes())
+    }
+}
+
+impl<'a, P: SharedPointerKind> PartialEq<&'a str> for DString<P> {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+impl<P: SharedPointerKind> PartialEq<String> for DString<P> {
+    fn eq(&self, other: &String) -> bool {
+        *self == other[..]
+    }
+}
+
+impl<P: SharedPointerKind> PartialOrd for DString<P> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P: SharedPointerKind> Ord for DString<P> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Comparing UTF-8 bytes orders strings by code point, like `str`.
+        self.chunks().flat_map(str::bytes).cmp(other.chunks().flat_map(str::bytes))
+    }
+}
+
+/// An iterator over the chunks of text that make up a `DString`,
+/// created by `DString::chunks`.
+pub struct Chunks<'a, P: SharedPointerKind = ArcK> {
+    iter: Iter<'a, Chunk, TREE_BITS, P, TextMeasure>,
+}
+
+impl<'a, P: SharedPointerKind> Iterator for Chunks<'a, P> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.iter.next().map(|chunk| &chunk.text[..])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'a, P: SharedPointerKind> FusedIterator for Chunks<'a, P> {}
+
+/// An iterator over the chars of a `DString`, created by
+/// `DString::chars`.
+pub struct Chars<'a, P: SharedPointerKind = ArcK> {
+    chunks: Chunks<'a, P>,
+    chars: str::Chars<'a>,
+}
+
+impl<'a, P: SharedPointerKind> Iterator for Chars<'a, P> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        loop {
+            if let Some(c) = self.chars.next() {
+                return Some(c);
+            }
+            self.chars = self.chunks.next()?.chars();
+        }
+    }
+}
+
+impl<'a, P: SharedPointerKind> FusedIterator for Chars<'a, P> {}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,166 @@
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::DString;
+use {ArcK, RcK, SharedPointerKind};
+
+const PIECES: &[&str] = &["a", "bc", "\n", "héllo", "wörld\n", "日本語", "🦀\n🦀", "", "\n\n", "the quick brown fox jumps over the lazy dog"];
+
+fn check<P: SharedPointerKind>(text: &DString<P>, model: &str) {
+    text.assert_valid();
+    assert_eq!(text.len_bytes(), model.len());
+    assert_eq!(text.len_chars(), model.chars().count());
+    assert_eq!(text.len_lines(), model.split('\n').count());
+    assert_eq!(text.to_string(), model);
+    assert!(text.chars().eq(model.chars()));
+    assert_eq!(*text, model);
+}
+
+/// Converts a char index in `model` to a byte offset.
+fn byte_offset(model: &str, char_idx: usize) -> usize {
+    model.char_indices().nth(char_idx).map_or(model.len(), |(offset, _)| offset)
+}
+
+fn matches_string<P: SharedPointerKind>() {
+    let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+    let mut text = DString::<P>::default();
+    let mut model = String::new();
+    let mut snapshots = vec![];
+
+    for i in 0..5000 {
+        let len = model.chars().count();
+        if rng.gen_range(0, 3) == 0 {
+            let start = rng.gen_range(0, len + 1);
+            let end = start + rng.gen_range(0, 20).min(len - start);
+            text.remove(start..end);
+            model.replace_range(byte_offset(&model, start)..byte_offset(&model, end), "");
+        } else {
+            let at = rng.gen_range(0, len + 1);
+            let piece = if rng.gen_range(0, 50) == 0 {
+                PIECES.concat()
+            } else {
+                PIECES[rng.gen_range(0, PIECES.len())].to_string()
+            };
+            text.insert(at, &piece);
+            model.insert_str(byte_offset(&model, at), &piece);
+        }
+        assert_eq!(text.len_chars(), model.chars().count());
+        if i % 250 == 0 {
+            check(&text, &model);
+            snapshots.push((text.clone(), model.clone()));
+        }
+    }
+
+    for (text, model) in snapshots {
+        check(&text, &model);
+    }
+}
+
+test_instances!(matches_string {
+    arc => [ArcK],
+    rc => [RcK],
+});
+
+#[test]
+fn lines_and_columns() {
+    let model: String = (0..200).map(|i| format!("línea {}{}\n", i, "·".repeat(i % 7))).collect();
+    let text: DString = DString::from(&model[..]);
+    check(&text, &model);
+
+    let mut char_idx = 0;
+    for (line, content) in model.split('\n').enumerate() {
+        assert_eq!(text.line_to_char(line), char_idx);
+        assert_eq!(text.line(line).to_string(), model.split_inclusive('\n').nth(line).unwrap_or(""));
+        // Every char of the line, and the newline (or end) after it.
+        let width = content.chars().count();
+        for col in 0..=width {
+            assert_eq!(text.line_col(char_idx + col), (line, col));
+            assert_eq!(text.char_to_byte(char_idx + col), byte_offset(&model, char_idx + col));
+        }
+        char_idx += width + 1;
+    }
+    assert_eq!(text.len_lines(), 201);
+    assert_eq!(text.line(200).len_bytes(), 0);
+}
+
+#[test]
+fn split_off_slice_and_append() {
+    let model: String = (0..300).map(|i| PIECES[i % PIECES.len()]).collect();
+    let text: DString = DString::from(model.clone());
+    let len = model.chars().count();
+
+    for at in (0..=len).step_by(7) {
+        let mut left = text.clone();
+        let right = left.split_off(at);
+        check(&left, &model[..byte_offset(&model, at)]);
+        check(&right, &model[byte_offset(&model, at)..]);
+
+        let mut joined = left.clone();
+        joined.append(right.clone());
+        check(&joined, &model);
+        let mut swapped = right;
+        swapped.append(left);
+        check(&swapped, &format!("{}{}", &model[byte_offset(&model, at)..], &model[..byte_offset(&model, at)]));
+
+        let end = (at + 100).min(len);
+        check(&text.slice(at..end), &model[byte_offset(&model, at)..byte_offset(&model, end)]);
+    }
+    check(&text, &model);
+}
+
+#[test]
+fn edits_leave_snapshots_alone() {
+    let model = "hello, world\n".repeat(100);
+    let mut text = DString::new();
+    text.push_str(&model);
+    let snapshot = text.clone();
+    assert!(text.ptr_eq(&snapshot));
+
+    text.insert(5, "!!");
+    text.remove(0..1);
+    text.remove(..);
+    assert!(text.is_empty());
+    check(&text, "");
+    check(&snapshot, &model);
+
+    text.insert(0, "x");
+    text.remove(1..);
+    assert_eq!(text, "x");
+    let (abc, abd): (DString, DString) = ("abc".into(), "abd".into());
+    assert!(abc < abd);
+    assert_eq!(format!("{:?}", DString::<ArcK>::from("a\"b\n")), "\"a\\\"b\\n\"");
+}
+
+macro_rules! insert {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use DString;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut text = DString::new();
+                    for i in 0 .. N {
+                        text.insert(i / 2, "ab");
+                    }
+                    text
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut text = String::new();
+                    for i in 0 .. N {
+                        text.insert_str(i / 2, "ab");
+                    }
+                    text
+                });
+            }
+        }
+    }
+}
+
+insert!(insert_5000, 5000);
+insert!(insert_50000, 50000);
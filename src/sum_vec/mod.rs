@@ -0,0 +1,323 @@
+//! Summaries of the elements of a `DVec`, cached in the nodes of its
+//! tree, so that prefix sums and searches over them take O(log n).
+
+use std::cmp;
+#[cfg(test)]
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+use super::{ArcK, DVec, Node, SharedPointerKind, Shift, position, prefix_len};
+
+/// How to summarize the elements of a `DVec`. The summaries, with
+/// `combine` and `identity`, must form a monoid: `combine` must be
+/// associative, and combining anything with `identity` must leave it
+/// unchanged. Sums, maxima and counts all qualify.
+///
+/// ```rust
+/// # use dogged::sum_vec::Measure;
+/// struct Sum;
+///
+/// impl Measure<u32> for Sum {
+///     type Summary = u64;
+///
+///     fn identity() -> u64 {
+///         0
+///     }
+///
+///     fn measure(element: &u32) -> u64 {
+///         *element as u64
+///     }
+///
+///     fn combine(left: &u64, right: &u64) -> u64 {
+///         left + right
+///     }
+/// }
+/// ```
+pub trait Measure<T> {
+    type Summary: Clone;
+
+    /// The summary of no elements at all.
+    fn identity() -> Self::Summary;
+
+    /// The summary of a single element.
+    fn measure(element: &T) -> Self::Summary;
+
+    /// The summary of two adjacent runs of elements, given theirs.
+    fn combine(left: &Self::Summary, right: &Self::Summary) -> Self::Summary;
+}
+
+/// The measure of a plain `DVec`, which summarizes nothing. Its
+/// summaries take no room in the nodes.
+impl<T> Measure<T> for () {
+    type Summary = ();
+
+    fn identity() {}
+
+    fn measure(_: &T) {}
+
+    fn combine(_: &(), _: &()) {}
+}
+
+/// A **persistent** vector that keeps a summary of its elements, as
+/// defined by the `Measure` `M`, in every node of its tree. That makes
+/// `prefix`, `range_summary` and `search` O(log n) rather than O(n),
+/// while clones stay as cheap as those of any other `DVec`, which is
+/// what this is.
+///
+/// Since every update has to recompute the summaries above it, there is
+/// no `get_mut`; elements are changed with `set` or `update` instead.
+///
+/// ```rust
+/// # use dogged::sum_vec::{DSumVec, Measure};
+/// # struct Sum;
+/// # impl Measure<u32> for Sum {
+/// #     type Summary = u64;
+/// #     fn identity() -> u64 { 0 }
+/// #     fn measure(element: &u32) -> u64 { *element as u64 }
+/// #     fn combine(left: &u64, right: &u64) -> u64 { left + right }
+/// # }
+/// // Weighted sampling: pick the element at which the running total of
+/// // the weights first exceeds some point in `0..total`.
+/// let mut weights: DSumVec<u32, Sum> = vec![5, 1, 0, 4].into_iter().collect();
+/// assert_eq!(weights.summary(), 10);
+/// assert_eq!(weights.search(|&total| total > 5), Some(1));
+/// assert_eq!(weights.search(|&total| total > 6), Some(3));
+///
+/// let snapshot = weights.clone();
+/// weights.set(2, 10);
+/// weights.push_front(7);
+/// assert_eq!(weights.prefix(4), 23);
+/// assert_eq!(weights.range_summary(2..), 15);
+/// assert_eq!(snapshot.prefix(3), 6);
+/// ```
+pub type DSumVec<T, M, const BITS: usize = 5, P = ArcK> = DVec<T, BITS, P, M>;
+
+/// Returns the summary of a run of elements.
+pub(crate) fn measure_all<T, M: Measure<T>>(elements: &[T]) -> M::Summary {
+    elements.iter().fold(M::identity(), |acc, element| M::combine(&acc, &M::measure(element)))
+}
+
+/// Combines the summaries of a run of subtrees.
+pub(crate) fn combine_all<'a, T: 'a, M, I>(summaries: I) -> M::Summary
+    where M: Measure<T>, M::Summary: 'a, I: IntoIterator<Item = &'a M::Summary>
+{
+    summaries.into_iter().fold(M::identity(), |acc, summary| M::combine(&acc, summary))
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// Returns the summary of all the elements.
+    pub fn summary(&self) -> M::Summary {
+        let tree = match self.root {
+            Some(ref root) => root.summary().clone(),
+            None => M::identity(),
+        };
+        let head = measure_all::<T, M>(&self.head);
+        M::combine(&M::combine(&head, &tree), &measure_all::<T, M>(&self.tail))
+    }
+
+    /// Returns the summary of the first `end` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `end > len`.
+    pub fn prefix(&self, end: usize) -> M::Summary {
+        self.range_summary(..end)
+    }
+
+    /// Returns the summary of the elements in `range`. Subtrees that
+    /// lie entirely inside the range contribute their cached summary,
+    /// so only the two edges of the range are looked at element by
+    /// element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is decreasing or extends past the end of
+    /// the vector.
+    pub fn range_summary<R: RangeBounds<usize>>(&self, range: R) -> M::Summary {
+        let len = self.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        };
+        if start > end {
+            panic!("slice index starts at {} but ends at {}", start, end);
+        }
+        if end > len {
+            panic!("range end index {} out of range for DVec of length {}", end, len);
+        }
+
+        // Clamp the range to the head, the tree and the tail in turn,
+        // each relative to its own start.
+        let clamp = |offset: usize, part_len: usize| {
+            let start = cmp::min(start.saturating_sub(offset), part_len);
+            let end = cmp::min(end.saturating_sub(offset), part_len);
+            start..end
+        };
+
+        let head_len = self.head.len();
+        let head = measure_all::<T, M>(&self.head[clamp(0, head_len)]);
+        let tree = clamp(head_len, self.root_len.0);
+        let tree = if tree.start == tree.end {
+            M::identity()
+        } else {
+            self.root.as_ref().unwrap().range_summary(self.shift, self.root_len.0, tree.start, tree.end)
+        };
+        let tail = &self.tail[clamp(head_len + self.root_len.0, self.tail.len())];
+        M::combine(&M::combine(&head, &tree), &measure_all::<T, M>(tail))
+    }
+
+    /// Returns the first index `i` for which `pred` holds of the summary
+    /// of the elements `[0, i]`, or `None` if there is no such index.
+    /// `pred` must be monotone: once it holds for some prefix, it must
+    /// hold for every longer one. The search skips over whole subtrees
+    /// for which it does not hold yet, so it takes O(log n) time.
+    pub fn search<F>(&self, pred: F) -> Option<usize>
+        where F: Fn(&M::Summary) -> bool
+    {
+        let mut acc = M::identity();
+        for (i, element) in self.head.iter().enumerate() {
+            acc = M::combine(&acc, &M::measure(element));
+            if pred(&acc) {
+                return Some(i);
+            }
+        }
+
+        if let Some(ref root) = self.root {
+            let next = M::combine(&acc, root.summary());
+            if pred(&next) {
+                return Some(self.head.len() + root.search(self.shift, self.root_len.0, acc, &pred));
+            }
+            acc = next;
+        }
+
+        let offset = self.head.len() + self.root_len.0;
+        for (i, element) in self.tail.iter().enumerate() {
+            acc = M::combine(&acc, &M::measure(element));
+            if pred(&acc) {
+                return Some(offset + i);
+            }
+        }
+        None
+    }
+
+    /// Checks the summaries cached in the tree, which `assert_valid`
+    /// leaves alone since most measures cannot be compared.
+    #[cfg(test)]
+    pub(crate) fn assert_summaries(&self)
+        where M::Summary: PartialEq + fmt::Debug
+    {
+        if let Some(ref root) = self.root {
+            if let Err(err) = root.validate_summaries(&mut vec![]) {
+                panic!("validation error {}", err);
+            }
+        }
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Node<T, BITS, P, M> {
+    /// Returns the summary of the elements `[start, end)` of this
+    /// subtree, which holds `len` elements. The range must not be
+    /// empty.
+    fn range_summary(&self, shift: Shift, len: usize, start: usize, end: usize) -> M::Summary {
+        if start == 0 && end == len {
+            return self.summary().clone();
+        }
+
+        match *self {
+            Node::Leaf { ref elements, .. } => measure_all::<T, M>(&elements[start..end]),
+            Node::Branch { ref children, ref sizes, .. } => {
+                let (first, _) = position(sizes, shift, start);
+                let (last, _) = position(sizes, shift, end - 1);
+                let mut acc = M::identity();
+                for (i, child) in children.iter().enumerate().take(last + 1).skip(first) {
+                    let child_start = prefix_len(sizes, shift, len, i);
+                    let child_len = prefix_len(sizes, shift, len, i + 1) - child_start;
+                    let summary = child.range_summary(shift.dec(BITS),
+                                                      child_len,
+                                                      start.saturating_sub(child_start),
+                                                      cmp::min(end - child_start, child_len));
+                    acc = M::combine(&acc, &summary);
+                }
+                acc
+            }
+        }
+    }
+
+    /// Returns the index, within this subtree of `len` elements, of
+    /// the first element at which `pred` holds of `acc` combined with
+    /// the summary of the elements up to it. `pred` must hold of `acc`
+    /// combined with the summary of the whole subtree.
+    fn search<F>(&self, shift: Shift, len: usize, acc: M::Summary, pred: &F) -> usize
+        where F: Fn(&M::Summary) -> bool
+    {
+        let mut node = self;
+        let mut shift = shift;
+        let mut len = len;
+        let mut acc = acc;
+        let mut index = 0;
+        loop {
+            match *node {
+                Node::Branch { ref children, ref sizes, .. } => {
+                    // The last child is the one if none before it is.
+                    let last = children.len() - 1;
+                    let mut i = 0;
+                    while i < last {
+                        let next = M::combine(&acc, children[i].summary());
+                        if pred(&next) {
+                            break;
+                        }
+                        acc = next;
+                        i += 1;
+                    }
+                    let child_start = prefix_len(sizes, shift, len, i);
+                    len = prefix_len(sizes, shift, len, i + 1) - child_start;
+                    index += child_start;
+                    node = &children[i];
+                    shift = shift.dec(BITS);
+                }
+                Node::Leaf { ref elements, .. } => {
+                    for (i, element) in elements[..elements.len() - 1].iter().enumerate() {
+                        acc = M::combine(&acc, &M::measure(element));
+                        if pred(&acc) {
+                            return index + i;
+                        }
+                    }
+                    return index + elements.len() - 1;
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn validate_summaries(&self, path: &mut Vec<usize>) -> Result<(), String>
+        where M::Summary: PartialEq + fmt::Debug
+    {
+        let expected = match *self {
+            Node::Leaf { ref elements, .. } => measure_all::<T, M>(elements),
+            Node::Branch { ref children, .. } => {
+                for (i, child) in children.iter().enumerate() {
+                    path.push(i);
+                    child.validate_summaries(path)?;
+                    path.pop();
+                }
+                combine_all::<T, M, _>(children.iter().map(|child| child.summary()))
+            }
+        };
+        if expected != *self.summary() {
+            return Err(format!("at path {:?}, summary is {:?} but should be {:?}",
+                               path,
+                               self.summary(),
+                               expected));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,330 @@
+use std::cmp;
+use std::fmt::Debug;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::{DSumVec, Measure};
+use {ArcK, RcK, SharedPointerKind};
+
+/// A measure whose summaries the tests can work out from a slice of
+/// elements. `weight` must never decrease as elements are appended, so
+/// that `search` can look for the first prefix that outweighs a point.
+trait Model: Measure<u32> {
+    fn model(elements: &[u32]) -> Self::Summary;
+
+    fn weight(summary: &Self::Summary) -> u64;
+}
+
+struct Sum;
+
+impl Measure<u32> for Sum {
+    type Summary = u64;
+
+    fn identity() -> u64 {
+        0
+    }
+
+    fn measure(element: &u32) -> u64 {
+        *element as u64
+    }
+
+    fn combine(left: &u64, right: &u64) -> u64 {
+        left + right
+    }
+}
+
+impl Model for Sum {
+    fn model(elements: &[u32]) -> u64 {
+        elements.iter().map(|&x| x as u64).sum()
+    }
+
+    fn weight(summary: &u64) -> u64 {
+        *summary
+    }
+}
+
+/// The largest element and how many elements there are, to check that
+/// summaries start out from the right identity.
+struct MaxCount;
+
+impl Measure<u32> for MaxCount {
+    type Summary = (Option<u32>, usize);
+
+    fn identity() -> (Option<u32>, usize) {
+        (None, 0)
+    }
+
+    fn measure(element: &u32) -> (Option<u32>, usize) {
+        (Some(*element), 1)
+    }
+
+    fn combine(left: &(Option<u32>, usize), right: &(Option<u32>, usize)) -> (Option<u32>, usize) {
+        (left.0.max(right.0), left.1 + right.1)
+    }
+}
+
+impl Model for MaxCount {
+    fn model(elements: &[u32]) -> (Option<u32>, usize) {
+        (elements.iter().cloned().max(), elements.len())
+    }
+
+    fn weight(summary: &(Option<u32>, usize)) -> u64 {
+        summary.1 as u64
+    }
+}
+
+/// All of the elements, in order. Unlike the other measures, `combine`
+/// is not commutative, so this one catches summaries that are combined
+/// the wrong way around.
+struct Concat;
+
+impl Measure<u32> for Concat {
+    type Summary = Vec<u32>;
+
+    fn identity() -> Vec<u32> {
+        vec![]
+    }
+
+    fn measure(element: &u32) -> Vec<u32> {
+        vec![*element]
+    }
+
+    fn combine(left: &Vec<u32>, right: &Vec<u32>) -> Vec<u32> {
+        left.iter().chain(right).cloned().collect()
+    }
+}
+
+impl Model for Concat {
+    fn model(elements: &[u32]) -> Vec<u32> {
+        elements.to_vec()
+    }
+
+    // Weighted by position, so that the weight depends on the order too.
+    fn weight(summary: &Vec<u32>) -> u64 {
+        summary.iter().zip(1..).map(|(&x, i)| x as u64 * i).sum()
+    }
+}
+
+fn check<M, const BITS: usize, P>(vec: &DSumVec<u32, M, BITS, P>, model: &[u32])
+    where M: Model, M::Summary: PartialEq + Debug, P: SharedPointerKind
+{
+    vec.assert_valid();
+    vec.assert_summaries();
+    assert_eq!(vec.len(), model.len());
+    assert_eq!(vec.iter().len(), model.len());
+    assert!(vec.iter().eq(model.iter()));
+    assert_eq!(vec.summary(), M::model(model));
+}
+
+fn matches_vec<M, const BITS: usize, P>()
+    where M: Model, M::Summary: PartialEq + Debug, P: SharedPointerKind
+{
+    let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+    let mut vec = DSumVec::<u32, M, BITS, P>::default();
+    let mut model: Vec<u32> = vec![];
+    let mut snapshots = vec![];
+
+    for i in 0..5000 {
+        match rng.gen_range(0, 12) {
+            0 => assert_eq!(vec.pop(), model.pop()),
+            1 => {
+                let element = if model.is_empty() { None } else { Some(model.remove(0)) };
+                assert_eq!(vec.pop_front(), element);
+            }
+            2 if !model.is_empty() => {
+                let index = rng.gen_range(0, model.len());
+                let element = rng.gen_range(0, 100);
+                assert_eq!(vec.set(index, element), model[index]);
+                model[index] = element;
+            }
+            3 => {
+                let element = rng.gen_range(0, 100);
+                vec.push_front(element);
+                model.insert(0, element);
+            }
+            4 => {
+                let index = rng.gen_range(0, model.len() + 1);
+                let element = rng.gen_range(0, 100);
+                vec.insert(index, element);
+                model.insert(index, element);
+            }
+            5 if !model.is_empty() => {
+                let index = rng.gen_range(0, model.len());
+                assert_eq!(vec.remove(index), model.remove(index));
+            }
+            6 => {
+                let len = model.len().saturating_sub(rng.gen_range(0, 20));
+                vec.truncate(len);
+                model.truncate(len);
+            }
+            7 => {
+                let at = rng.gen_range(0, model.len() + 1);
+                let rest = vec.split_off(at);
+                check(&vec, &model[..at]);
+                check(&rest, &model[at..]);
+                vec.append(rest);
+            }
+            8 => {
+                let len = rng.gen_range(0, 100);
+                let elements: Vec<u32> = (0..len).map(|_| rng.gen_range(0, 100)).collect();
+                vec.append(elements.iter().cloned().collect());
+                model.extend(elements);
+            }
+            _ => {
+                let element = rng.gen_range(0, 100);
+                vec.push(element);
+                model.push(element);
+            }
+        }
+        assert_eq!(vec.len(), model.len());
+        assert_eq!(vec.summary(), M::model(&model));
+        if i % 250 == 0 {
+            check(&vec, &model);
+            snapshots.push((vec.clone(), model.clone()));
+        }
+    }
+
+    for (vec, model) in snapshots {
+        check(&vec, &model);
+        let collected: DSumVec<u32, M, BITS, P> = model.iter().cloned().collect();
+        check(&collected, &model);
+        assert_eq!(collected, vec);
+    }
+}
+
+test_instances!(matches_vec {
+    arc => [Sum, 5, ArcK],
+    rc => [Sum, 5, RcK],
+    narrow => [Sum, 2, ArcK],
+    concat => [Concat, 5, ArcK],
+    concat_narrow => [Concat, 2, RcK],
+});
+
+fn prefix_and_range_summary<M>()
+    where M: Model, M::Summary: PartialEq + Debug
+{
+    for &len in &[0, 1, 4, 5, 16, 17, 100, 257] {
+        let model: Vec<u32> = (0..len).map(|i| (i * 7919) % 100).collect();
+        let collected: DSumVec<u32, M, 2> = model.iter().cloned().collect();
+        // Pushing onto the front fills the head, and gives the branches
+        // along the left edge size tables.
+        let half = model.len() / 2;
+        let mut pushed: DSumVec<u32, M, 2> = model[half..].iter().cloned().collect();
+        for &element in model[..half].iter().rev() {
+            pushed.push_front(element);
+        }
+
+        for vec in &[collected, pushed] {
+            check(vec, &model);
+            for start in 0..=model.len() {
+                assert_eq!(vec.prefix(start), M::model(&model[..start]));
+                for end in start..=model.len() {
+                    assert_eq!(vec.range_summary(start..end), M::model(&model[start..end]));
+                }
+            }
+            assert_eq!(vec.range_summary(..), vec.summary());
+        }
+    }
+}
+
+test_instances!(prefix_and_range_summary {
+    max_count => [MaxCount],
+    concat => [Concat],
+});
+
+#[test]
+#[should_panic(expected = "out of range")]
+fn range_summary_past_the_end() {
+    let vec: DSumVec<u32, Sum> = (0..10).collect();
+    vec.range_summary(5..11);
+}
+
+fn search_finds_first_index<M>()
+    where M: Model, M::Summary: PartialEq + Debug
+{
+    let mut rng = XorShiftRng::from_seed([4, 5, 6, 7]);
+    // Zero weights are never picked, since the total does not grow there.
+    let model: Vec<u32> = (0..1000).map(|_| rng.gen_range(0, 3)).collect();
+    let collected: DSumVec<u32, M, 3> = model.iter().cloned().collect();
+    let mut pushed: DSumVec<u32, M, 3> = model[500..].iter().cloned().collect();
+    for &element in model[..500].iter().rev() {
+        pushed.push_front(element);
+    }
+    let weights: Vec<u64> = (1..=model.len()).map(|end| M::weight(&M::model(&model[..end]))).collect();
+
+    for vec in &[collected, pushed] {
+        let total = M::weight(&vec.summary());
+        for point in (0..total).step_by(cmp::max(1, total as usize / 3000)) {
+            let expected = weights.iter().position(|&weight| weight > point);
+            let index = vec.search(|summary| M::weight(summary) > point);
+            assert_eq!(index, expected);
+            assert!(model[index.unwrap()] > 0);
+        }
+        assert_eq!(vec.search(|summary| M::weight(summary) > total), None);
+        assert_eq!(vec.search(|_| true), Some(0));
+    }
+    assert_eq!(DSumVec::<u32, M>::default().search(|_| true), None);
+}
+
+test_instances!(search_finds_first_index {
+    sum => [Sum],
+    concat => [Concat],
+});
+
+#[test]
+fn updates_leave_snapshots_alone() {
+    let mut vec: DSumVec<u32, Sum> = (0..2000).collect();
+    let snapshot = vec.clone();
+    assert!(vec.ptr_eq(&snapshot));
+
+    for i in 0..1000 {
+        vec.update(i * 2, |x| *x += 1);
+    }
+    while vec.len() > 1500 {
+        vec.pop();
+    }
+    assert!(!vec.ptr_eq(&snapshot));
+    assert_eq!(vec.summary(), (0..1500).sum::<u64>() + 750);
+    assert_eq!(vec[10], 11);
+    assert_eq!(vec.get(1500), None);
+    check(&snapshot, &(0..2000).collect::<Vec<_>>());
+    assert_eq!(snapshot.prefix(1500), (0..1500).sum::<u64>());
+
+    vec.extend(vec![5, 6]);
+    assert_eq!(vec.len(), 1502);
+    assert_eq!(vec.range_summary(1500..), 11);
+}
+
+macro_rules! search {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use super::Sum;
+            use DSumVec;
+            use test_crate;
+            const N: u32 = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                let vec: DSumVec<u32, Sum> = (0 .. N).collect();
+                b.iter(|| {
+                    (0 .. 100).map(|i| vec.search(|&acc| acc > i * (N as u64) * 50)).count()
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                let vec: Vec<u32> = (0 .. N).collect();
+                b.iter(|| {
+                    (0 .. 100).map(|i| {
+                        vec.iter()
+                           .scan(0, |acc, &x| { *acc += x as u64; Some(*acc) })
+                           .position(|acc| acc > i * (N as u64) * 50)
+                    }).count()
+                });
+            }
+        }
+    }
+}
+
+search!(search_5000, 5000);
+search!(search_50000, 50000);
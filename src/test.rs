@@ -1,78 +1,1135 @@
-use super::DVec;
-use super::BRANCH_FACTOR;
+use rand::{Rng, XorShiftRng};
 
-#[test]
-fn push_matches_len() {
-    const N: usize = 5000;
-    let mut pv = DVec::new();
-    for i in 0..N {
-        pv.push(i);
-    }
-    assert_eq!(pv.len(), N);
+macro_rules! tests {
+    ($mod_name: ident, $BITS: expr, $P: ty) => {
+        mod $mod_name {
+            use std::ptr;
 
-    for i in 0..N {
-        assert_eq!(*pv.get(i).unwrap(), i);
-    }
-}
+            const BRANCH_FACTOR: usize = 1 << $BITS;
 
-#[test]
-fn push_matches_len_cloned() {
-    const N: usize = 5000;
-    let mut pv = DVec::new();
-    for i in 0..N {
-        pv.push(i);
-    }
-    let pv0 = pv.clone();
-    assert_eq!(pv.len(), N);
-    assert_eq!(pv0.len(), N);
+            type DVec<T> = ::DVec<T, $BITS, $P>;
 
-    for i in 0..N {
-        pv.push(i);
-    }
+            #[test]
+            fn push_matches_len() {
+                const N: usize = 5000;
+                let mut pv = DVec::default();
+                for i in 0..N {
+                    pv.push(i);
+                }
+                assert_eq!(pv.len(), N);
 
-    assert_eq!(pv.len(), 2 * N);
-    assert_eq!(pv0.len(), N);
+                for i in 0..N {
+                    assert_eq!(*pv.get(i).unwrap(), i);
+                }
+            }
 
-    for i in 0..N {
-        assert_eq!(*pv.get(i).unwrap(), i);
-        assert_eq!(*pv0.get(i).unwrap(), i);
-    }
+            #[test]
+            fn push_matches_len_cloned() {
+                const N: usize = 5000;
+                let mut pv = DVec::default();
+                for i in 0..N {
+                    pv.push(i);
+                }
+                let pv0 = pv.clone();
+                assert_eq!(pv.len(), N);
+                assert_eq!(pv0.len(), N);
 
-    for i in 0..N {
-        assert_eq!(*pv.get(i + N).unwrap(), i);
-    }
-}
+                for i in 0..N {
+                    pv.push(i);
+                }
 
-#[test]
-fn push_matches_mutate_in_place() {
-    const N: usize = BRANCH_FACTOR * 4;
-    let mut pv = DVec::new();
-    for i in 0..N {
-        pv.push(i);
-    }
-    let pv0 = pv.clone();
-    assert_eq!(pv.len(), N);
-    assert_eq!(pv0.len(), N);
+                assert_eq!(pv.len(), 2 * N);
+                assert_eq!(pv0.len(), N);
 
-    for i in 0..(N / 2) {
-        *pv.get_mut(i).unwrap() += 1;
-    }
+                for i in 0..N {
+                    assert_eq!(*pv.get(i).unwrap(), i);
+                    assert_eq!(*pv0.get(i).unwrap(), i);
+                }
+
+                for i in 0..N {
+                    assert_eq!(*pv.get(i + N).unwrap(), i);
+                }
+            }
+
+            #[test]
+            fn push_matches_mutate_in_place() {
+                const N: usize = BRANCH_FACTOR * 4;
+                let mut pv = DVec::default();
+                for i in 0..N {
+                    pv.push(i);
+                }
+                let pv0 = pv.clone();
+                assert_eq!(pv.len(), N);
+                assert_eq!(pv0.len(), N);
+
+                for i in 0..(N / 2) {
+                    *pv.get_mut(i).unwrap() += 1;
+                }
+
+                assert_eq!(pv.len(), N);
+                assert_eq!(pv0.len(), N);
+
+                for i in 0..(N / 2) {
+                    assert_eq!(*pv.get(i).unwrap(), i + 1);
+                    assert_eq!(*pv0.get(i).unwrap(), i);
+                }
+
+                // the second half ought to be untouched
+                for i in N / 2..N {
+                    assert_eq!(*pv.get(i).unwrap(), i);
+                    assert_eq!(*pv0.get(i).unwrap(), i);
+                    assert_eq!(pv.get(i).unwrap() as *const usize,
+                               pv0.get(i).unwrap() as *const usize);
+                }
+            }
+
+            #[test]
+            fn clone_shares_tail() {
+                let mut pv = dvec_range(0, BRANCH_FACTOR * 2 + 3);
+                let pv0 = pv.clone();
+                let last = pv.len() - 1;
+                assert!(ptr::eq(&pv[last], &pv0[last]));
+
+                pv.push(1000);
+                pv[last] += 1;
+                assert!(pv0.iter().cloned().eq(0..BRANCH_FACTOR * 2 + 3));
+                assert_eq!(pv[last], last + 1);
+                assert_eq!(pv.pop(), Some(1000));
+            }
+
+            #[test]
+            fn pop_matches_push() {
+                const N: usize = 5000;
+                let mut pv = DVec::default();
+                for i in 0..N {
+                    pv.push(i);
+                }
+
+                for i in (0..N).rev() {
+                    assert_eq!(pv.pop(), Some(i));
+                    assert_eq!(pv.len(), i);
+                    pv.assert_valid();
+                }
+                assert_eq!(pv.pop(), None);
+                assert!(pv.is_empty());
+            }
+
+            #[test]
+            fn pop_leaves_clone_untouched() {
+                const N: usize = BRANCH_FACTOR * BRANCH_FACTOR + 3;
+                let mut pv = DVec::default();
+                for i in 0..N {
+                    pv.push(i);
+                }
+                let pv0 = pv.clone();
+
+                for _ in 0..(N / 2) {
+                    pv.pop();
+                }
+                pv.assert_valid();
+                for i in 0..(N / 2) {
+                    pv.push(i + N);
+                }
+                pv.assert_valid();
+
+                assert_eq!(pv0.len(), N);
+                for i in 0..N {
+                    assert_eq!(*pv0.get(i).unwrap(), i);
+                }
+                for i in 0..(N - N / 2) {
+                    assert_eq!(*pv.get(i).unwrap(), i);
+                }
+                for i in 0..(N / 2) {
+                    assert_eq!(*pv.get(i + N - N / 2).unwrap(), i + N);
+                }
+            }
+
+            #[test]
+            fn truncate_to_every_length() {
+                const N: usize = BRANCH_FACTOR * BRANCH_FACTOR + BRANCH_FACTOR + 1;
+                let mut pv = DVec::default();
+                for i in 0..N {
+                    pv.push(i);
+                }
+
+                for len in (0..N + 1).rev() {
+                    let mut pv1 = pv.clone();
+                    pv1.truncate(len);
+                    pv1.assert_valid();
+                    assert_eq!(pv1.len(), len);
+                    for i in 0..len {
+                        assert_eq!(*pv1.get(i).unwrap(), i);
+                    }
+
+                    // the truncated vector can keep growing
+                    pv1.push(N);
+                    assert_eq!(*pv1.get(len).unwrap(), N);
+                }
+
+                assert_eq!(pv.len(), N);
+                pv.assert_valid();
+            }
+
+            fn dvec_range(start: usize, end: usize) -> DVec<usize> {
+                let mut pv = DVec::default();
+                for i in start..end {
+                    pv.push(i);
+                }
+                pv
+            }
+
+            #[test]
+            fn append_matches_push() {
+                let lens = [0, 1, 3, BRANCH_FACTOR - 1, BRANCH_FACTOR, BRANCH_FACTOR + 1,
+                            BRANCH_FACTOR * 3 + 2, BRANCH_FACTOR * BRANCH_FACTOR,
+                            BRANCH_FACTOR * BRANCH_FACTOR * 2 + 7, 5000];
+                for &n in &lens {
+                    for &m in &lens {
+                        let mut pv = dvec_range(0, n);
+                        pv.append(dvec_range(n, n + m));
+                        pv.assert_valid();
+                        assert_eq!(pv.len(), n + m);
+                        for i in 0..(n + m) {
+                            assert_eq!(*pv.get(i).unwrap(), i);
+                        }
+
+                        // the result keeps behaving like a vector
+                        pv.push(n + m);
+                        assert_eq!(pv.pop(), Some(n + m));
+                        pv.truncate((n + m) / 2);
+                        pv.assert_valid();
+                        for i in 0..pv.len() {
+                            assert_eq!(*pv.get(i).unwrap(), i);
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn append_many_small() {
+                // Repeatedly appending short, uneven vectors exercises the
+                // rebalancing of relaxed nodes.
+                let mut pv = DVec::default();
+                let mut len = 0;
+                for round in 0..300 {
+                    let n = (round * 7) % (BRANCH_FACTOR * 3) + 1;
+                    pv.append(dvec_range(len, len + n));
+                    len += n;
+                    pv.assert_valid();
+                }
+                for i in 0..len {
+                    assert_eq!(pv[i], i);
+                }
+
+                // and the other way around
+                let mut pv1 = DVec::default();
+                let mut start = len;
+                for round in 0..300 {
+                    let n = (round * 5) % (BRANCH_FACTOR * 3) + 1;
+                    let mut front = dvec_range(start - n.min(start), start);
+                    start -= front.len();
+                    front.append(pv1);
+                    pv1 = front;
+                    pv1.assert_valid();
+                }
+                for i in 0..pv1.len() {
+                    assert_eq!(pv1[i], start + i);
+                }
+            }
+
+            #[test]
+            fn append_shares_subtrees() {
+                const N: usize = BRANCH_FACTOR * BRANCH_FACTOR * 3;
+                let left = dvec_range(0, N);
+                let right = dvec_range(N, 2 * N);
+                let mut pv = left.clone();
+                pv.append(right.clone());
+
+                // Only the leaves along the seam may have been copied.
+                let shared_left = (0..N).filter(|&i| ptr::eq(&pv[i], &left[i])).count();
+                let shared_right = (0..N).filter(|&i| ptr::eq(&pv[N + i], &right[i])).count();
+                assert!(shared_left >= N - 2 * BRANCH_FACTOR);
+                assert!(shared_right >= N - 2 * BRANCH_FACTOR);
+
+                // and mutating the result leaves the inputs alone
+                for i in 0..(2 * N) {
+                    *pv.get_mut(i).unwrap() += 1;
+                }
+                for i in 0..N {
+                    assert_eq!(left[i], i);
+                    assert_eq!(right[i], N + i);
+                }
+            }
+
+            #[test]
+            fn split_off_at_every_index() {
+                const N: usize = BRANCH_FACTOR * BRANCH_FACTOR + BRANCH_FACTOR / 2;
+                let pv = dvec_range(0, N);
+                for at in 0..(N + 1) {
+                    let mut left = pv.clone();
+                    let right = left.split_off(at);
+                    left.assert_valid();
+                    right.assert_valid();
+                    assert_eq!(left.len(), at);
+                    assert_eq!(right.len(), N - at);
+                    for i in 0..at {
+                        assert_eq!(left[i], i);
+                    }
+                    for i in 0..(N - at) {
+                        assert_eq!(right[i], at + i);
+                    }
+
+                    // putting the halves back together restores the original
+                    left.append(right);
+                    left.assert_valid();
+                    for i in 0..N {
+                        assert_eq!(left[i], i);
+                    }
+                }
+            }
+
+            #[test]
+            fn slice_shares_subtrees() {
+                const N: usize = 5000;
+                let pv = dvec_range(0, N);
+                for &(start, end) in &[(0, N), (0, 0), (17, 17), (1, N - 1), (100, 2100), (N - 40, N)] {
+                    let slice = pv.slice(start..end);
+                    slice.assert_valid();
+                    assert_eq!(slice.len(), end - start);
+                    for i in 0..(end - start) {
+                        assert_eq!(slice[i], start + i);
+                    }
+                    let shared = (0..(end - start)).filter(|&i| ptr::eq(&slice[i], &pv[start + i])).count();
+                    assert!(shared + 2 * BRANCH_FACTOR >= end - start);
+                }
+
+                let (left, right) = pv.split_at(N / 3);
+                assert_eq!(left.len(), N / 3);
+                assert_eq!(right[0], N / 3);
+                assert_eq!(pv.slice(..).len(), N);
+                assert_eq!(pv.slice(10..=19)[9], 19);
+            }
+
+            #[test]
+            fn insert_remove_match_vec() {
+                use rand::{Rng, SeedableRng, XorShiftRng};
+
+                let mut rng = XorShiftRng::from_seed([0, 1, 2, 3]);
+                let mut pv = dvec_range(0, 1000);
+                let mut v: Vec<usize> = (0..1000).collect();
+                let pv0 = pv.clone();
+
+                for round in 0..2000 {
+                    if round % 3 == 0 {
+                        let index = rng.gen_range(0, v.len());
+                        assert_eq!(pv.remove(index), v.remove(index));
+                    } else {
+                        let index = rng.gen_range(0, v.len() + 1);
+                        pv.insert(index, round + 1000);
+                        v.insert(index, round + 1000);
+                    }
+                }
+
+                pv.assert_valid();
+                assert_eq!(pv.len(), v.len());
+                for i in 0..v.len() {
+                    assert_eq!(pv[i], v[i]);
+                }
+
+                // the original snapshot never noticed
+                assert_eq!(pv0.len(), 1000);
+                for i in 0..1000 {
+                    assert_eq!(pv0[i], i);
+                }
+            }
+
+            #[test]
+            fn push_front_matches_push() {
+                const N: usize = 5000;
+                let mut pv = DVec::default();
+                let mut snapshots = vec![];
+                for i in (0..N).rev() {
+                    pv.push_front(i);
+                    if i % 1000 == 0 {
+                        snapshots.push(pv.clone());
+                    }
+                }
+                pv.assert_valid();
+                assert_eq!(pv.len(), N);
+                for i in 0..N {
+                    assert_eq!(pv[i], i);
+                }
+                assert!(pv.iter().rev().cloned().eq((0..N).rev()));
+                assert!(pv.clone().into_iter().eq(0..N));
+
+                for (k, snapshot) in snapshots.iter().enumerate() {
+                    let start = N - 1 - k * 1000 - (N - 1) % 1000;
+                    assert!(snapshot.iter().cloned().eq(start..N));
+                }
+
+                for i in 0..N {
+                    assert_eq!(pv.pop_front(), Some(i));
+                }
+                assert_eq!(pv.pop_front(), None);
+                assert!(pv.is_empty());
+                pv.assert_valid();
+            }
+
+            #[test]
+            fn deque_matches_vecdeque() {
+                use std::collections::VecDeque;
+                use rand::{Rng, SeedableRng, XorShiftRng};
+
+                let mut rng = XorShiftRng::from_seed([4, 5, 6, 7]);
+                let mut pv = DVec::default();
+                let mut model = VecDeque::new();
+                let mut snapshots = vec![];
+
+                for round in 0..20000 {
+                    match rng.gen_range(0, 10) {
+                        0..=2 => {
+                            pv.push_front(round);
+                            model.push_front(round);
+                        }
+                        3..=5 => {
+                            pv.push(round);
+                            model.push_back(round);
+                        }
+                        6 | 7 => assert_eq!(pv.pop_front(), model.pop_front()),
+                        8 => assert_eq!(pv.pop(), model.pop_back()),
+                        _ => {
+                            let index = rng.gen_range(0, model.len() + 1);
+                            if rng.gen() && index < model.len() {
+                                assert_eq!(pv.remove(index), model.remove(index).unwrap());
+                            } else {
+                                pv.insert(index, round);
+                                model.insert(index, round);
+                            }
+                        }
+                    }
+                    assert_eq!(pv.len(), model.len());
+                    if round % 500 == 0 {
+                        pv.assert_valid();
+                        snapshots.push((pv.clone(), model.clone()));
+                    }
+                }
+
+                for (pv, model) in snapshots {
+                    pv.assert_valid();
+                    assert!(pv.iter().eq(model.iter()));
+                    assert!(pv.iter().rev().eq(model.iter().rev()));
+                    for i in (0..model.len()).step_by(7) {
+                        assert_eq!(pv[i], model[i]);
+                        assert_eq!(pv.iter().nth(i), model.get(i));
+                        assert_eq!(pv.iter().nth_back(i), model.iter().nth_back(i));
+                    }
+
+                    let mut updated = pv.clone();
+                    for element in updated.iter_mut() {
+                        *element += 1;
+                    }
+                    assert!(updated.into_iter().eq(model.iter().map(|&x| x + 1)));
+
+                    let len = model.len();
+                    let (left, right) = pv.split_at(len / 3);
+                    left.assert_valid();
+                    right.assert_valid();
+                    assert!(pv.slice(len / 4..len / 2).iter().eq(model.range(len / 4..len / 2)));
+                    let mut joined = left;
+                    joined.append(right);
+                    joined.assert_valid();
+                    assert!(joined.iter().eq(model.iter()));
+
+                    let mut extended = pv.clone();
+                    extended.extend(0..100);
+                    extended.assert_valid();
+                    assert!(extended.iter().eq(model.iter().chain(&(0..100).collect::<Vec<_>>())));
+                }
+            }
+
+            #[test]
+            fn append_and_truncate_with_heads() {
+                let mut left = DVec::default();
+                let mut right = DVec::default();
+                for i in 0..BRANCH_FACTOR / 2 {
+                    left.push_front(i);
+                    right.push_front(i + 100);
+                }
+                let mut both = left.clone();
+                both.append(right.clone());
+                both.assert_valid();
+                assert!(both.iter().eq(left.iter().chain(right.iter())));
+
+                right.extend(0..BRANCH_FACTOR * 3);
+                both.append(right.clone());
+                both.assert_valid();
+                assert_eq!(both.len(), BRANCH_FACTOR * 3 / 2 + BRANCH_FACTOR * 3);
+                assert!(both.iter().skip(BRANCH_FACTOR).eq(right.iter()));
+
+                for len in (0..both.len()).rev() {
+                    let mut pv = both.clone();
+                    pv.truncate(len);
+                    pv.assert_valid();
+                    assert!(pv.iter().eq(both.iter().take(len)));
+                }
+            }
+
+            #[test]
+            fn iter_matches_index() {
+                // one dense vector, and one built by concatenation so that it has
+                // relaxed nodes and partial leaves
+                let mut relaxed = DVec::default();
+                for chunk in 0..40 {
+                    let start = relaxed.len();
+                    relaxed.append(dvec_range(start, start + chunk * 3 + 1));
+                }
+                for pv in &[dvec_range(0, 5000), relaxed] {
+                    let n = pv.len();
+                    assert!(pv.iter().cloned().eq(0..n));
+                    assert!(pv.iter().rev().cloned().eq((0..n).rev()));
+                    assert_eq!(pv.iter().len(), n);
+
+                    // alternate between the two ends until they meet
+                    let mut iter = pv.iter();
+                    for i in 0..(n / 2) {
+                        assert_eq!(iter.next(), Some(&i));
+                        assert_eq!(iter.next_back(), Some(&(n - 1 - i)));
+                        assert_eq!(iter.len(), n - 2 * (i + 1));
+                    }
+                    assert_eq!(iter.next().cloned(), if n % 2 == 1 { Some(n / 2) } else { None });
+                    assert_eq!(iter.next(), None);
+                    assert_eq!(iter.next_back(), None);
+
+                    for &step in &[0, 1, 5, BRANCH_FACTOR, 3 * BRANCH_FACTOR + 1, 1000] {
+                        let mut iter = pv.iter();
+                        let mut expected = 0..n;
+                        loop {
+                            let next = iter.nth(step);
+                            assert_eq!(next.cloned(), expected.nth(step));
+                            assert_eq!(iter.len(), expected.len());
+                            let back = iter.nth_back(step / 2);
+                            assert_eq!(back.cloned(), expected.nth_back(step / 2));
+                            if next.is_none() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn into_iter_moves_unique_leaves() {
+                use std::sync::atomic::{AtomicUsize, Ordering};
+
+                static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+                #[derive(Debug, PartialEq)]
+                struct Counted(usize);
+
+                impl Clone for Counted {
+                    fn clone(&self) -> Self {
+                        CLONES.fetch_add(1, Ordering::SeqCst);
+                        Counted(self.0)
+                    }
+                }
+
+                const N: usize = BRANCH_FACTOR * BRANCH_FACTOR * 2 + 5;
+                let mut pv = DVec::default();
+                for i in 0..N {
+                    pv.push(Counted(i));
+                }
+
+                // Clone the vector, then update the second half of it: the first
+                // half of the tree is still shared, the second half is not.
+                let pv0 = pv.clone();
+                for i in (N / 2)..N {
+                    pv.get_mut(i).unwrap().0 += N;
+                }
+                CLONES.store(0, Ordering::SeqCst);
+
+                let mut iter = pv.into_iter();
+                assert_eq!(iter.len(), N);
+                assert_eq!(iter.next_back(), Some(Counted(2 * N - 1)));
+                let elements: Vec<usize> = iter.map(|c| c.0).collect();
+                assert!(elements.iter().cloned().eq((0..(N / 2)).chain((N / 2 + N)..(2 * N - 1))));
+
+                // only leaves shared with `pv0` were cloned
+                let clones = CLONES.load(Ordering::SeqCst);
+                assert!(clones <= N / 2 + BRANCH_FACTOR, "{} clones", clones);
+                CLONES.store(0, Ordering::SeqCst);
+
+                // now `pv0` is the sole owner of everything
+                assert!(pv0.into_iter().map(|c| c.0).eq(0..N));
+                assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+            }
+
+            #[test]
+            fn iter_mut_copies_shared_leaves_once() {
+                let mut relaxed = dvec_range(0, 1000);
+                relaxed.append(dvec_range(1000, 2500));
+                for pv in &mut [dvec_range(0, 2500), relaxed] {
+                    let pv0 = pv.clone();
+                    for element in pv.iter_mut() {
+                        *element += 1;
+                    }
+                    {
+                        // both ends at once
+                        let mut iter = pv.iter_mut();
+                        while let Some(front) = iter.next() {
+                            *front *= 2;
+                            if let Some(back) = iter.next_back() {
+                                *back *= 2;
+                            }
+                        }
+                    }
+                    pv.assert_valid();
+                    assert!(pv.iter().cloned().eq((0..2500).map(|i| (i + 1) * 2)));
+                    assert!(pv0.iter().cloned().eq(0..2500));
+                }
+            }
+
+            #[test]
+            fn transient_matches_push() {
+                let mut relaxed = dvec_range(0, 1000);
+                relaxed.append(dvec_range(1000, 1100));
+                for pv in &[DVec::default(), dvec_range(0, 1100), relaxed] {
+                    let start = pv.len();
+                    let mut transient = pv.clone().transient();
+                    for i in start..start + 40000 {
+                        transient.push(i);
+                        assert_eq!(transient.len(), i + 1);
+                    }
+                    for i in 0..transient.len() {
+                        assert_eq!(transient[i], i);
+                        transient[i] *= 2;
+                    }
+                    // pop back down across leaves, subtrees and into the prefix
+                    for i in (start / 2..start + 40000).rev() {
+                        assert_eq!(transient.pop(), Some(i * 2));
+                    }
+                    for i in start / 2..start + 5000 {
+                        transient.push(i * 2);
+                    }
+                    let new = transient.persistent();
+                    new.assert_valid();
+                    assert!(new.iter().cloned().eq((0..start + 5000).map(|i| i * 2)));
+                    assert!(pv.iter().cloned().eq(0..start));
+                }
+            }
+
+            #[test]
+            fn transient_pop_to_every_length() {
+                const N: usize = BRANCH_FACTOR * BRANCH_FACTOR;
+                for len in 0..N {
+                    let mut transient = DVec::default().transient();
+                    transient.extend(0..N);
+                    for i in (len..N).rev() {
+                        assert_eq!(transient.pop(), Some(i));
+                    }
+                    let pv = transient.persistent();
+                    pv.assert_valid();
+                    assert!(pv.iter().cloned().eq(0..len));
+                }
+            }
+
+            #[test]
+            fn collect_matches_push() {
+                for &n in &[0, 1, BRANCH_FACTOR, BRANCH_FACTOR * BRANCH_FACTOR + 1, 40000] {
+                    let vec: Vec<usize> = (0..n).collect();
+                    let pvs: [DVec<usize>; 4] = [
+                        (0..n).collect(),
+                        (0..n).filter(|_| true).collect(), // no exact size hint
+                        DVec::from(vec.clone()),
+                        DVec::from(&vec[..]),
+                    ];
+                    for pv in &pvs {
+                        pv.assert_valid();
+                        assert_eq!(pv.len(), n);
+                        assert!(pv.iter().cloned().eq(0..n));
+                    }
+                }
+                assert!(DVec::<usize>::default().is_empty());
+            }
+
+            #[test]
+            fn extend_matches_push() {
+                let mut relaxed = dvec_range(0, 1000);
+                relaxed.append(dvec_range(1000, 1100));
+                for pv in &[DVec::default(), dvec_range(0, 1), dvec_range(0, 1100), relaxed] {
+                    let start = pv.len();
+                    let mut new = pv.clone();
+                    new.extend(start..start + 3);
+                    new.extend(&[start + 3, start + 4]);
+                    new.extend(start + 5..start + 20000);
+                    new.assert_valid();
+                    assert!(new.iter().cloned().eq(0..start + 20000));
+                    assert!(pv.iter().cloned().eq(0..start));
+                }
+            }
+
+            #[test]
+            fn read_only_needs_no_clone() {
+                // neither Clone nor Debug
+                type Op = Box<dyn Fn(usize) -> usize>;
+                let pv: DVec<Op> = (0..2000).map(|i: usize| Box::new(move |x| x + i) as Op).collect();
+                assert_eq!(pv.len(), 2000);
+                assert_eq!(pv[1500](1), 1501);
+                assert_eq!(pv.get(1999).map(|f| f(0)), Some(1999));
+                assert!(pv.iter().map(|f| f(0)).eq(0..2000));
+
+                let mut transient = ::TransientDVec::<Op, $BITS, $P>::default();
+                transient.push(Box::new(|x| x * 2));
+                assert_eq!(transient[0](2), 4);
+                let pv: DVec<Op> = DVec::from(vec![Box::new(|x: usize| x * 3) as Op]);
+                assert_eq!(pv[0](2), 6);
+            }
+
+            /// Applies a random edit to both a vector and its model.
+            fn random_edit<R: ::rand::Rng>(rng: &mut R, pv: &mut DVec<usize>, model: &mut Vec<usize>) {
+                let value = rng.gen_range(0, 1000000);
+                match rng.gen_range(0, 10) {
+                    0..=4 if !model.is_empty() => {
+                        let index = rng.gen_range(0, model.len());
+                        pv[index] = value;
+                        model[index] = value;
+                    }
+                    5 => {
+                        pv.push(value);
+                        model.push(value);
+                    }
+                    6 => assert_eq!(pv.pop(), model.pop()),
+                    7 => {
+                        let len = model.len() - rng.gen_range(0, model.len().min(BRANCH_FACTOR * 3) + 1);
+                        pv.truncate(len);
+                        model.truncate(len);
+                    }
+                    8 => {
+                        pv.extend(0..BRANCH_FACTOR * 5);
+                        model.extend(0..BRANCH_FACTOR * 5);
+                    }
+                    _ => {
+                        // Moves everything by one, so nothing lines up.
+                        pv.push_front(value);
+                        model.insert(0, value);
+                    }
+                }
+            }
+
+            fn model_diff<'a>(old: &'a [usize], new: &'a [usize]) -> Vec<::DiffItem<&'a usize>> {
+                (0..::std::cmp::max(old.len(), new.len())).filter_map(|index| {
+                    match (old.get(index), new.get(index)) {
+                        (Some(old), Some(new)) if old != new => Some(::DiffItem::Changed { index, old, new }),
+                        (Some(value), None) => Some(::DiffItem::Removed { index, value }),
+                        (None, Some(value)) => Some(::DiffItem::Appended { index, value }),
+                        _ => None,
+                    }
+                }).collect()
+            }
+
+            #[test]
+            fn diff_matches_elementwise() {
+                use rand::{Rng, SeedableRng, XorShiftRng};
+
+                let mut rng = XorShiftRng::from_seed([8, 9, 10, 11]);
+                let mut pv: DVec<usize> = (0..5000).collect();
+                let mut model: Vec<usize> = (0..5000).collect();
+                let mut versions = vec![];
+
+                for round in 0..300 {
+                    for _ in 0..rng.gen_range(0, 5) {
+                        random_edit(&mut rng, &mut pv, &mut model);
+                    }
+                    if round % 10 == 0 {
+                        versions.push((pv.clone(), model.clone()));
+                    }
+                }
+                let half = model.len() / 2;
+                versions.push((pv.split_at(half).0, model[..half].to_vec()));
+                versions.push((DVec::default(), vec![]));
+
+                for (old, old_model) in &versions {
+                    for (new, new_model) in &versions {
+                        let diff: Vec<_> = old.diff(new).collect();
+                        assert_eq!(diff, model_diff(old_model, new_model));
+                        let (lower, upper) = old.diff(new).size_hint();
+                        assert!(lower <= diff.len() && diff.len() <= upper.unwrap());
+                    }
+                }
+            }
+
+            #[test]
+            fn diff_skips_shared_subtrees() {
+                use std::cell::Cell;
+
+                thread_local!(static COMPARISONS: Cell<usize> = Cell::new(0));
+
+                #[derive(Clone, Debug)]
+                struct Counted(usize);
+
+                impl PartialEq for Counted {
+                    fn eq(&self, other: &Counted) -> bool {
+                        COMPARISONS.with(|c| c.set(c.get() + 1));
+                        self.0 == other.0
+                    }
+                }
+
+                let old: DVec<Counted> = (0..100000).map(Counted).collect();
+                let mut new = old.clone();
+                for i in 0..10 {
+                    new[i * 9973] = Counted(0);
+                }
+                new.pop();
+                new.push_front(Counted(0));
+                new.pop_front();
+
+                COMPARISONS.with(|c| c.set(0));
+                let indices: Vec<_> = old.diff(&new).map(|item| item.index()).collect();
+                let mut expected: Vec<_> = (1..10).map(|i| i * 9973).collect();
+                expected.push(99999);
+                assert_eq!(indices, expected);
+                // Each changed leaf, plus the heads and tails.
+                assert!(COMPARISONS.with(|c| c.get()) <= 12 * BRANCH_FACTOR);
+            }
+
+            #[test]
+            fn merge3_matches_model() {
+                use rand::{Rng, SeedableRng, XorShiftRng};
+
+                fn model_merge3<F>(base: &[usize], ours: &[usize], theirs: &[usize], mut resolve: F) -> Vec<usize>
+                    where F: FnMut(usize, Option<&usize>, Option<&usize>, Option<&usize>) -> Option<usize>
+                {
+                    let mut merged = vec![];
+                    for index in 0..base.len().max(ours.len()).max(theirs.len()) {
+                        let (b, o, t) = (base.get(index), ours.get(index), theirs.get(index));
+                        let element = if o == t {
+                            o.cloned()
+                        } else if o == b {
+                            t.cloned()
+                        } else if t == b {
+                            o.cloned()
+                        } else {
+                            resolve(index, b, o, t)
+                        };
+                        merged.extend(element);
+                    }
+                    merged
+                }
+
+                // Prefers the larger element; on every seventh conflict,
+                // leaves the index out.
+                fn resolver() -> impl FnMut(usize, Option<&usize>, Option<&usize>, Option<&usize>) -> Option<usize> {
+                    let mut conflicts = 0;
+                    move |_, _, o, t| {
+                        conflicts += 1;
+                        if conflicts % 7 == 0 { None } else { ::std::cmp::max(o, t).cloned() }
+                    }
+                }
+
+                let mut rng = XorShiftRng::from_seed([12, 13, 14, 15]);
+                for round in 0..100 {
+                    let len = rng.gen_range(0, BRANCH_FACTOR * BRANCH_FACTOR * 3);
+                    let base: DVec<usize> = (0..len).collect();
+                    let base_model: Vec<usize> = (0..len).collect();
+                    let (mut ours, mut ours_model) = (base.clone(), base_model.clone());
+                    let (mut theirs, mut theirs_model) = (base.clone(), base_model.clone());
+                    for _ in 0..rng.gen_range(0, 10) {
+                        random_edit(&mut rng, &mut ours, &mut ours_model);
+                        random_edit(&mut rng, &mut theirs, &mut theirs_model);
+                    }
+                    if round % 10 == 0 {
+                        // Both sides make the same change.
+                        ours.push(7);
+                        ours_model.push(7);
+                        theirs.push(7);
+                        theirs_model.push(7);
+                    }
+
+                    let merged = DVec::merge3(&base, &ours, &theirs, resolver());
+                    merged.assert_valid();
+                    let expected = model_merge3(&base_model, &ours_model, &theirs_model, resolver());
+                    assert!(merged.iter().eq(expected.iter()));
+                    assert!(ours.iter().eq(ours_model.iter()));
+                }
+
+                let base: DVec<usize> = (0..100).collect();
+                let mut theirs = base.clone();
+                theirs[3] = 0;
+                let merged = DVec::merge3(&base, &base, &theirs, |_, _, _, _| unreachable!());
+                assert!(merged.iter().eq(theirs.iter()));
+
+                // An edit on one side of an index the other side removed
+                // is a conflict, however far past the removal it is.
+                let mut ours = base.clone();
+                ours[90] = 0;
+                let theirs = base.slice(..10);
+                let mut conflicts = vec![];
+                let merged = DVec::merge3(&base, &ours, &theirs, |index, b, o, t| {
+                    conflicts.push((index, b.cloned(), o.cloned(), t.cloned()));
+                    o.cloned()
+                });
+                assert_eq!(conflicts, vec![(90, Some(90), Some(0), None)]);
+                assert!(merged.iter().eq([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0].iter()));
+            }
+
+            #[test]
+            fn content_hash_ignores_layout() {
+                let model: Vec<usize> = (0..BRANCH_FACTOR * BRANCH_FACTOR * 5).collect();
+                let mut pushed = DVec::default();
+                for &x in &model {
+                    pushed.push(x);
+                }
+                let collected: DVec<usize> = model.iter().cloned().collect();
+                let mut appended = DVec::default();
+                for chunk in model.chunks(7) {
+                    appended.append(chunk.iter().cloned().collect());
+                }
+                let mut fronted = DVec::default();
+                for &x in model.iter().rev() {
+                    fronted.push_front(x);
+                }
+
+                let hash = pushed.content_hash();
+                for vec in &[collected, appended, fronted] {
+                    assert_eq!(vec.content_hash(), hash);
+                }
+                assert_ne!(pushed.split_at(model.len() / 2).0.content_hash(), hash);
+                assert_ne!(DVec::<usize>::default().content_hash(), hash);
+                assert_ne!(DVec::<usize>::default().content_hash(), DVec::from(vec![0]).content_hash());
+            }
+
+            #[test]
+            fn content_hash_follows_updates() {
+                use rand::{Rng, SeedableRng, XorShiftRng};
+
+                let mut rng = XorShiftRng::from_seed([16, 17, 18, 19]);
+                let mut pv: DVec<usize> = (0..3000).collect();
+                let mut model: Vec<usize> = (0..3000).collect();
+                let mut snapshots = vec![];
+
+                for round in 0..300 {
+                    match rng.gen_range(0, 4) {
+                        0 => random_edit(&mut rng, &mut pv, &mut model),
+                        1 => {
+                            for element in pv.iter_mut().skip(round) {
+                                *element += 1;
+                            }
+                            for element in model.iter_mut().skip(round) {
+                                *element += 1;
+                            }
+                        }
+                        2 if !model.is_empty() => {
+                            let index = rng.gen_range(0, model.len());
+                            assert_eq!(pv.remove(index), model.remove(index));
+                            pv.insert(index / 2, round);
+                            model.insert(index / 2, round);
+                        }
+                        _ => {
+                            let len = model.len();
+                            let mut rest = pv.split_off(len / 2);
+                            rest.append(pv);
+                            pv = rest;
+                            model.rotate_left(len / 2);
+                        }
+                    }
+                    let fresh: DVec<usize> = model.iter().cloned().collect();
+                    assert_eq!(pv.content_hash(), fresh.content_hash());
+                    if round % 20 == 0 {
+                        snapshots.push((pv.clone(), pv.content_hash()));
+                    }
+                }
+                for (pv, hash) in snapshots {
+                    assert_eq!(pv.content_hash(), hash);
+                }
+            }
+
+            #[test]
+            #[cfg(feature = "cache-hashes")]
+            fn eq_uses_known_content_hashes() {
+                use std::cell::Cell;
+                use std::hash::{Hash, Hasher};
+
+                thread_local!(static COMPARISONS: Cell<usize> = Cell::new(0));
+
+                #[derive(Clone, Debug)]
+                struct Counted(usize);
+
+                impl PartialEq for Counted {
+                    fn eq(&self, other: &Counted) -> bool {
+                        COMPARISONS.with(|c| c.set(c.get() + 1));
+                        self.0 == other.0
+                    }
+                }
+
+                impl Hash for Counted {
+                    fn hash<H: Hasher>(&self, state: &mut H) {
+                        self.0.hash(state);
+                    }
+                }
+
+                let old: DVec<Counted> = (0..10000).map(Counted).collect();
+                let mut new = old.clone();
+                new[9000] = Counted(0);
+                old.content_hash();
+                new.content_hash();
+
+                COMPARISONS.with(|c| c.set(0));
+                assert!(old != new);
+                assert_eq!(COMPARISONS.with(|c| c.get()), 0);
+
+                new[9000] = Counted(9000);
+                assert_eq!(new.content_hash(), old.content_hash());
+                assert!(old == new);
+            }
+
+            #[test]
+            fn comparisons_match_vec() {
+                use rand::{SeedableRng, XorShiftRng};
+
+                let mut rng = XorShiftRng::from_seed([20, 21, 22, 23]);
+                let mut versions = vec![];
+                let mut pv: DVec<usize> = (0..2000).collect();
+                let mut model: Vec<usize> = (0..2000).collect();
+                for round in 0..200 {
+                    random_edit(&mut rng, &mut pv, &mut model);
+                    if round % 10 == 0 {
+                        // The same elements, laid out differently.
+                        let mut appended: DVec<usize> = model[..model.len() / 3].iter().cloned().collect();
+                        appended.append(model[model.len() / 3..].iter().cloned().collect());
+                        versions.push((appended, model.clone()));
+                        versions.push((pv.clone(), model.clone()));
+                    }
+                }
+
+                for (a, a_model) in &versions {
+                    for (b, b_model) in &versions {
+                        assert_eq!(a == b, a_model == b_model);
+                        assert_eq!(a.cmp(b), a_model.cmp(b_model));
+                        assert_eq!(a.partial_cmp(b), a_model.partial_cmp(b_model));
+                    }
+                    assert!(*a == *a_model && *a_model == *a);
+                    assert!(*a == a_model[..] && a_model[..] == *a);
+                    assert!(*a == &a_model[..]);
+                    assert!(*a != a_model[1..]);
+                }
+
+                let floats: DVec<f64> = vec![1.0, f64::NAN, 3.0].into_iter().collect();
+                let mut other = floats.clone();
+                assert!(floats != other);
+                assert_eq!(floats.partial_cmp(&other), None);
+                other[0] = 0.0;
+                assert_eq!(floats.partial_cmp(&other), Some(::std::cmp::Ordering::Greater));
+
+                let small: DVec<u32> = (1..4).collect();
+                assert!(small == [1, 2, 3] && [1, 2, 3] == small);
+                assert!(small != [1, 2] && small != [1, 2, 4]);
+            }
 
-    assert_eq!(pv.len(), N);
-    assert_eq!(pv0.len(), N);
+            #[test]
+            fn comparisons_skip_shared_subtrees() {
+                use std::cell::Cell;
+                use std::cmp::Ordering;
+
+                thread_local!(static COMPARISONS: Cell<usize> = Cell::new(0));
+
+                #[derive(Clone, Debug, Eq)]
+                struct Counted(usize);
+
+                impl PartialEq for Counted {
+                    fn eq(&self, other: &Counted) -> bool {
+                        COMPARISONS.with(|c| c.set(c.get() + 1));
+                        self.0 == other.0
+                    }
+                }
+
+                impl PartialOrd for Counted {
+                    fn partial_cmp(&self, other: &Counted) -> Option<Ordering> {
+                        Some(self.cmp(other))
+                    }
+                }
+
+                impl Ord for Counted {
+                    fn cmp(&self, other: &Counted) -> Ordering {
+                        COMPARISONS.with(|c| c.set(c.get() + 1));
+                        self.0.cmp(&other.0)
+                    }
+                }
 
-    for i in 0..(N / 2) {
-        assert_eq!(*pv.get(i).unwrap(), i + 1);
-        assert_eq!(*pv0.get(i).unwrap(), i);
+                let old: DVec<Counted> = (0..100000).map(Counted).collect();
+                let mut new = old.clone();
+                new[99000] = Counted(0);
+                let copy = old.clone();
+
+                COMPARISONS.with(|c| c.set(0));
+                assert_eq!(old.cmp(&copy), Ordering::Equal);
+                assert_eq!(old.cmp(&new), Ordering::Greater);
+                assert_eq!(new.cmp(&old), Ordering::Less);
+                new.pop();
+                assert_eq!(new.cmp(&old), Ordering::Less);
+                // Only the changed leaf, the tails and the element that differs.
+                assert!(COMPARISONS.with(|c| c.get()) <= 20 * BRANCH_FACTOR);
+            }
+
+            #[test]
+            fn nan_in_shared_subtree_is_not_equal() {
+                let mut floats: DVec<f64> = (0..1000).map(|i| i as f64).collect();
+                floats[10] = f64::NAN;
+                let copy = floats.clone();
+                assert!(floats != copy);
+                assert_eq!(floats.partial_cmp(&copy), None);
+                assert!(floats != floats.iter().cloned().collect::<Vec<_>>());
+            }
+
+            #[test]
+            #[cfg(feature = "serde")]
+            fn serde_round_trip() {
+                use serde_test::{assert_tokens, Token};
+
+                let empty: DVec<u32> = DVec::default();
+                assert_tokens(&empty, &[Token::Seq { len: Some(0) }, Token::SeqEnd]);
+                let small: DVec<u32> = (1..3).collect();
+                assert_tokens(&small, &[Token::Seq { len: Some(2) }, Token::U32(1), Token::U32(2), Token::SeqEnd]);
+
+                for &len in &[0, 1, BRANCH_FACTOR, BRANCH_FACTOR * BRANCH_FACTOR + 1, 5000] {
+                    let model: Vec<usize> = (0..len).collect();
+                    let mut vec: DVec<usize> = model.iter().cloned().collect();
+                    vec.push_front(7);
+                    let json = ::serde_json::to_string(&vec).unwrap();
+                    let mut expected = vec![7];
+                    expected.extend(&model);
+                    assert_eq!(json, ::serde_json::to_string(&expected).unwrap());
+
+                    let read: DVec<usize> = ::serde_json::from_str(&json).unwrap();
+                    read.assert_valid();
+                    assert_eq!(read, expected);
+                }
+                assert!(::serde_json::from_str::<DVec<usize>>("{}").is_err());
+            }
+        }
     }
+}
+
+// With a branching factor of 4 the trees get deep quickly, so the
+// same tests exercise many more levels.
+tests!(bits_2, 2, ::ArcK);
+tests!(bits_5, 5, ::ArcK);
+tests!(bits_2_local, 2, ::RcK);
 
-    // the second half ought to be untouched
-    for i in N / 2..N {
-        assert_eq!(*pv.get(i).unwrap(), i);
-        assert_eq!(*pv0.get(i).unwrap(), i);
-        assert_eq!(pv.get(i).unwrap() as *const usize,
-                   pv0.get(i).unwrap() as *const usize);
+/// Overwrites one to three random bytes of `bytes`, for the tests of
+/// how the readers of the on-disk formats cope with damaged input.
+pub fn damage(rng: &mut XorShiftRng, bytes: &[u8]) -> Vec<u8> {
+    let mut damaged = bytes.to_vec();
+    for _ in 0..rng.gen_range(1, 4) {
+        let index = rng.gen_range(0, damaged.len());
+        damaged[index] = rng.gen();
     }
+    damaged
+}
+
+#[test]
+fn sync_vectors_are_send() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<::DVecSync<u32>>();
+    assert_send_sync::<::DVec<u32>>();
 }
 
 macro_rules! push {
@@ -92,6 +1149,22 @@ macro_rules! push {
                 });
             }
 
+            #[bench]
+            fn transient(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut vec = DVec::new().transient();
+                    for i in 0 .. N {
+                        vec.push(i);
+                    }
+                    vec.persistent()
+                });
+            }
+
+            #[bench]
+            fn collect(b: &mut test_crate::Bencher) {
+                b.iter(|| (0 .. N).collect::<DVec<_>>());
+            }
+
             #[bench]
             fn standard(b: &mut test_crate::Bencher) {
                 b.iter(|| {
@@ -109,13 +1182,49 @@ push!(push_5000, 5000);
 push!(push_50000, 50000);
 push!(push_500000, 500000);
 
-macro_rules! push_clone {
+macro_rules! push_front {
     ($mod_name: ident, $N: expr) => {
         mod $mod_name {
+            use std::collections::VecDeque;
             use DVec;
             use test_crate;
             const N: usize = $N;
 
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut vec = DVec::new();
+                    for i in 0 .. N {
+                        vec.push_front(i);
+                    }
+                    vec
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut vec = VecDeque::new();
+                    for i in 0 .. N {
+                        vec.push_front(i);
+                    }
+                    vec
+                });
+            }
+        }
+    }
+}
+
+push_front!(push_front_5000, 5000);
+push_front!(push_front_50000, 50000);
+
+macro_rules! push_clone {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use {DVec, DVecLocal};
+            use test_crate;
+            const N: usize = $N;
+
             #[bench]
             fn dogged(b: &mut test_crate::Bencher) {
                 b.iter(|| {
@@ -129,6 +1238,44 @@ macro_rules! push_clone {
                 });
             }
 
+            #[bench]
+            fn dogged_local(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut vec: DVecLocal<usize> = DVecLocal::default();
+                    let mut vec1 = vec.clone();
+                    for i in 0 .. N {
+                        vec.push(i);
+                        vec1 = vec.clone();
+                    }
+                    drop(vec1);
+                });
+            }
+
+            #[bench]
+            fn dogged_string(b: &mut test_crate::Bencher) {
+                b.iter(|| {
+                    let mut vec = DVec::new();
+                    let mut vec1 = vec.clone();
+                    for i in 0 .. N {
+                        vec.push(i.to_string());
+                        vec1 = vec.clone();
+                    }
+                    drop(vec1);
+                });
+            }
+
+            #[bench]
+            fn dogged_string_snapshots(b: &mut test_crate::Bencher) {
+                let mut vec = DVec::new();
+                for i in 0 .. N {
+                    vec.push(i.to_string());
+                }
+                b.iter(|| {
+                    let snapshots: Vec<_> = (0 .. N).map(|_| vec.clone()).collect();
+                    snapshots
+                });
+            }
+
             #[bench]
             fn standard(b: &mut test_crate::Bencher) {
                 b.iter(|| {
@@ -189,6 +1336,89 @@ index_sequentially!(index_sequentially_5000, 5000);
 index_sequentially!(index_sequentially_50000, 50000);
 index_sequentially!(index_sequentially_500000, 500000);
 
+macro_rules! iterate {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use DVec;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                let mut vec = DVec::new();
+                for i in 0 .. N {
+                    vec.push(i * 2);
+                }
+                b.iter(|| {
+                    for (i, &x) in vec.iter().enumerate() {
+                        assert_eq!(x, i * 2);
+                    }
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                let mut vec = Vec::new();
+                for i in 0 .. N {
+                    vec.push(i * 2);
+                }
+                b.iter(|| {
+                    for (i, &x) in vec.iter().enumerate() {
+                        assert_eq!(x, i * 2);
+                    }
+                });
+            }
+        }
+    }
+}
+
+iterate!(iterate_5000, 5000);
+iterate!(iterate_50000, 50000);
+iterate!(iterate_500000, 500000);
+
+macro_rules! update_cloned {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use DVec;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn get_mut(b: &mut test_crate::Bencher) {
+                let mut vec = DVec::new();
+                for i in 0 .. N {
+                    vec.push(i);
+                }
+                b.iter(|| {
+                    let mut vec = vec.clone();
+                    for i in 0 .. N {
+                        *vec.get_mut(i).unwrap() += 1;
+                    }
+                    vec
+                });
+            }
+
+            #[bench]
+            fn iter_mut(b: &mut test_crate::Bencher) {
+                let mut vec = DVec::new();
+                for i in 0 .. N {
+                    vec.push(i);
+                }
+                b.iter(|| {
+                    let mut vec = vec.clone();
+                    for x in vec.iter_mut() {
+                        *x += 1;
+                    }
+                    vec
+                });
+            }
+        }
+    }
+}
+
+update_cloned!(update_cloned_5000, 5000);
+update_cloned!(update_cloned_50000, 50000);
+
 macro_rules! index_randomly {
     ($mod_name: ident, $N: expr) => {
         mod $mod_name {
@@ -233,3 +1463,76 @@ macro_rules! index_randomly {
 index_randomly!(index_randomly_5000, 5000);
 index_randomly!(index_randomly_50000, 50000);
 index_randomly!(index_randomly_500000, 500000);
+
+macro_rules! diff {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use DVec;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                let old: DVec<usize> = (0 .. N).collect();
+                let mut new = old.clone();
+                for i in 0 .. 10 {
+                    new[i * (N / 10)] = 0;
+                }
+                b.iter(|| old.diff(&new).count());
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                let old: Vec<usize> = (0 .. N).collect();
+                let mut new = old.clone();
+                for i in 0 .. 10 {
+                    new[i * (N / 10)] = 0;
+                }
+                b.iter(|| old.iter().zip(&new).filter(|&(a, b)| a != b).count());
+            }
+        }
+    }
+}
+
+diff!(diff_50000, 50000);
+diff!(diff_500000, 500000);
+
+macro_rules! content_hash {
+    ($mod_name: ident, $N: expr) => {
+        mod $mod_name {
+            use std::collections::hash_map::DefaultHasher;
+            use std::hash::{Hash, Hasher};
+            use DVec;
+            use test_crate;
+            const N: usize = $N;
+
+            #[bench]
+            fn dogged(b: &mut test_crate::Bencher) {
+                let mut vec: DVec<usize> = (0 .. N).collect();
+                vec.content_hash();
+                let mut i = 0;
+                b.iter(|| {
+                    i = (i + 7919) % N;
+                    vec[i] += 1;
+                    vec.content_hash()
+                });
+            }
+
+            #[bench]
+            fn standard(b: &mut test_crate::Bencher) {
+                let mut vec: Vec<usize> = (0 .. N).collect();
+                let mut i = 0;
+                b.iter(|| {
+                    i = (i + 7919) % N;
+                    vec[i] += 1;
+                    let mut hasher = DefaultHasher::new();
+                    vec.hash(&mut hasher);
+                    hasher.finish()
+                });
+            }
+        }
+    }
+}
+
+content_hash!(content_hash_50000, 50000);
+content_hash!(content_hash_500000, 500000);
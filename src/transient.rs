@@ -0,0 +1,298 @@
+use std::mem;
+use std::ops;
+
+use super::{ArcK, DVec, Index, Measure, Node, NodeRef, SharedPointerKind, Shift};
+
+/// A vector for batches of updates, obtained from `DVec::transient`.
+/// Like Clojure's transients, it trades away cheap snapshots for speed
+/// until it is turned back into a `DVec` with `persistent`.
+///
+/// Elements pushed onto a transient never go through `P::make_mut`:
+/// full leaves are gathered bottom-up into complete subtrees that only
+/// the transient owns, and these are grafted onto the tree of the
+/// original vector in one O(log n) concatenation by `persistent`. The
+/// vector the transient was created from, and any snapshots of it,
+/// are unaffected by anything done to the transient.
+///
+/// ```rust
+/// # use dogged::DVec;
+/// let mut vec = DVec::new();
+/// vec.push(0);
+/// let snapshot = vec.clone();
+///
+/// let mut transient = vec.transient();
+/// for i in 1..1000 {
+///     transient.push(i);
+/// }
+/// transient[0] = 1000;
+/// let vec = transient.persistent();
+///
+/// assert_eq!(vec.len(), 1000);
+/// assert_eq!(vec[0], 1000);
+/// assert_eq!(snapshot[0], 0);
+/// ```
+pub struct TransientDVec<T, const BITS: usize = 5, P: SharedPointerKind = ArcK, M: Measure<T> = ()> {
+    // The tree of the vector we started from. Its tail is moved into
+    // ours, so new elements go straight into `levels`.
+    prefix: DVec<T, BITS, P, M>,
+    // `levels[k]` holds full subtrees at shift `k * BITS`
+    // that have not yet been gathered under a parent; everything in
+    // `levels[k + 1]` comes before everything in `levels[k]`.
+    levels: Vec<Vec<NodeRef<T, BITS, P, M>>>,
+    levels_len: usize, // number of elements in `levels`
+    tail: Vec<T>,
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> DVec<T, BITS, P, M> {
+    /// Turns this vector into a `TransientDVec` for a batch of updates.
+    pub fn transient(mut self) -> TransientDVec<T, BITS, P, M> {
+        let tail = mem::replace(&mut self.tail, P::new(Vec::new()));
+        TransientDVec {
+            prefix: self,
+            levels: vec![],
+            levels_len: 0,
+            tail: P::unwrap_or_clone(tail),
+        }
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> TransientDVec<T, BITS, P, M> {
+    const BRANCH_FACTOR: usize = 1 << BITS;
+
+    pub fn len(&self) -> usize {
+        self.prefix.len() + self.levels_len + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, element: T) {
+        self.tail.push(element);
+
+        if self.tail.len() == Self::BRANCH_FACTOR {
+            let tail = mem::replace(&mut self.tail, Vec::with_capacity(Self::BRANCH_FACTOR));
+            self.push_leaf(tail);
+        }
+    }
+
+    /// Adds a full leaf to `levels`, gathering up every level that
+    /// fills up as a result.
+    #[cold]
+    fn push_leaf(&mut self, elements: Vec<T>) {
+        debug_assert!(elements.len() == Self::BRANCH_FACTOR);
+        self.levels_len += Self::BRANCH_FACTOR;
+
+        let mut node = P::new(Node::leaf(elements));
+        let mut level = 0;
+        loop {
+            if self.levels.len() == level {
+                self.levels.push(Vec::with_capacity(Self::BRANCH_FACTOR));
+            }
+            self.levels[level].push(node);
+            if self.levels[level].len() < Self::BRANCH_FACTOR {
+                return;
+            }
+
+            // All the children are full, so the new branch is dense.
+            let children = mem::replace(&mut self.levels[level], Vec::with_capacity(Self::BRANCH_FACTOR));
+            node = P::new(Node::dense_branch(children));
+            level += 1;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let prefix_len = self.prefix.len();
+        if index < prefix_len {
+            return self.prefix.get(index);
+        }
+
+        let mut index = index - prefix_len;
+        for (level, nodes) in self.levels.iter().enumerate().rev() {
+            let shift = Shift(level * BITS);
+            let capacity = Self::BRANCH_FACTOR << shift.0;
+            if index < nodes.len() * capacity {
+                let node = &nodes[index / capacity];
+                return Some(node.get(shift, Index(index % capacity)));
+            }
+            index -= nodes.len() * capacity;
+        }
+        self.tail.get(index)
+    }
+
+    /// Splits the transient into the vector it was created from and a
+    /// vector of the elements pushed onto it since.
+    pub(crate) fn into_parts(self) -> (DVec<T, BITS, P, M>, DVec<T, BITS, P, M>) {
+        // `pop` can leave the top levels empty; the root comes from the
+        // highest one that is not.
+        let mut levels = self.levels;
+        while levels.last().is_some_and(|nodes| nodes.is_empty()) {
+            levels.pop();
+        }
+
+        // Gather what is left on each level under a (dense) branch and
+        // add that to the level above, until we are left with a root.
+        let top = levels.len();
+        let mut root = None;
+        let mut shift = Shift(0);
+        let mut carry = None;
+        for (level, mut nodes) in levels.into_iter().enumerate() {
+            nodes.extend(carry.take());
+            if level + 1 == top && nodes.len() == 1 {
+                root = nodes.pop();
+                break;
+            }
+            shift = shift.inc(BITS);
+            if !nodes.is_empty() {
+                carry = Some(P::new(Node::dense_branch(nodes)));
+            }
+        }
+        let root = root.or(carry);
+
+        let suffix = DVec {
+            root_len: Index(if root.is_some() { self.levels_len } else { 0 }),
+            shift: if root.is_some() { shift } else { Shift(0) },
+            root,
+            head: P::new(Vec::new()),
+            tail: P::new(self.tail),
+        };
+        (self.prefix, suffix)
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind, M: Measure<T>> TransientDVec<T, BITS, P, M> {
+    /// Clones and appends all the elements of `other`, a leaf at a time.
+    pub fn extend_from_slice(&mut self, other: &[T]) {
+        let mut other = other;
+        while !self.tail.is_empty() && !other.is_empty() {
+            self.push(other[0].clone());
+            other = &other[1..];
+        }
+
+        let mut chunks = other.chunks_exact(Self::BRANCH_FACTOR);
+        for chunk in &mut chunks {
+            self.push_leaf(chunk.to_vec());
+        }
+        self.tail.extend_from_slice(chunks.remainder());
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.tail.is_empty() {
+            if self.levels_len == 0 {
+                return self.prefix.pop();
+            }
+            self.pop_leaf();
+        }
+        self.tail.pop()
+    }
+
+    /// Moves the last leaf in `levels` into the (empty) tail, taking
+    /// apart the subtree holding it if need be.
+    #[cold]
+    fn pop_leaf(&mut self) {
+        debug_assert!(self.tail.is_empty());
+        let mut level = self.levels.iter().position(|l| !l.is_empty()).unwrap();
+        let mut node = self.levels[level].pop().unwrap();
+        while level > 0 {
+            // Every level below this one is empty, so the children of
+            // `node` can go right into the one below.
+            level -= 1;
+            let children = match P::try_unwrap(node) {
+                Ok(Node::Branch { children, .. }) => children,
+                Ok(Node::Leaf { .. }) => unreachable!(),
+                Err(shared) => shared.children().to_vec(),
+            };
+            self.levels[level] = children;
+            node = self.levels[level].pop().unwrap();
+        }
+
+        self.tail = Node::<T, BITS, P, M>::into_elements(node);
+        self.levels_len -= Self::BRANCH_FACTOR;
+    }
+
+    /// Turns the transient back into an ordinary `DVec`.
+    pub fn persistent(self) -> DVec<T, BITS, P, M> {
+        let (mut vec, suffix) = self.into_parts();
+        vec.append(suffix);
+        vec
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind> TransientDVec<T, BITS, P> {
+    /// Returns a mutable reference to the element at `index`. Nodes of
+    /// the original vector are copied the first time they are written
+    /// through; after that they belong to the transient alone.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let prefix_len = self.prefix.len();
+        if index < prefix_len {
+            return self.prefix.get_mut(index);
+        }
+
+        let mut index = index - prefix_len;
+        for (level, nodes) in self.levels.iter_mut().enumerate().rev() {
+            let shift = Shift(level * BITS);
+            let capacity = Self::BRANCH_FACTOR << shift.0;
+            if index < nodes.len() * capacity {
+                let node = Node::<T, BITS, P, ()>::make_mut(&mut nodes[index / capacity]);
+                return Some(node.get_mut(shift, Index(index % capacity)));
+            }
+            index -= nodes.len() * capacity;
+        }
+        self.tail.get_mut(index)
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Default for TransientDVec<T, BITS, P, M> {
+    fn default() -> Self {
+        TransientDVec {
+            prefix: DVec::default(),
+            levels: vec![],
+            levels_len: 0,
+            tail: Vec::with_capacity(Self::BRANCH_FACTOR),
+        }
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> Extend<T> for TransientDVec<T, BITS, P, M> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter();
+
+        // Top up the tail, then fill whole leaves straight from `iter`.
+        while !self.tail.is_empty() {
+            match iter.next() {
+                Some(element) => self.push(element),
+                None => return,
+            }
+        }
+        loop {
+            let mut elements = Vec::with_capacity(Self::BRANCH_FACTOR);
+            elements.extend(iter.by_ref().take(Self::BRANCH_FACTOR));
+            if elements.len() < Self::BRANCH_FACTOR {
+                self.tail = elements;
+                return;
+            }
+            self.push_leaf(elements);
+        }
+    }
+}
+
+impl<T, const BITS: usize, P: SharedPointerKind, M: Measure<T>> ops::Index<usize> for TransientDVec<T, BITS, P, M> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        self.get(index).unwrap_or_else(|| {
+            panic!("index `{}` out of bounds in TransientDVec of length `{}`",
+                   index, self.len())
+        })
+    }
+}
+
+impl<T: Clone, const BITS: usize, P: SharedPointerKind> ops::IndexMut<usize> for TransientDVec<T, BITS, P> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        let len = self.len();
+        self.get_mut(index).unwrap_or_else(|| {
+            panic!("index `{}` out of bounds in TransientDVec of length `{}`",
+                   index, len)
+        })
+    }
+}